@@ -0,0 +1,487 @@
+use core::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::compiler::{Compiler, UnionSemantics};
+use crate::error::Error;
+use crate::inst::*;
+use crate::json::{FromJson, Json, ToJson};
+use crate::ty::{Endian, Ty};
+
+/// Leading bytes and version of an encoded program.
+/// The version is bumped whenever the encoding changes.
+pub const MAGIC: &[u8; 4] = b"TVMP";
+pub const VERSION: u8 = 6;
+
+/// Bumped whenever the compiler emits different instructions for the same
+/// type, which invalidates every cached program.
+pub const COMPILER_VERSION: u32 = 5;
+
+/// Environment variable naming the cache directory used by the CLI.
+pub const CACHE_DIR_ENV: &str = "TRANSMUTE_VM_CACHE";
+
+#[derive(Debug, Clone)]
+pub struct DecodeError {
+    pub message: String,
+}
+
+impl DecodeError {
+    fn new(message: impl Into<String>) -> Self {
+        Self { message: message.into() }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Why `ProgramCache::compile` failed: the cache directory couldn't be
+/// used, or the type couldn't be compiled in the first place.
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    Compile(Error),
+}
+
+impl From<io::Error> for CacheError {
+    fn from(err: io::Error) -> Self {
+        CacheError::Io(err)
+    }
+}
+
+impl From<Error> for CacheError {
+    fn from(err: Error) -> Self {
+        CacheError::Compile(err)
+    }
+}
+
+/// A compiled program along with the key of the type it was compiled from
+/// and the file that type was declared in.
+pub struct CachedProgram {
+    pub key: u128,
+    pub source: String,
+    pub program: Program,
+}
+
+/// Content hash of a type and the endianness and union semantics it is
+/// compiled for. The JSON form of the type is used as its canonical encoding.
+pub fn type_key(ty: &Ty, endian: Endian, unions: UnionSemantics) -> u128 {
+    // FNV-1a, 128 bit
+    let mut hash: u128 = 0x6c62272e07bb014262b821756295c58d;
+    let mut feed = |bytes: &[u8]| for &byte in bytes {
+        hash ^= byte as u128;
+        hash = hash.wrapping_mul(0x0000000001000000000000000000013b);
+    };
+    feed(&COMPILER_VERSION.to_le_bytes());
+    feed(&[endian_tag(endian), unions as u8]);
+    feed(ty.to_json().to_string().as_bytes());
+    hash
+}
+
+fn endian_tag(endian: Endian) -> u8 {
+    match endian {
+        Endian::Little => 0,
+        Endian::Big => 1,
+    }
+}
+
+// opcodes live in the low nibble, flags in the high one
+const OP_ACCEPT: u8 = 0;
+const OP_UNINIT: u8 = 1;
+const OP_BYTE: u8 = 2;
+const OP_BYTE_RANGE: u8 = 3;
+const OP_SPLIT: u8 = 4;
+const OP_JOIN_GOTO: u8 = 5;
+const OP_POINTER: u8 = 6;
+const OP_REF_SHARED: u8 = 7;
+const OP_REF_UNIQUE: u8 = 8;
+const OP_OPAQUE: u8 = 9;
+const OP_SPAN: u8 = 10;
+const OP_ANY_BYTES: u8 = 11;
+const OP_UNINIT_RUN: u8 = 12;
+const OP_BYTE_RANGE_RUN: u8 = 13;
+const OP_REPEAT: u8 = 14;
+const FLAG_PRIVATE: u8 = 0x10;
+const FLAG_ALTERNATE: u8 = 0x20;
+const FLAG_CELL: u8 = 0x40;
+const FLAG_PROVENANCE: u8 = 0x80;
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_str(out: &mut Vec<u8>, text: &str) {
+    write_varint(out, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+/// Encodes `program` as `MAGIC`, `VERSION`, the type key, the program name,
+/// the source path, the instructions and the JSON form of the span types.
+/// Integers are LEB128 varints.
+pub fn encode(program: &Program, key: u128, source: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&key.to_le_bytes());
+    write_str(&mut out, program.name());
+    write_str(&mut out, source);
+    write_varint(&mut out, program.insts().len() as u64);
+    for inst in program.insts() {
+        let private = |private: bool| if private { FLAG_PRIVATE } else { 0 };
+        match inst {
+            Inst::Accept => out.push(OP_ACCEPT),
+            Inst::Uninit => out.push(OP_UNINIT),
+            &Inst::UninitRun(count) => {
+                out.push(OP_UNINIT_RUN);
+                write_varint(&mut out, count as u64);
+            }
+            Inst::Byte(byte) => {
+                out.push(OP_BYTE | private(byte.private));
+                out.push(byte.byte);
+            }
+            Inst::ByteRange(range) => {
+                let alternate = if range.alternate.is_some() { FLAG_ALTERNATE } else { 0 };
+                out.push(OP_BYTE_RANGE | private(range.private) | alternate);
+                out.push(range.range.start);
+                out.push(range.range.end);
+                if let Some(alternate) = range.alternate {
+                    write_varint(&mut out, alternate as u64);
+                }
+            }
+            Inst::ByteRangeRun(run) => {
+                out.push(OP_BYTE_RANGE_RUN | private(run.private));
+                out.push(run.range.start);
+                out.push(run.range.end);
+                write_varint(&mut out, run.count as u64);
+            }
+            Inst::Opaque(opaque) => {
+                // ids are hashes, a varint would only make them longer
+                out.push(OP_OPAQUE);
+                out.extend_from_slice(&opaque.id.to_le_bytes());
+                write_varint(&mut out, opaque.size as u64);
+            }
+            Inst::AnyBytes(any) => {
+                let provenance = if any.provenance { FLAG_PROVENANCE } else { 0 };
+                out.push(OP_ANY_BYTES | provenance);
+                write_varint(&mut out, any.count as u64);
+            }
+            Inst::Repeat(repeat) => {
+                out.push(OP_REPEAT);
+                write_varint(&mut out, repeat.count as u64);
+                write_varint(&mut out, repeat.end as u64);
+            }
+            Inst::Span(span) => {
+                let cell = if span.cell { FLAG_CELL } else { 0 };
+                out.push(OP_SPAN | private(span.private) | cell);
+                write_varint(&mut out, span.ty as u64);
+                write_varint(&mut out, span.size as u64);
+                write_varint(&mut out, span.end as u64);
+            }
+            Inst::Split(split) => {
+                out.push(OP_SPLIT);
+                write_varint(&mut out, split.alternate as u64);
+            }
+            &Inst::JoinGoto(addr) => {
+                out.push(OP_JOIN_GOTO);
+                write_varint(&mut out, addr as u64);
+            }
+            Inst::Pointer(ptr) => {
+                out.push(OP_POINTER | private(ptr.private));
+                out.push(ptr.range.start);
+                out.push(ptr.range.end);
+                write_varint(&mut out, ptr.count as u64);
+            }
+            Inst::Ref(d_ref) => {
+                out.push(match d_ref.ref_type {
+                    RefKind::Shared => OP_REF_SHARED,
+                    RefKind::Unique => OP_REF_UNIQUE,
+                });
+                write_varint(&mut out, d_ref.pointer_size as u64);
+                write_varint(&mut out, d_ref.data_align as u64);
+            }
+        }
+    }
+    write_varint(&mut out, program.types().len() as u64);
+    for ty in program.types() {
+        write_str(&mut out, &ty.to_json().to_string());
+    }
+    out
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.pos.checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| DecodeError::new("unexpected end of data"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::new("varint is too long"))
+    }
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        let value = self.varint()?;
+        if value > u32::MAX as u64 {
+            return Err(DecodeError::new(format!("{} does not fit in u32", value)));
+        }
+        Ok(value as u32)
+    }
+    fn str(&mut self) -> Result<String, DecodeError> {
+        let len = self.varint()? as usize;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| DecodeError::new("string is not valid UTF-8"))
+    }
+}
+
+pub fn decode(data: &[u8]) -> Result<CachedProgram, DecodeError> {
+    let mut reader = Reader { data, pos: 0 };
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err(DecodeError::new("not an encoded program"));
+    }
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(DecodeError::new(format!("unsupported version {}", version)));
+    }
+    let mut key = [0_u8; 16];
+    key.copy_from_slice(reader.bytes(16)?);
+    let name = reader.str()?;
+    let source = reader.str()?;
+    let count = reader.varint()?;
+    let mut insts = Vec::new();
+    for _ in 0..count {
+        let op = reader.u8()?;
+        let private = op & FLAG_PRIVATE != 0;
+        let inst = match op & 0x0f {
+            OP_ACCEPT => Inst::Accept,
+            OP_UNINIT => Inst::Uninit,
+            OP_UNINIT_RUN => Inst::UninitRun(reader.u32()?),
+            OP_BYTE => Inst::Byte(InstByte { private, byte: reader.u8()? }),
+            OP_BYTE_RANGE => {
+                let range = RangeInclusive { start: reader.u8()?, end: reader.u8()? };
+                let alternate = if op & FLAG_ALTERNATE != 0 {
+                    Some(reader.u32()?)
+                } else {
+                    None
+                };
+                Inst::ByteRange(InstByteRange { private, range, alternate })
+            }
+            OP_BYTE_RANGE_RUN => {
+                let range = RangeInclusive { start: reader.u8()?, end: reader.u8()? };
+                Inst::ByteRangeRun(InstByteRangeRun { private, range, count: reader.u32()? })
+            }
+            OP_OPAQUE => {
+                let mut id = [0_u8; 8];
+                id.copy_from_slice(reader.bytes(8)?);
+                Inst::Opaque(InstOpaque { id: u64::from_le_bytes(id), size: reader.u32()? })
+            }
+            OP_ANY_BYTES => Inst::AnyBytes(InstAnyBytes {
+                provenance: op & FLAG_PROVENANCE != 0,
+                count: reader.u32()?,
+            }),
+            OP_REPEAT => Inst::Repeat(InstRepeat { count: reader.u32()?, end: reader.u32()? }),
+            OP_SPAN => {
+                let ty = reader.u32()?;
+                let size = usize::try_from(reader.varint()?)
+                    .map_err(|_| DecodeError::new("span size does not fit in usize"))?;
+                let cell = op & FLAG_CELL != 0;
+                Inst::Span(InstSpan {
+                    ty, private, cell, size, end: reader.u32()?,
+                })
+            }
+            OP_SPLIT => Inst::Split(InstSplit { alternate: reader.u32()? }),
+            OP_JOIN_GOTO => Inst::JoinGoto(reader.u32()?),
+            OP_POINTER => Inst::Pointer(InstrPointer {
+                private,
+                range: RangeInclusive { start: reader.u8()?, end: reader.u8()? },
+                count: reader.u32()?,
+            }),
+            OP_REF_SHARED | OP_REF_UNIQUE => Inst::Ref(InstrRef {
+                ref_type: if op & 0x0f == OP_REF_SHARED { RefKind::Shared } else { RefKind::Unique },
+                pointer_size: reader.u32()?,
+                data_align: reader.u32()?,
+            }),
+            _ => return Err(DecodeError::new(format!("unknown opcode 0x{:02x}", op))),
+        };
+        insts.push(inst);
+    }
+    let count = reader.varint()?;
+    let mut types = Vec::new();
+    for _ in 0..count {
+        let ty = Json::parse(&reader.str()?).and_then(|json| Ty::from_json(&json))
+            .map_err(|err| DecodeError::new(format!("invalid span type: {}", err)))?;
+        types.push(ty);
+    }
+    if reader.pos != data.len() {
+        return Err(DecodeError::new("trailing data after program"));
+    }
+    if !Program::is_well_formed(&insts, &types) {
+        return Err(DecodeError::new("program is not well-formed"));
+    }
+    Ok(CachedProgram {
+        key: u128::from_le_bytes(key),
+        source,
+        program: Program::new(insts, types, name),
+    })
+}
+
+/// A directory of compiled programs, one `<key>.bin` file per type.
+pub struct ProgramCache {
+    dir: PathBuf,
+}
+
+impl ProgramCache {
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+    /// Opens the cache named by `CACHE_DIR_ENV`, if it is set.
+    pub fn from_env() -> Option<io::Result<Self>> {
+        std::env::var_os(CACHE_DIR_ENV)
+            .filter(|dir| !dir.is_empty())
+            .map(ProgramCache::new)
+    }
+    fn path_for(&self, key: u128) -> PathBuf {
+        self.dir.join(format!("{:032x}.bin", key))
+    }
+    /// Returns the cached program for `ty`, compiling and storing it first if
+    /// it is missing. Unreadable or stale entries are silently replaced.
+    pub fn compile(
+        &self, ty: &Ty, endian: Endian, unions: UnionSemantics, name: &str, source: &str,
+    ) -> Result<Program, CacheError> {
+        let key = type_key(ty, endian, unions);
+        let path = self.path_for(key);
+        match std::fs::read(&path) {
+            Ok(data) => match decode(&data) {
+                // the name is not part of the key, the same type may be
+                // checked under different names
+                Ok(cached) if cached.key == key => {
+                    return Ok(cached.program.renamed(name));
+                }
+                _ => {}
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        let program = Compiler::compile_with(ty, endian, unions, name)?;
+        // write under a temporary name first, so concurrent checks never
+        // observe a partially written entry
+        let tmp = self.dir.join(format!("{:032x}.{}.tmp", key, std::process::id()));
+        std::fs::write(&tmp, encode(&program, key, source))?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(program)
+    }
+    /// The programs in the cache, by the file their type was declared in
+    /// and its name. Entries that don't decode are left out, the next
+    /// `compile` replaces them anyway.
+    pub fn entries(&self) -> io::Result<Vec<CachedProgram>> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "bin") {
+                continue;
+            }
+            if let Ok(cached) = decode(&std::fs::read(&path)?) {
+                entries.push(cached);
+            }
+        }
+        entries.sort_by(|a, b| (&a.source, a.program.name()).cmp(&(&b.source, b.program.name())));
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::rust::parse_rust;
+
+    fn program_with(unions: UnionSemantics) -> Program {
+        let types = parse_rust("
+            #[repr(C)] struct S { a: u8, e: [E; 4], u: U }
+            #[repr(C, u8)] enum E { P(u32), Q { b: bool } }
+            #[repr(C)] union U { x: u16, y: bool }
+        ").unwrap();
+        Compiler::compile_with(&types["S"], Endian::Little, unions, "S").unwrap()
+    }
+
+    fn program() -> Program {
+        program_with(UnionSemantics::Variants)
+    }
+
+    #[test]
+    fn round_trip() {
+        for unions in [UnionSemantics::Variants, UnionSemantics::BytesWithProvenance] {
+            let program = program_with(unions);
+            let data = encode(&program, 0x1234, "s.rs");
+            let cached = decode(&data).unwrap();
+            assert_eq!(cached.key, 0x1234);
+            assert_eq!(cached.source, "s.rs");
+            assert_eq!(cached.program.to_json(), program.to_json());
+        }
+    }
+
+    #[test]
+    fn damaged_data_is_an_error() {
+        let data = encode(&program(), 0, "");
+        for len in 0..data.len() {
+            assert!(decode(&data[..len]).is_err());
+        }
+        let mut data = data;
+        for idx in 0..data.len() {
+            data[idx] ^= 0xff;
+            let _ = decode(&data);
+            data[idx] ^= 0xff;
+        }
+    }
+
+    #[test]
+    fn entries_name_their_source() {
+        let dir = std::env::temp_dir().join(format!("transmute-vm-cache-{}", std::process::id()));
+        let cache = ProgramCache::new(dir.clone()).unwrap();
+        let types = parse_rust("#[repr(C)] struct T { a: u16 }").unwrap();
+        for (name, source) in [("T", "t.rs"), ("u16", "a.rs")] {
+            let ty = types.get(name).cloned().unwrap_or(Ty::Int(2));
+            cache.compile(&ty, Endian::Little, UnionSemantics::Variants, name, source).unwrap();
+        }
+        std::fs::write(dir.join("damaged.bin"), b"TVMP").unwrap();
+        let entries = cache.entries().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let listed = entries.iter()
+            .map(|cached| (cached.source.as_str(), cached.program.name()))
+            .collect::<Vec<_>>();
+        assert_eq!(listed, [("a.rs", "u16"), ("t.rs", "T")]);
+    }
+
+    #[test]
+    fn key_depends_on_target() {
+        let ty = Ty::Int(2);
+        let key = |ty: &Ty, endian| type_key(ty, endian, UnionSemantics::Variants);
+        assert_ne!(key(&ty, Endian::Little), key(&ty, Endian::Big));
+        assert_ne!(key(&ty, Endian::Little), key(&Ty::Int(4), Endian::Little));
+        assert_ne!(key(&ty, Endian::Little), type_key(&ty, Endian::Little, UnionSemantics::Bytes));
+    }
+}
@@ -1,12 +1,55 @@
 use core::alloc::Layout;
+use std::collections::HashMap;
 
+use crate::error::Error;
 use crate::ty::*;
 use crate::inst::*;
 
+/// Environment variable picking the `UnionSemantics` used by the CLI.
+pub const UNIONS_ENV: &str = "TRANSMUTE_VM_UNIONS";
+
+/// What a value of a `Ty::Union` may hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnionSemantics {
+    /// One of its variants, padded with uninit bytes. The most
+    /// conservative model.
+    Variants,
+    /// Any bytes, uninit included. Pointer bytes lose their provenance.
+    Bytes,
+    /// Any bytes, and the pointer bytes among them keep their provenance,
+    /// as the unsafe code guidelines have it.
+    BytesWithProvenance,
+}
+
+impl UnionSemantics {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "variants" => Some(UnionSemantics::Variants),
+            "bytes" => Some(UnionSemantics::Bytes),
+            "provenance" => Some(UnionSemantics::BytesWithProvenance),
+            _ => None,
+        }
+    }
+    /// Reads `UNIONS_ENV`, `Variants` if it is unset.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var(UNIONS_ENV) {
+            Ok(name) if !name.is_empty() => Self::parse(&name).ok_or_else(|| {
+                format!("{}: expected variants, bytes or provenance, got `{}`", UNIONS_ENV, name)
+            }),
+            _ => Ok(UnionSemantics::Variants),
+        }
+    }
+}
+
 pub struct Compiler {
     pub endian: Endian,
+    pub unions: UnionSemantics,
     pub layout: Layout,
     pub insts: Vec<Inst>,
+    /// The types of the spans, see `InstSpan::ty`.
+    pub types: Vec<Ty>,
+    /// The index of each type in `types`.
+    interned: HashMap<Ty, u32>,
     pub priv_depth: usize,
 }
 
@@ -14,60 +57,122 @@ impl Compiler {
     pub fn new(endian: Endian) -> Self {
         Self {
             endian,
+            unions: UnionSemantics::Variants,
             layout: Layout::from_size_align(0, 1).expect("????"),
             insts: Vec::new(),
+            types: Vec::new(),
+            interned: HashMap::new(),
             priv_depth: 0,
         }
     }
-    pub fn compile(ty: &Ty, endian: Endian, name: &'static str) -> Program {
+    /// Validates `ty` and compiles it into a program named `name`.
+    /// Uninhabited types compile to a program without instructions,
+    /// see `Program::is_uninhabited`.
+    pub fn compile(ty: &Ty, endian: Endian, name: &str) -> Result<Program, Error> {
+        Self::compile_with(ty, endian, UnionSemantics::Variants, name)
+    }
+    /// Like `compile`, with unions holding what `unions` allows.
+    pub fn compile_with(
+        ty: &Ty, endian: Endian, unions: UnionSemantics, name: &str,
+    ) -> Result<Program, Error> {
+        let problems = ty.validate();
+        if !problems.is_empty() {
+            return Err(Error::InvalidTy(problems));
+        }
+        if ty.is_uninhabited() {
+            return Ok(Program::new(Vec::new(), Vec::new(), name));
+        }
         let mut compiler = Self::new(endian);
-        compiler.extend_from_ty(ty);
+        compiler.unions = unions;
+        compiler.extend_from_ty(ty)?;
         compiler.insts.push(Inst::Accept);
-        Program::new(compiler.insts, name)
+        Ok(Program::new(compiler.insts, compiler.types, name))
     }
-    pub fn extend_from_ty(&mut self, ty: &Ty) {
-        let layout = layout_of(ty);
-        self.layout = self.layout.align_to(layout.align()).unwrap();
-        self.pad_to_align(layout.align());
+    pub fn extend_from_ty(&mut self, ty: &Ty) -> Result<(), Error> {
+        let layout = layout_of(ty)?;
+        // identical types at the same position are accepted as a whole,
+        // even if they are private; cell spans also mark interior mutability.
+        // Through a shared reference to a union any field can be written,
+        // so every byte of one with a cell in a field is mutable
+        let span = match *ty {
+            Ty::Struct(_) | Ty::Enum(_) | Ty::Union(_) | Ty::Opaque(_) | Ty::UnsafeCell(_) => {
+                let cell = match ty {
+                    Ty::UnsafeCell(_) => true,
+                    Ty::Union(_) => ty.contains_cell(),
+                    _ => false,
+                };
+                let id = self.intern(ty)?;
+                self.insts.push(Inst::new_invalid_span(id, self.priv_depth > 0, cell, layout.size()));
+                Some(self.insts.len() - 1)
+            }
+            _ => None,
+        };
         match *ty {
+            Ty::Never => {
+                return Err(Error::Uninhabited("!".into()));
+            }
             Ty::Void => {
                 // let literal = InstBytes::for_literal(Endian::Little, 4, 0x13371337);
                 // self.insts.extend(literal.map(Inst::Bytes));
             }
             Ty::Bool => {
                 self.repeat_byte(1, (0..=1).into());
-                self.layout = self.layout.extend(layout).unwrap().0;
+                self.advance(layout.size());
             }
             Ty::Int(size) => {
                 self.repeat_byte(size, (0..=255).into());
-                self.layout = self.layout.extend(layout).unwrap().0;
+                self.advance(layout.size());
+            }
+            Ty::Scalar(_) | Ty::Valid(_) => {
+                // validation made sure `Ty::Valid` narrows a bool or integer
+                let valid = ty.valid_values()
+                    .ok_or(Error::Unsupported("valid ranges on this type"))?;
+                if valid.is_empty() {
+                    return Err(Error::Uninhabited("#[valid()]".into()));
+                }
+                let mut budget = MAX_RANGE_BRANCHES;
+                let tree = range_tree(layout.size() as u32, self.endian, &valid, &mut budget)
+                    .ok_or(Error::Unsupported("a valid range this irregular"))?;
+                self.extend_range_tree(&tree)?;
+                self.advance(layout.size());
             }
-            Ty::Ptr(ref _ptr) => {
-                unimplemented!();
+            Ty::Ptr(ref ptr) => {
+                let size = u32::try_from(layout.size()).map_err(|_| Error::LayoutOverflow)?;
+                if ptr.non_null {
+                    self.extend_non_null(size)?;
+                } else {
+                    self.push_pointer((0..=255).into(), size);
+                }
+                self.advance(layout.size());
             }
             Ty::Ref(ref _ptr) => {
-                unimplemented!();
+                return Err(Error::Unsupported("reference"));
+            }
+            Ty::UnsafeCell(ref inner) => {
+                self.extend_from_ty(inner)?;
             }
             Ty::Array(ref array) => {
-                for _ in 0..array.count {
-                    self.extend_from_ty(&array.element);
+                let elem_size = layout_of(&array.element)?.size();
+                if array.count == 1 || (array.count > 1 && elem_size == 0) {
+                    // zero-sized elements only leave their spans behind
+                    self.extend_from_ty(&array.element)?;
+                } else if array.count > 1 {
+                    let count = u32::try_from(array.count).map_err(|_| Error::LayoutOverflow)?;
+                    self.extend_repeat(&array.element, elem_size, count)?;
                 }
             }
             Ty::Struct(ref s_def) => {
-                for field in s_def.fields.iter() {
-                    let layout = layout_of(&field.ty);
-                    self.pad_to_align(layout.align());
-                    if field.private { self.priv_depth += 1; }
-                    self.extend_from_ty(&field.ty);
-                    if field.private { self.priv_depth -= 1; }
-                }
-                self.pad_to_align(layout.align());
+                let start = self.layout.size();
+                self.extend_struct_fields(s_def, start)?;
+                self.pad_to(start + layout.size())?;
             }
             Ty::Enum(ref e_def) => {
-                assert!(!e_def.variants.is_empty(), "zero-variant enum isn't repr-c");
-                let mut variants = e_def.variants.iter();
+                // variants without values can't be constructed, so their
+                // tags are never valid either
+                let mut variants = e_def.variants.iter()
+                    .filter(|variant| !variant.payload.is_uninhabited());
                 let last_variant = variants.next_back()
-                    .expect("at least one variant is present");
+                    .ok_or_else(|| Error::Uninhabited(e_def.name.clone()))?;
                 let mut patches = Vec::with_capacity(e_def.variants.len());
                 let mut prev_patch: Option<usize> = None;
                 let orig_layout = self.layout;
@@ -75,12 +180,12 @@ impl Compiler {
                 for variant in variants {
                     let split = self.insts.len();
                     if let Some(prev_split) = prev_patch {
-                        self.insts[prev_split].patch_split(split as InstPtr);
+                        self.insts[prev_split].patch_split(split as InstPtr)?;
                     }
                     prev_patch = Some(split);
                     self.insts.push(Inst::new_invalid_split());
 
-                    self.extend_enum_variant(e_def, variant);
+                    self.extend_enum_variant(e_def, variant)?;
 
                     patches.push(self.insts.len());
                     self.insts.push(Inst::new_invalid_goto());
@@ -89,23 +194,35 @@ impl Compiler {
 
                 if let Some(last_split) = prev_patch {
                     let ip = self.insts.len() as InstPtr;
-                    self.insts[last_split].patch_split(ip);
+                    self.insts[last_split].patch_split(ip)?;
                 }
 
-                self.extend_enum_variant(e_def, last_variant);
+                self.extend_enum_variant(e_def, last_variant)?;
                 let ip = self.insts.len() as InstPtr;
                 // self.insts.push(Inst::Join);
 
                 for patch in patches {
-                    self.insts[patch].patch_goto(ip);
+                    self.insts[patch].patch_goto(ip)?;
                 }
 
             }
+            Ty::Union(_) if self.unions != UnionSemantics::Variants => {
+                // any bytes, the variants don't matter; private bytes can be
+                // neither read nor written, so those are any value instead
+                let size = u32::try_from(layout.size()).map_err(|_| Error::LayoutOverflow)?;
+                if self.priv_depth > 0 {
+                    self.repeat_byte(size, (0..=255).into());
+                } else if size > 0 {
+                    let provenance = self.unions == UnionSemantics::BytesWithProvenance;
+                    self.insts.push(Inst::AnyBytes(InstAnyBytes { provenance, count: size }));
+                }
+                self.advance(layout.size());
+            }
             Ty::Union(ref u_def) => {
-                assert!(!u_def.variants.is_empty(), "zero-variant enum isn't repr-c");
-                let mut variants = u_def.variants.iter();
+                let mut variants = u_def.variants.iter()
+                    .filter(|variant| !variant.ty.is_uninhabited());
                 let last_variant = variants.next_back()
-                    .expect("at least one variant is present");
+                    .ok_or_else(|| Error::Uninhabited(u_def.name.clone()))?;
                 let mut patches = Vec::with_capacity(u_def.variants.len());
                 let mut prev_patch: Option<usize> = None;
                 let orig_layout = self.layout;
@@ -113,12 +230,12 @@ impl Compiler {
                 for variant in variants {
                     let split = self.insts.len();
                     if let Some(prev_split) = prev_patch {
-                        self.insts[prev_split].patch_split(split as InstPtr);
+                        self.insts[prev_split].patch_split(split as InstPtr)?;
                     }
                     prev_patch = Some(split);
                     self.insts.push(Inst::new_invalid_split());
 
-                    self.extend_union_variant(u_def, variant);
+                    self.extend_union_variant(u_def, variant)?;
                     patches.push(self.insts.len());
                     self.insts.push(Inst::new_invalid_goto());
                     self.layout = orig_layout;
@@ -126,63 +243,291 @@ impl Compiler {
 
                 if let Some(last_split) = prev_patch {
                     let ip = self.insts.len() as InstPtr;
-                    self.insts[last_split].patch_split(ip);
+                    self.insts[last_split].patch_split(ip)?;
                 }
 
-                self.extend_union_variant(u_def, last_variant);
+                self.extend_union_variant(u_def, last_variant)?;
                 let ip = self.insts.len() as InstPtr;
                 // self.insts.push(Inst::Join);
 
                 for patch in patches {
-                    self.insts[patch].patch_goto(ip);
+                    self.insts[patch].patch_goto(ip)?;
                 }
             }
+            Ty::Opaque(ref op) => {
+                let id = op.id();
+                let size = u32::try_from(layout.size()).map_err(|_| Error::LayoutOverflow)?;
+                if size > 0 {
+                    self.insts.push(Inst::Opaque(InstOpaque { id, size }));
+                }
+                self.advance(layout.size());
+            }
+        }
+        if let Some(span) = span {
+            let end = self.insts.len() as InstPtr;
+            self.insts[span].patch_span(end)?;
+        }
+        Ok(())
+    }
+    /// Emits the fields of `s_def` at their offsets from `start`.
+    fn extend_struct_fields(&mut self, s_def: &Struct, start: usize) -> Result<(), Error> {
+        for field in s_def.fields.iter() {
+            self.pad_to(start + field.offset)?;
+            if field.private { self.priv_depth += 1; }
+            self.extend_from_ty(&field.ty)?;
+            if field.private { self.priv_depth -= 1; }
         }
+        Ok(())
     }
-    fn extend_union_variant(&mut self, u_def: &Union, variant: &UnionVariant) {
-        self.pad_to_align(u_def.layout.align());
+    /// Emits the bytes of a pointer that aren't all zero, as an alternation
+    /// on the first non-zero byte in memory order: `[01-ff][00-ff]..`,
+    /// `[00][01-ff][00-ff]..` and so on.
+    fn extend_non_null(&mut self, size: u32) -> Result<(), Error> {
+        let mut patches = Vec::with_capacity(size as usize);
+        for zeros in 0..size {
+            let last = zeros + 1 == size;
+            let split = self.insts.len();
+            if !last {
+                self.insts.push(Inst::new_invalid_split());
+            }
+            self.push_pointer((0..=0).into(), zeros);
+            self.push_pointer((1..=255).into(), 1);
+            self.push_pointer((0..=255).into(), size - zeros - 1);
+            if !last {
+                patches.push(self.insts.len());
+                self.insts.push(Inst::new_invalid_goto());
+                let next = self.insts.len() as InstPtr;
+                self.insts[split].patch_split(next)?;
+            }
+        }
+        let ip = self.insts.len() as InstPtr;
+        for patch in patches {
+            self.insts[patch].patch_goto(ip)?;
+        }
+        Ok(())
+    }
+    fn push_pointer(&mut self, range: RangeInclusive, count: u32) {
+        if count > 0 {
+            let private = self.priv_depth > 0;
+            self.insts.push(Inst::Pointer(InstrPointer { private, range, count }));
+        }
+    }
+    fn extend_union_variant(&mut self, u_def: &Union, variant: &UnionVariant) -> Result<(), Error> {
+        let start = self.layout.size();
         self.priv_depth += variant.private as usize;
-        self.extend_from_ty(&variant.ty);
+        self.extend_from_ty(&variant.ty)?;
         self.priv_depth -= variant.private as usize;
-        let variant_layout = layout_of(&variant.ty);
-        self.pad(u_def.layout.size() - variant_layout.size());
+        self.pad_to(start + u_def.layout.size())
     }
-    fn extend_enum_variant(&mut self, e_def: &Enum, variant: &EnumVariant) {
+    fn extend_enum_variant(&mut self, e_def: &Enum, variant: &EnumVariant) -> Result<(), Error> {
         let endian = self.endian;
         let private = self.priv_depth > 0;
         let tag = InstByte::for_literal(
                 endian, e_def.tag_layout.size(), variant.disc, private
             );
+        let start = self.layout.size();
         self.insts.extend(tag);
-        self.layout = self.layout.extend(e_def.tag_layout).unwrap().0;
-        self.pad_to_align(e_def.payload_layout.align());
-        self.extend_from_ty(&variant.payload);
-        let variant_layout = layout_of(&variant.payload);
-        self.pad(e_def.payload_layout.size() - variant_layout.size());
+        self.advance(e_def.tag_layout.size());
+        match (e_def.repr, &variant.payload) {
+            // the struct shares its start with the enum, its fields follow the tag
+            (EnumRepr::Primitive, Ty::Struct(ref s_def)) => {
+                self.extend_struct_fields(s_def, start)?;
+            }
+            (_, payload) => {
+                self.pad_to(start + e_def.payload_offset()?)?;
+                self.extend_from_ty(payload)?;
+            }
+        }
+        self.pad_to(start + e_def.layout.size())
     }
-    fn repeat_with<F>(&mut self, count: u32, f: F)
-        where F: Fn() -> Inst
-    {
-        for _ in 0..count {
-            self.insts.push(f());
+    /// Emits the branches of a `range_tree` as an alternation, like the
+    /// variants of an enum.
+    fn extend_range_tree(&mut self, branches: &[RangeBranch]) -> Result<(), Error> {
+        if branches.is_empty() {
+            return Err(Error::MalformedProgram("empty valid range".into()));
         }
+        let private = self.priv_depth > 0;
+        let mut patches = Vec::with_capacity(branches.len());
+        for (idx, branch) in branches.iter().enumerate() {
+            let last = idx + 1 == branches.len();
+            let split = self.insts.len();
+            if !last {
+                self.insts.push(Inst::new_invalid_split());
+            }
+            let range = branch.range;
+            self.insts.push(Inst::ByteRange(InstByteRange { private, range, alternate: None }));
+            if !branch.rest.is_empty() {
+                self.extend_range_tree(&branch.rest)?;
+            }
+            if !last {
+                patches.push(self.insts.len());
+                self.insts.push(Inst::new_invalid_goto());
+                let next = self.insts.len() as InstPtr;
+                self.insts[split].patch_split(next)?;
+            }
+        }
+        let ip = self.insts.len() as InstPtr;
+        for patch in patches {
+            self.insts[patch].patch_goto(ip)?;
+        }
+        Ok(())
+    }
+    /// Emits `count` values of `ty` as a `Repeat` of one, or as a single
+    /// run if `ty` is a run of the same byte itself.
+    fn extend_repeat(&mut self, ty: &Ty, size: usize, count: u32) -> Result<(), Error> {
+        let repeat = self.insts.len();
+        self.insts.push(Inst::new_invalid_repeat(count));
+        self.extend_from_ty(ty)?;
+        self.advance(size * (count as usize - 1));
+        let run = match self.insts[repeat + 1..] {
+            [Inst::Uninit] => Some(Inst::UninitRun(count)),
+            [Inst::UninitRun(len)] => len.checked_mul(count).map(Inst::UninitRun),
+            [Inst::ByteRange(InstByteRange { private, range, alternate: None })] => {
+                Some(Inst::byte_run(private, range, count))
+            }
+            [Inst::ByteRangeRun(ref run)] => run.count.checked_mul(count)
+                .map(|count| Inst::byte_run(run.private, run.range, count)),
+            _ => None,
+        };
+        match run {
+            Some(run) => {
+                self.insts.truncate(repeat);
+                self.insts.push(run);
+            }
+            None => {
+                let end = self.insts.len() as InstPtr;
+                self.insts[repeat].patch_repeat(end)?;
+            }
+        }
+        Ok(())
+    }
+    /// The index of `ty` in `types`, added if it isn't there yet.
+    fn intern(&mut self, ty: &Ty) -> Result<u32, Error> {
+        if let Some(&idx) = self.interned.get(ty) {
+            return Ok(idx);
+        }
+        let idx = u32::try_from(self.types.len()).map_err(|_| Error::LayoutOverflow)?;
+        self.types.push(ty.clone());
+        self.interned.insert(ty.clone(), idx);
+        Ok(idx)
+    }
+    /// Moves the current position forward by `size` bytes, which the caller
+    /// has already emitted instructions for.
+    /// Offsets are tracked relative to the enclosing type, so this doesn't
+    /// align anything: members of packed structs may sit at any offset.
+    fn advance(&mut self, size: usize) {
+        let size_layout = Layout::from_size_align(size, 1).unwrap();
+        self.layout = self.layout.extend(size_layout).unwrap().0;
     }
     fn pad(&mut self, padding: usize) {
         // println!("i:{}, padding: {}, layout: {:?}", self.insts.len(), padding, self.layout);
-        let padding_layout = Layout::from_size_align(padding, 1).unwrap();
-        self.layout = self.layout.extend(padding_layout).unwrap().0;
-        self.repeat_with(padding as u32, || Inst::Uninit);
+        self.advance(padding);
+        match padding {
+            0 => {}
+            1 => self.insts.push(Inst::Uninit),
+            _ => self.insts.push(Inst::UninitRun(padding as u32)),
+        }
     }
-    fn pad_to_align(&mut self, align: usize) {
-        let padding = self.layout.padding_needed_for(align);
+    fn pad_to(&mut self, offset: usize) -> Result<(), Error> {
+        let end = self.layout.size();
+        let padding = offset.checked_sub(end)
+            .ok_or(Error::OverlappingField { offset, end })?;
         self.pad(padding);
+        Ok(())
     }
     fn repeat_byte(&mut self, size: u32, byte_ranges: RangeInclusive) {
-        let private = self.priv_depth > 0;
-        self.repeat_with(size, || Inst::ByteRange(InstByteRange {
-            private,
-            range: byte_ranges,
-            alternate: None,
-        }));
+        if size > 0 {
+            self.insts.push(Inst::byte_run(self.priv_depth > 0, byte_ranges, size));
+        }
+    }
+}
+
+/// Byte ranges a valid range may compile to. Trees of little endian ranges
+/// grow exponentially with the size when neither bound is regular, e.g. a
+/// random `u128` range, while `NonZeroU128` and the like stay linear.
+const MAX_RANGE_BRANCHES: usize = 1 << 16;
+
+/// A range of the next byte in memory order and the bytes that may follow.
+struct RangeBranch {
+    range: RangeInclusive,
+    rest: Vec<RangeBranch>,
+}
+
+/// Splits the `size` byte integers in `valid`, inclusive ranges, into a
+/// tree of byte ranges in memory order. Sibling branches never overlap,
+/// so a value takes a single path, e.g. `0x0001..=0xffff` little endian
+/// is `[00][01-ff]` or `[01-ff][00-ff]`.
+/// Returns `None` once the tree has more than `budget` branches.
+fn range_tree(
+    size: u32, endian: Endian, valid: &[(u128, u128)], budget: &mut usize,
+) -> Option<Vec<RangeBranch>> {
+    if size == 1 {
+        *budget = budget.checked_sub(valid.len())?;
+        return Some(valid.iter().map(|&(start, end)| RangeBranch {
+            range: (start as u8..=end as u8).into(),
+            rest: Vec::new(),
+        }).collect());
     }
-}
\ No newline at end of file
+    let shift = (size - 1) * 8;
+    let rest_max = int_max(size - 1);
+    // the first byte in memory order and the value of the bytes after it
+    let split = |value: u128| match endian {
+        Endian::Big => ((value >> shift) as u8, value & rest_max),
+        Endian::Little => (value as u8, value >> 8),
+    };
+    // what may follow `byte`, as sorted and merged ranges
+    let rest_of = |byte: u8| {
+        let mut rest = valid.iter().filter_map(|&(start, end)| {
+            let (start_byte, start_rest) = split(start);
+            let (end_byte, end_rest) = split(end);
+            let (low, high) = match endian {
+                Endian::Big if byte < start_byte || byte > end_byte => return None,
+                Endian::Big => (
+                    if byte == start_byte { start_rest } else { 0 },
+                    if byte == end_byte { end_rest } else { rest_max },
+                ),
+                // carries into the higher bytes
+                Endian::Little => (
+                    start_rest + (byte < start_byte) as u128,
+                    end_rest.checked_sub((byte > end_byte) as u128)?,
+                ),
+            };
+            Some((low, high)).filter(|&(low, high)| low <= high)
+        }).collect::<Vec<_>>();
+        rest.sort_unstable();
+        let mut merged: Vec<(u128, u128)> = Vec::with_capacity(rest.len());
+        for (low, high) in rest {
+            match merged.last_mut() {
+                Some(last) if low <= last.1.saturating_add(1) => last.1 = last.1.max(high),
+                _ => merged.push((low, high)),
+            }
+        }
+        merged
+    };
+    // `rest_of` only changes at the first bytes of the bounds
+    let mut cuts = vec![0_u16, 256];
+    for &(start, end) in valid {
+        for bound in [split(start).0, split(end).0] {
+            cuts.extend([bound as u16, bound as u16 + 1]);
+        }
+    }
+    cuts.sort_unstable();
+    cuts.dedup();
+    let mut branches: Vec<(RangeInclusive, Vec<(u128, u128)>)> = Vec::new();
+    for window in cuts.windows(2) {
+        let (start, end) = (window[0] as u8, (window[1] - 1) as u8);
+        let rest = rest_of(start);
+        match branches.last_mut() {
+            _ if rest.is_empty() => {}
+            Some((range, last)) if *last == rest && range.end as u16 + 1 == start as u16 => {
+                range.end = end;
+            }
+            _ => branches.push(((start..=end).into(), rest)),
+        }
+    }
+    *budget = budget.checked_sub(branches.len())?;
+    branches.into_iter().map(|(range, rest)| Some(RangeBranch {
+        range,
+        rest: range_tree(size - 1, endian, &rest, budget)?,
+    })).collect()
+}
@@ -2,6 +2,7 @@ use crate::ty::*;
 
 pub trait InspectTy {
     fn ty_of() -> Ty;
+    #[allow(dead_code)]
     fn ty_of_this(&self) -> Ty {
         <Self as InspectTy>::ty_of()
     }
@@ -21,6 +22,7 @@ impl<T: Sized> InspectTy for *const T {
         Ty::Ptr(Pointer {
             kind: RefKind::Shared,
             align: core::mem::align_of::<T>(),
+            non_null: false,
         })
     }
 }
@@ -29,6 +31,16 @@ impl<T: Sized> InspectTy for *mut T {
         Ty::Ptr(Pointer {
             kind: RefKind::Unique,
             align: core::mem::align_of::<T>(),
+            non_null: false,
+        })
+    }
+}
+impl<T: Sized> InspectTy for core::ptr::NonNull<T> {
+    fn ty_of() -> Ty {
+        Ty::Ptr(Pointer {
+            kind: RefKind::Unique,
+            align: core::mem::align_of::<T>(),
+            non_null: true,
         })
     }
 }
@@ -60,12 +72,85 @@ impl<T, const C: usize> InspectTy for [T; C]
         }))
     }
 }
+impl<T: ?Sized> InspectTy for core::marker::PhantomData<T> {
+    fn ty_of() -> Ty { Ty::Void }
+}
+impl<T: InspectTy> InspectTy for core::mem::ManuallyDrop<T> {
+    fn ty_of() -> Ty { <T as InspectTy>::ty_of() }
+}
+impl<T: InspectTy> InspectTy for core::num::Wrapping<T> {
+    fn ty_of() -> Ty { <T as InspectTy>::ty_of() }
+}
+impl<T: InspectTy> InspectTy for core::cell::UnsafeCell<T> {
+    fn ty_of() -> Ty { Ty::UnsafeCell(Box::new(<T as InspectTy>::ty_of())) }
+}
+impl<T: InspectTy> InspectTy for core::cell::Cell<T> {
+    fn ty_of() -> Ty { <core::cell::UnsafeCell<T> as InspectTy>::ty_of() }
+}
+/// Laid out like its definition, `union { uninit: (), value: T }`:
+/// any bytes, uninit included, can be written to it.
+impl<T: InspectTy> InspectTy for core::mem::MaybeUninit<T> {
+    fn ty_of() -> Ty {
+        let mut un = Union::new("MaybeUninit");
+        un.add_variant("uninit", false, Ty::Void)
+            .and_then(|()| un.add_variant("value", false, <T as InspectTy>::ty_of()))
+            .expect("derived types have a layout");
+        Ty::Union(un)
+    }
+}
+
+/// Zero is the only invalid value of a `NonZero` integer.
+macro_rules! non_zero_ty {
+    ($($ty:ident),* $(,)?) => {
+        $(
+            impl InspectTy for core::num::$ty {
+                fn ty_of() -> Ty {
+                    Ty::Scalar(Scalar::non_zero(core::mem::size_of::<Self>() as u32))
+                }
+            }
+        )*
+    };
+}
+
+non_zero_ty!(NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU128,
+    NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI128);
+
+/// Atomics are their integer in an `UnsafeCell`.
+macro_rules! atomic_ty {
+    ($($atomic:ident($ty:ty)),* $(,)?) => {
+        $(
+            impl InspectTy for core::sync::atomic::$atomic {
+                fn ty_of() -> Ty { <core::cell::UnsafeCell<$ty> as InspectTy>::ty_of() }
+            }
+        )*
+    };
+}
+
+atomic_ty!(AtomicBool(bool), AtomicU8(u8), AtomicU16(u16), AtomicU32(u32), AtomicU64(u64),
+    AtomicI8(i8), AtomicI16(i16), AtomicI32(i32), AtomicI64(i64));
+impl<T: Sized> InspectTy for core::sync::atomic::AtomicPtr<T> {
+    fn ty_of() -> Ty { <core::cell::UnsafeCell<*mut T> as InspectTy>::ty_of() }
+}
+
+/// `#[valid(0..=1, 7)]` on a field narrows it to those values,
+/// see `Ty::narrowed`.
+macro_rules! valid_range {
+    ($value:literal) => { ($value, $value) };
+    ($start:literal ..= $end:literal) => { ($start, $end) };
+}
+
+pub(crate) use valid_range;
 
+/// Declares a `#[repr(C)]` type and implements `InspectTy` for it.
+/// Fields of structs and unions may be narrowed with `#[valid(...)]`.
 macro_rules! derive_ty {
     (
         #[repr(C)]
         struct $name:ident {
-            $($field:ident: $ty:ty),*
+            $(
+                $(#[valid($($start:literal $(..= $end:literal)?),+ $(,)?)])?
+                $field:ident: $ty:ty
+            ),*
             $(,)?
         }
     ) => {
@@ -77,7 +162,14 @@ macro_rules! derive_ty {
             fn ty_of() -> Ty {
                 let mut st = $crate::ty::Struct::new(stringify!($name));
                 $(
-                    st.add_field(false, <$ty as $crate::derive::InspectTy>::ty_of());
+                    let ty = <$ty as $crate::derive::InspectTy>::ty_of();
+                    $(
+                        let ty = ty.narrowed(vec![
+                            $($crate::derive::valid_range!($start $(..= $end)?)),+
+                        ]);
+                    )?
+                    st.add_field(stringify!($field), false, ty)
+                        .expect("derived types have a layout");
                 )*
                 Ty::Struct(st)
             }
@@ -96,10 +188,12 @@ macro_rules! derive_ty {
         }
         impl $crate::derive::InspectTy for $name {
             fn ty_of() -> Ty {
-                let mut en = $crate::ty::Enum::new(stringify!($name), core::mem::size_of::<$sz>() as u32);
+                let mut en = $crate::ty::Enum::new(stringify!($name), core::mem::size_of::<$sz>() as u32)
+                    .expect("derived types have a layout");
                 let mut pos = 0;
                 $(
-                    en.add_variant(pos, <$payload as $crate::derive::InspectTy>::ty_of());
+                    en.add_variant(stringify!($variant), pos, <$payload as $crate::derive::InspectTy>::ty_of())
+                        .expect("derived types have a layout");
                     pos += 1;
                 )*
                 let _ = pos;
@@ -107,22 +201,71 @@ macro_rules! derive_ty {
             }
         }
     };
+    (
+        #[repr($sz:ty)]
+        enum $name:ident {
+            $($variant:ident $fields:tt),*
+            $(,)?
+        }
+    ) => {
+        #[repr($sz)]
+        enum $name {
+            $($variant $fields),*
+        }
+        impl $crate::derive::InspectTy for $name {
+            fn ty_of() -> Ty {
+                let mut en = $crate::ty::Enum::with_repr(
+                    stringify!($name), core::mem::size_of::<$sz>() as u32,
+                    $crate::ty::EnumRepr::Primitive,
+                ).expect("derived types have a layout");
+                let mut pos = 0;
+                $(
+                    en.add_variant_fields(stringify!($variant), pos, $crate::derive::derive_ty!(@fields $fields))
+                        .expect("derived types have a layout");
+                    pos += 1;
+                )*
+                let _ = pos;
+                Ty::Enum(en)
+            }
+        }
+    };
+    // the fields of a `A(u8, u32)` or `B { x: u16 }` variant
+    (@fields ($($field:ty),* $(,)?)) => {
+        // tuple fields are named by their index, like `.0`
+        vec![$(<$field as $crate::derive::InspectTy>::ty_of()),*].into_iter().enumerate()
+            .map(|(idx, ty)| (idx.to_string(), false, ty))
+    };
+    (@fields { $($field:ident: $ty:ty),* $(,)? }) => {
+        vec![$(
+            (stringify!($field).to_string(), false, <$ty as $crate::derive::InspectTy>::ty_of())
+        ),*]
+    };
     (
         #[repr(C)]
         union $name:ident {
-            $($variant:ident: $payload:ty),*
+            $(
+                $(#[valid($($start:literal $(..= $end:literal)?),+ $(,)?)])?
+                $variant:ident: $payload:ty
+            ),*
             $(,)?
         }
     ) => {
         #[repr(C)]
         union $name {
-            $($variant: $payload),*
+            $($variant: core::mem::ManuallyDrop<$payload>),*
         }
         impl $crate::derive::InspectTy for $name {
             fn ty_of() -> Ty {
                 let mut un = Union::new(stringify!($name));
                 $(
-                    un.add_variant(false, <$payload as $crate::derive::InspectTy>::ty_of());
+                    let ty = <$payload as $crate::derive::InspectTy>::ty_of();
+                    $(
+                        let ty = ty.narrowed(vec![
+                            $($crate::derive::valid_range!($start $(..= $end)?)),+
+                        ]);
+                    )?
+                    un.add_variant(stringify!($variant), false, ty)
+                        .expect("derived types have a layout");
                 )*
                 Ty::Union(un)
             }
@@ -130,4 +273,21 @@ macro_rules! derive_ty {
     };
 }
 
-pub(crate) use derive_ty;
\ No newline at end of file
+pub(crate) use derive_ty;
+
+/// Implements `InspectTy` as `Ty::Opaque` for types whose fields we can't
+/// describe, e.g. `repr(Rust)` types or ones from other crates.
+macro_rules! opaque_ty {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl $crate::derive::InspectTy for $ty {
+                fn ty_of() -> Ty {
+                    Ty::Opaque($crate::ty::Opaque::new(
+                        stringify!($ty), core::alloc::Layout::new::<$ty>()))
+                }
+            }
+        )*
+    };
+}
+
+opaque_ty!(String);
\ No newline at end of file
@@ -0,0 +1,1226 @@
+use core::alloc::Layout;
+use core::fmt;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::Error;
+use crate::ty::*;
+
+#[derive(Debug, Clone)]
+pub struct DwarfError {
+    pub message: String,
+}
+
+impl DwarfError {
+    fn new(message: impl Into<String>) -> Self {
+        Self { message: message.into() }
+    }
+}
+
+impl fmt::Display for DwarfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<Error> for DwarfError {
+    fn from(err: Error) -> Self {
+        Self::new(err.to_string())
+    }
+}
+
+type DwarfResult<T> = Result<T, DwarfError>;
+
+// DWARF tags
+const TAG_ARRAY_TYPE: u64 = 0x01;
+const TAG_CLASS_TYPE: u64 = 0x02;
+const TAG_ENUMERATION_TYPE: u64 = 0x04;
+const TAG_MEMBER: u64 = 0x0d;
+const TAG_POINTER_TYPE: u64 = 0x0f;
+const TAG_STRUCTURE_TYPE: u64 = 0x13;
+const TAG_TYPEDEF: u64 = 0x16;
+const TAG_UNION_TYPE: u64 = 0x17;
+const TAG_VARIANT: u64 = 0x19;
+const TAG_SUBRANGE_TYPE: u64 = 0x21;
+const TAG_BASE_TYPE: u64 = 0x24;
+const TAG_CONST_TYPE: u64 = 0x26;
+const TAG_ENUMERATOR: u64 = 0x28;
+const TAG_VARIANT_PART: u64 = 0x33;
+const TAG_VOLATILE_TYPE: u64 = 0x35;
+const TAG_RESTRICT_TYPE: u64 = 0x37;
+const TAG_NAMESPACE: u64 = 0x39;
+const TAG_ATOMIC_TYPE: u64 = 0x47;
+
+// DWARF attributes
+const AT_NAME: u64 = 0x03;
+const AT_LANGUAGE: u64 = 0x13;
+const AT_BYTE_SIZE: u64 = 0x0b;
+const AT_BIT_SIZE: u64 = 0x0d;
+const AT_DISCR: u64 = 0x15;
+const AT_DISCR_VALUE: u64 = 0x16;
+const AT_CONST_VALUE: u64 = 0x1c;
+const AT_LOWER_BOUND: u64 = 0x22;
+const AT_UPPER_BOUND: u64 = 0x2f;
+const AT_ACCESSIBILITY: u64 = 0x32;
+const AT_COUNT: u64 = 0x37;
+const AT_DATA_MEMBER_LOCATION: u64 = 0x38;
+const AT_DECLARATION: u64 = 0x3c;
+const AT_DISCR_LIST: u64 = 0x3d;
+const AT_ENCODING: u64 = 0x3e;
+const AT_TYPE: u64 = 0x49;
+const AT_DATA_BIT_OFFSET: u64 = 0x6b;
+const AT_STR_OFFSETS_BASE: u64 = 0x72;
+const AT_ALIGNMENT: u64 = 0x88;
+
+const ACCESS_PRIVATE: u64 = 3;
+
+// base type encodings
+const ATE_BOOLEAN: u64 = 0x02;
+const ATE_FLOAT: u64 = 0x04;
+const ATE_SIGNED: u64 = 0x05;
+const ATE_SIGNED_CHAR: u64 = 0x06;
+const ATE_UNSIGNED: u64 = 0x07;
+const ATE_UNSIGNED_CHAR: u64 = 0x08;
+
+const OP_PLUS_UCONST: u8 = 0x23;
+
+const LANG_RUST: u64 = 0x1c;
+
+/// The types read from the DWARF debug info of an ELF file.
+pub struct Imported {
+    pub types: BTreeMap<String, Ty>,
+    /// The named types that can't be represented as `Ty` and why, e.g.
+    /// because they contain bit-fields.
+    pub skipped: BTreeMap<String, DwarfError>,
+}
+
+/// Reads the types described by the DWARF debug info in an ELF executable,
+/// shared object or relocatable object file.
+/// Named structs, unions, enums and typedefs are returned under their
+/// `::`-qualified names; Rust enums become unions of per-variant structs
+/// that start with their tag (or niche) value, at the offsets from the
+/// debug info, and fieldless Rust enums are limited to their values; enums
+/// of other languages are integers of their size. Pointers and Rust
+/// references are thin and as wide as the host's, like `Ty::Ptr` and
+/// `Ty::Ref`; types with pointers of another width, e.g. from a 32-bit file
+/// read on a 64-bit host, are skipped like any other type that can't be
+/// represented as `Ty`.
+pub fn import_elf(data: &[u8]) -> DwarfResult<Imported> {
+    let elf = Elf::parse(data)?;
+    let sections = Sections {
+        info: elf.section(".debug_info")?
+            .ok_or_else(|| DwarfError::new("no .debug_info section, was this built with -g?"))?,
+        abbrev: elf.section(".debug_abbrev")?.unwrap_or_default(),
+        str: elf.section(".debug_str")?.unwrap_or_default(),
+        line_str: elf.section(".debug_line_str")?.unwrap_or_default(),
+        str_offsets: elf.section(".debug_str_offsets")?.unwrap_or_default(),
+        big_endian: elf.big_endian,
+        address_size: if elf.is_64 { 8 } else { 4 },
+    };
+    let mut info = DebugInfo::parse(&sections)?;
+
+    let mut imported = Imported { types: BTreeMap::new(), skipped: BTreeMap::new() };
+    for idx in 0..info.dies.len() {
+        let die = &info.dies[idx];
+        let named = matches!(die.tag,
+            TAG_STRUCTURE_TYPE | TAG_CLASS_TYPE | TAG_UNION_TYPE
+            | TAG_ENUMERATION_TYPE | TAG_TYPEDEF);
+        if !named || die.flag(AT_DECLARATION) || die.attr(AT_NAME).is_none() {
+            continue;
+        }
+        let name = info.qualified_name(idx, &sections)?;
+        if imported.types.contains_key(&name) || imported.skipped.contains_key(&name) {
+            continue;
+        }
+        match info.ty_of(idx, &sections) {
+            Ok(ty) => { imported.types.insert(name, ty); }
+            Err(err) => { imported.skipped.insert(name, err); }
+        }
+    }
+    Ok(imported)
+}
+
+/// Bounds-checked reader over a section in the file's byte order.
+#[derive(Clone)]
+struct Reader<'d> {
+    data: &'d [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'d> Reader<'d> {
+    fn new(data: &'d [u8], big_endian: bool) -> Self {
+        Self { data, pos: 0, big_endian }
+    }
+    fn at(&self, pos: usize) -> Self {
+        Self { pos, ..self.clone() }
+    }
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+    fn bytes(&mut self, len: usize) -> DwarfResult<&'d [u8]> {
+        let bytes = self.pos.checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| DwarfError::new(format!(
+                "unexpected end of data reading {} bytes at 0x{:x}", len, self.pos)))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+    fn uint(&mut self, len: usize) -> DwarfResult<u64> {
+        let bytes = self.bytes(len)?;
+        let mut value = 0_u64;
+        for idx in 0..len {
+            let byte = if self.big_endian { bytes[idx] } else { bytes[len - 1 - idx] };
+            value = (value << 8) | byte as u64;
+        }
+        Ok(value)
+    }
+    fn u8(&mut self) -> DwarfResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u16(&mut self) -> DwarfResult<u64> {
+        self.uint(2)
+    }
+    fn u32(&mut self) -> DwarfResult<u64> {
+        self.uint(4)
+    }
+    fn u64(&mut self) -> DwarfResult<u64> {
+        self.uint(8)
+    }
+    fn uleb(&mut self) -> DwarfResult<u64> {
+        let mut value = 0_u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+    fn sleb(&mut self) -> DwarfResult<i64> {
+        let mut value = 0_i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1_i64 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+    fn cstr(&mut self) -> DwarfResult<String> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest.iter().position(|&b| b == 0)
+            .ok_or_else(|| DwarfError::new("unterminated string"))?;
+        let text = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.pos += len + 1;
+        Ok(text)
+    }
+}
+
+fn to_usize(value: u64) -> DwarfResult<usize> {
+    usize::try_from(value).map_err(|_| DwarfError::new(format!("value 0x{:x} is too large", value)))
+}
+
+struct Elf<'d> {
+    data: &'d [u8],
+    big_endian: bool,
+    is_64: bool,
+    machine: u64,
+    sections: Vec<SectionHeader>,
+}
+
+struct SectionHeader {
+    name: String,
+    kind: u64,
+    flags: u64,
+    offset: usize,
+    size: usize,
+    link: usize,
+    info: usize,
+}
+
+const SHT_SYMTAB: u64 = 2;
+const SHT_RELA: u64 = 4;
+const SHT_NOBITS: u64 = 8;
+const SHF_COMPRESSED: u64 = 0x800;
+
+const EM_X86_64: u64 = 62;
+const EM_AARCH64: u64 = 183;
+
+impl<'d> Elf<'d> {
+    fn parse(data: &'d [u8]) -> DwarfResult<Self> {
+        if !data.starts_with(b"\x7fELF") || data.len() < 16 {
+            return Err(DwarfError::new("not an ELF file"));
+        }
+        let is_64 = match data[4] {
+            1 => false,
+            2 => true,
+            class => return Err(DwarfError::new(format!("unknown ELF class {}", class))),
+        };
+        let big_endian = match data[5] {
+            1 => false,
+            2 => true,
+            order => return Err(DwarfError::new(format!("unknown ELF byte order {}", order))),
+        };
+        let mut header = Reader::new(data, big_endian).at(18);
+        let machine = header.u16()?;
+        let word = if is_64 { 8 } else { 4 };
+        // skip e_version, e_entry and e_phoff
+        header.bytes(4 + 2 * word)?;
+        let shoff = to_usize(header.uint(word)?)?;
+        // skip e_flags, e_ehsize, e_phentsize and e_phnum
+        header.bytes(10)?;
+        let shentsize = to_usize(header.u16()?)?;
+        let shnum = to_usize(header.u16()?)?;
+        let shstrndx = to_usize(header.u16()?)?;
+
+        let mut sections = Vec::with_capacity(shnum);
+        let mut names = Vec::with_capacity(shnum);
+        for idx in 0..shnum {
+            let header = idx.checked_mul(shentsize).and_then(|start| start.checked_add(shoff))
+                .ok_or_else(|| DwarfError::new("section headers are out of bounds"))?;
+            let mut sh = Reader::new(data, big_endian).at(header);
+            names.push(to_usize(sh.u32()?)?);
+            let kind = sh.u32()?;
+            let flags = sh.uint(word)?;
+            let _addr = sh.uint(word)?;
+            let offset = to_usize(sh.uint(word)?)?;
+            let size = to_usize(sh.uint(word)?)?;
+            let link = to_usize(sh.u32()?)?;
+            let info = to_usize(sh.u32()?)?;
+            sections.push(SectionHeader {
+                name: String::new(), kind, flags, offset, size, link, info,
+            });
+        }
+        let mut elf = Self { data, big_endian, is_64, machine, sections };
+        if let Some(strtab) = elf.sections.get(shstrndx) {
+            let strtab = Reader::new(elf.data_of(strtab)?, big_endian);
+            for (section, name) in elf.sections.iter_mut().zip(names) {
+                section.name = strtab.at(name).cstr()?;
+            }
+        }
+        Ok(elf)
+    }
+    fn data_of(&self, section: &SectionHeader) -> DwarfResult<&'d [u8]> {
+        if section.kind == SHT_NOBITS {
+            return Ok(&[]);
+        }
+        section.offset.checked_add(section.size)
+            .and_then(|end| self.data.get(section.offset..end))
+            .ok_or_else(|| DwarfError::new(format!(
+                "section {} is out of bounds", section.name)))
+    }
+    /// Returns the contents of a section with relocations applied,
+    /// as relocatable objects refer to other debug sections through them.
+    fn section(&self, name: &str) -> DwarfResult<Option<Vec<u8>>> {
+        let idx = match self.sections.iter().position(|s| s.name == name) {
+            Some(idx) => idx,
+            None => return Ok(None),
+        };
+        let section = &self.sections[idx];
+        if section.flags & SHF_COMPRESSED != 0 {
+            return Err(DwarfError::new(format!("compressed section {} is not supported", name)));
+        }
+        let mut data = self.data_of(section)?.to_vec();
+        for rela in self.sections.iter().filter(|s| s.kind == SHT_RELA && s.info == idx) {
+            self.apply_relocations(&mut data, rela)?;
+        }
+        Ok(Some(data))
+    }
+    fn apply_relocations(&self, target: &mut [u8], rela: &SectionHeader) -> DwarfResult<()> {
+        let symtab = self.sections.get(rela.link)
+            .filter(|s| s.kind == SHT_SYMTAB)
+            .ok_or_else(|| DwarfError::new(format!("{} has no symbol table", rela.name)))?;
+        let symbols = Reader::new(self.data_of(symtab)?, self.big_endian);
+        let mut entries = Reader::new(self.data_of(rela)?, self.big_endian);
+        while !entries.is_empty() {
+            let (offset, sym, kind, addend) = if self.is_64 {
+                let offset = entries.u64()?;
+                let info = entries.u64()?;
+                (offset, info >> 32, info & 0xffff_ffff, entries.u64()? as i64)
+            } else {
+                let offset = entries.u32()?;
+                let info = entries.u32()?;
+                (offset, info >> 8, info & 0xff, entries.u32()? as i32 as i64)
+            };
+            // st_value of the symbol
+            let (entry_size, value_offset) = if self.is_64 { (24, 8) } else { (16, 4) };
+            let at = to_usize(sym)?.checked_mul(entry_size)
+                .and_then(|entry| entry.checked_add(value_offset))
+                .ok_or_else(|| DwarfError::new(format!(
+                    "symbol {} in {} is out of bounds", sym, rela.name)))?;
+            let value = if self.is_64 {
+                symbols.at(at).u64()?
+            } else {
+                symbols.at(at).u32()?
+            };
+            let size = match (self.machine, kind) {
+                (EM_X86_64, 1) | (EM_AARCH64, 257) => 8,
+                (EM_X86_64, 10) | (EM_X86_64, 11) | (EM_AARCH64, 258) => 4,
+                _ => {
+                    return Err(DwarfError::new(format!(
+                        "unsupported relocation type {} for machine {}", kind, self.machine)));
+                }
+            };
+            let value = value.wrapping_add(addend as u64);
+            let offset = to_usize(offset)?;
+            let bytes = offset.checked_add(size)
+                .and_then(|end| target.get_mut(offset..end))
+                .ok_or_else(|| DwarfError::new(format!(
+                    "relocation at 0x{:x} in {} is out of bounds", offset, rela.name)))?;
+            for (idx, byte) in bytes.iter_mut().enumerate() {
+                let shift = if self.big_endian { (size - 1 - idx) * 8 } else { idx * 8 };
+                *byte = (value >> shift) as u8;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Sections {
+    info: Vec<u8>,
+    abbrev: Vec<u8>,
+    str: Vec<u8>,
+    line_str: Vec<u8>,
+    str_offsets: Vec<u8>,
+    big_endian: bool,
+    /// Size of an address in the ELF class, 4 or 8 bytes.
+    address_size: u64,
+}
+
+impl Sections {
+    fn string_at(&self, section: &[u8], offset: u64) -> DwarfResult<String> {
+        Reader::new(section, self.big_endian).at(to_usize(offset)?).cstr()
+    }
+}
+
+#[derive(Clone)]
+enum Value {
+    Udata(u64),
+    Sdata(i64),
+    Data16(u128),
+    Str(String),
+    StrOffset(u64),
+    LineStrOffset(u64),
+    StrIndex(u64),
+    /// An offset into another debug section, e.g. `DW_AT_str_offsets_base`.
+    SecOffset(u64),
+    /// Absolute offset of the referenced entry in `.debug_info`.
+    Ref(usize),
+    Block(Vec<u8>),
+    Flag(bool),
+    Other,
+}
+
+struct AttrSpec {
+    name: u64,
+    form: u64,
+    implicit: i64,
+}
+
+struct Abbrev {
+    tag: u64,
+    has_children: bool,
+    attrs: Vec<AttrSpec>,
+}
+
+struct Unit {
+    offset_size: usize,
+    str_offsets_base: u64,
+    /// The `DW_AT_language` of the unit, if it has one.
+    language: Option<u64>,
+}
+
+struct Die {
+    tag: u64,
+    unit: usize,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    attrs: Vec<(u64, Value)>,
+}
+
+impl Die {
+    fn attr(&self, name: u64) -> Option<&Value> {
+        self.attrs.iter().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+    fn flag(&self, name: u64) -> bool {
+        matches!(self.attr(name), Some(Value::Flag(true)))
+    }
+    fn udata(&self, name: u64) -> Option<u64> {
+        match *self.attr(name)? {
+            Value::Udata(value) => Some(value),
+            Value::Sdata(value) => Some(value as u64),
+            _ => None,
+        }
+    }
+}
+
+struct DebugInfo {
+    dies: Vec<Die>,
+    units: Vec<Unit>,
+    by_offset: HashMap<usize, usize>,
+    cache: HashMap<usize, Result<Ty, DwarfError>>,
+    /// The types being converted, innermost last.
+    converting: Vec<usize>,
+    /// How often a type being converted was reached again.
+    cycles: usize,
+}
+
+impl DebugInfo {
+    fn parse(sections: &Sections) -> DwarfResult<Self> {
+        let mut info = Self {
+            dies: Vec::new(),
+            units: Vec::new(),
+            by_offset: HashMap::new(),
+            cache: HashMap::new(),
+            converting: Vec::new(),
+            cycles: 0,
+        };
+        let mut reader = Reader::new(&sections.info, sections.big_endian);
+        while !reader.is_empty() {
+            let unit_start = reader.pos;
+            let (unit_length, offset_size) = match reader.u32()? {
+                0xffff_ffff => (reader.u64()?, 8),
+                length => (length, 4),
+            };
+            let unit_end = to_usize(unit_length)?.checked_add(reader.pos)
+                .ok_or_else(|| DwarfError::new("unit length overflows"))?;
+            let version = reader.u16()?;
+            let (abbrev_offset, address_size) = match version {
+                2..=4 => {
+                    let abbrev_offset = reader.uint(offset_size)?;
+                    (abbrev_offset, reader.u8()?)
+                }
+                5 => {
+                    let unit_type = reader.u8()?;
+                    let address_size = reader.u8()?;
+                    let abbrev_offset = reader.uint(offset_size)?;
+                    match unit_type {
+                        // type units: type signature and type offset
+                        0x02 | 0x06 => { reader.bytes(8 + offset_size)?; }
+                        // skeleton and split units: dwo id
+                        0x04 | 0x05 => { reader.bytes(8)?; }
+                        _ => {}
+                    }
+                    (abbrev_offset, address_size)
+                }
+                _ => return Err(DwarfError::new(format!("unsupported DWARF version {}", version))),
+            };
+            let abbrevs = parse_abbrevs(sections, abbrev_offset)?;
+            let format = Format {
+                version,
+                offset_size,
+                address_size: address_size as usize,
+                unit_start,
+            };
+            info.units.push(Unit {
+                offset_size,
+                str_offsets_base: 2 * offset_size as u64,
+                language: None,
+            });
+            let unit = info.units.len() - 1;
+            let mut unit_reader = Reader {
+                data: &sections.info[..unit_end.min(sections.info.len())],
+                ..reader.clone()
+            };
+            info.parse_dies(&mut unit_reader, unit, &abbrevs, &format)?;
+            reader.pos = unit_end;
+        }
+        Ok(info)
+    }
+
+    fn parse_dies(
+        &mut self, reader: &mut Reader, unit: usize,
+        abbrevs: &HashMap<u64, Abbrev>, format: &Format,
+    ) -> DwarfResult<()> {
+        let mut parents: Vec<usize> = Vec::new();
+        while !reader.is_empty() {
+            let offset = reader.pos;
+            let code = reader.uleb()?;
+            if code == 0 {
+                parents.pop();
+                continue;
+            }
+            let abbrev = abbrevs.get(&code).ok_or_else(|| DwarfError::new(format!(
+                "unknown abbreviation {} at 0x{:x}", code, offset)))?;
+            let mut attrs = Vec::with_capacity(abbrev.attrs.len());
+            for spec in abbrev.attrs.iter() {
+                let value = read_value(reader, spec.form, spec.implicit, format)?;
+                attrs.push((spec.name, value));
+            }
+            let idx = self.dies.len();
+            let parent = parents.last().copied();
+            if let Some(parent) = parent {
+                self.dies[parent].children.push(idx);
+            } else {
+                // the unit's own entry
+                for (name, value) in attrs.iter() {
+                    match (*name, value) {
+                        (AT_STR_OFFSETS_BASE, &Value::SecOffset(base) | &Value::Udata(base)) => {
+                            self.units[unit].str_offsets_base = base;
+                        }
+                        (AT_LANGUAGE, &Value::Udata(language)) => {
+                            self.units[unit].language = Some(language);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            self.dies.push(Die { tag: abbrev.tag, unit, parent, children: Vec::new(), attrs });
+            self.by_offset.insert(offset, idx);
+            if abbrev.has_children {
+                parents.push(idx);
+            }
+        }
+        Ok(())
+    }
+
+    fn die_at(&self, offset: usize) -> DwarfResult<usize> {
+        self.by_offset.get(&offset).copied().ok_or_else(|| {
+            DwarfError::new(format!("reference to unknown entry at 0x{:x}", offset))
+        })
+    }
+
+    fn string(&self, idx: usize, name: u64, sections: &Sections) -> DwarfResult<Option<String>> {
+        let die = &self.dies[idx];
+        let text = match die.attr(name) {
+            None => return Ok(None),
+            Some(Value::Str(text)) => text.clone(),
+            Some(&Value::StrOffset(offset)) => sections.string_at(&sections.str, offset)?,
+            Some(&Value::LineStrOffset(offset)) => sections.string_at(&sections.line_str, offset)?,
+            Some(&Value::StrIndex(index)) => {
+                let unit = &self.units[die.unit];
+                let entry = index.checked_mul(unit.offset_size as u64)
+                    .and_then(|entry| entry.checked_add(unit.str_offsets_base))
+                    .ok_or_else(|| DwarfError::new(format!("string index {} overflows", index)))?;
+                let offset = Reader::new(&sections.str_offsets, sections.big_endian)
+                    .at(to_usize(entry)?)
+                    .uint(unit.offset_size)?;
+                sections.string_at(&sections.str, offset)?
+            }
+            Some(_) => return Err(DwarfError::new("string attribute has an unexpected form")),
+        };
+        Ok(Some(text))
+    }
+
+    fn qualified_name(&self, idx: usize, sections: &Sections) -> DwarfResult<String> {
+        let mut path = Vec::new();
+        let mut current = Some(idx);
+        while let Some(idx) = current {
+            let die = &self.dies[idx];
+            let scope = matches!(die.tag,
+                TAG_NAMESPACE | TAG_STRUCTURE_TYPE | TAG_CLASS_TYPE
+                | TAG_UNION_TYPE | TAG_ENUMERATION_TYPE | TAG_TYPEDEF);
+            if scope {
+                if let Some(name) = self.string(idx, AT_NAME, sections)? {
+                    path.push(name);
+                }
+            }
+            current = die.parent;
+        }
+        path.reverse();
+        Ok(path.join("::"))
+    }
+
+    fn type_ref(&self, idx: usize) -> DwarfResult<Option<usize>> {
+        match self.dies[idx].attr(AT_TYPE) {
+            Some(&Value::Ref(offset)) => self.die_at(offset).map(Some),
+            Some(_) => Err(DwarfError::new("type signatures are not supported")),
+            None => Ok(None),
+        }
+    }
+
+    fn ty_of(&mut self, idx: usize, sections: &Sections) -> DwarfResult<Ty> {
+        if let Some(ty) = self.cache.get(&idx) {
+            return ty.clone();
+        }
+        // types refer to themselves through pointers, which only need
+        // their pointee for its alignment
+        if self.converting.contains(&idx) {
+            self.cycles += 1;
+            let name = self.qualified_name(idx, sections)?;
+            return Err(DwarfError::new(format!("type `{}` contains itself", name)));
+        }
+        let cycles = self.cycles;
+        self.converting.push(idx);
+        let ty = self.convert(idx, sections);
+        self.converting.pop();
+        // a type that ran into one being converted may be fine on its own
+        if ty.is_ok() || self.cycles == cycles {
+            self.cache.insert(idx, ty.clone());
+        }
+        ty
+    }
+
+    fn convert(&mut self, idx: usize, sections: &Sections) -> DwarfResult<Ty> {
+        let name = self.qualified_name(idx, sections)?;
+        let die = &self.dies[idx];
+        match die.tag {
+            TAG_TYPEDEF | TAG_CONST_TYPE | TAG_VOLATILE_TYPE
+            | TAG_RESTRICT_TYPE | TAG_ATOMIC_TYPE => {
+                match self.type_ref(idx)? {
+                    Some(target) => self.ty_of(target, sections),
+                    None => Ok(Ty::Void),
+                }
+            }
+            TAG_BASE_TYPE => {
+                let size = die.udata(AT_BYTE_SIZE).unwrap_or(0);
+                let encoding = die.udata(AT_ENCODING).unwrap_or(0);
+                match (encoding, size) {
+                    (_, 0) => Ok(Ty::Void),
+                    (ATE_BOOLEAN, 1) => Ok(Ty::Bool),
+                    (ATE_SIGNED | ATE_UNSIGNED | ATE_SIGNED_CHAR
+                        | ATE_UNSIGNED_CHAR | ATE_FLOAT, 1 | 2 | 4 | 8 | 16) => {
+                        Ok(Ty::Int(size as u32))
+                    }
+                    _ => Err(DwarfError::new(format!(
+                        "base type `{}` with encoding {} is not supported", name, encoding))),
+                }
+            }
+            TAG_POINTER_TYPE => {
+                let size = die.udata(AT_BYTE_SIZE).unwrap_or(sections.address_size);
+                if size != core::mem::size_of::<*const u8>() as u64 {
+                    return Err(DwarfError::new(format!(
+                        "pointers of {} bytes are not supported", size)));
+                }
+                // Rust names its pointers and references `*const T`, `*mut T`,
+                // `&T` and `&mut T`, C pointers are to `const T` or `T`
+                let pointer = self.string(idx, AT_NAME, sections)?.unwrap_or_default();
+                let pointee = self.type_ref(idx)?;
+                let to_const = pointee.is_some_and(|pointee| self.dies[pointee].tag == TAG_CONST_TYPE);
+                let shared = to_const || pointer.starts_with("*const ")
+                    || pointer.starts_with('&') && !pointer.starts_with("&mut ");
+                let kind = if shared { RefKind::Shared } else { RefKind::Unique };
+                if pointer.starts_with('&') {
+                    let pointee = pointee.ok_or_else(|| DwarfError::new(format!(
+                        "reference `{}` has no pointee", pointer)))?;
+                    let layout = layout_of(&self.ty_of(pointee, sections)?)?;
+                    return Ok(Ty::Ref(Reference { kind, size: layout.size(), align: layout.align() }));
+                }
+                // `void`, functions and incomplete types have no alignment
+                let align = match pointee.map(|pointee| self.ty_of(pointee, sections)) {
+                    Some(Ok(ty)) => layout_of(&ty).map_or(1, |layout| layout.align()),
+                    _ => 1,
+                };
+                Ok(Ty::Ptr(Pointer { kind, align, non_null: false }))
+            }
+            TAG_ARRAY_TYPE => {
+                let element = self.type_ref(idx)?
+                    .ok_or_else(|| DwarfError::new("array without element type"))?;
+                let mut ty = self.ty_of(element, sections)?;
+                let dims = self.dies[idx].children.iter()
+                    .filter(|&&child| self.dies[child].tag == TAG_SUBRANGE_TYPE)
+                    .map(|&child| {
+                        let range = &self.dies[child];
+                        match (range.udata(AT_COUNT), range.udata(AT_UPPER_BOUND)) {
+                            (Some(count), _) => count,
+                            (None, Some(upper)) => {
+                                let lower = range.udata(AT_LOWER_BOUND).unwrap_or(0);
+                                // an upper bound of -1 is an empty array
+                                upper.wrapping_add(1).saturating_sub(lower)
+                            }
+                            // flexible array member
+                            (None, None) => 0,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                for count in dims.into_iter().rev() {
+                    ty = Ty::Array(Box::new(Array { element: ty, count: to_usize(count)? }));
+                }
+                Ok(ty)
+            }
+            TAG_ENUMERATION_TYPE => {
+                let size = die.udata(AT_BYTE_SIZE)
+                    .ok_or_else(|| DwarfError::new(format!("enum `{}` has no size", name)))?;
+                if !matches!(size, 1 | 2 | 4 | 8 | 16) {
+                    return Err(DwarfError::new(format!("enum `{}` has size {}", name, size)));
+                }
+                // a C or C++ enum may hold any value of its underlying
+                // integer, only a Rust one is limited to its enumerators
+                if self.units[die.unit].language != Some(LANG_RUST) {
+                    return Ok(Ty::Int(size as u32));
+                }
+                let mask = u128::MAX >> (128 - size * 8);
+                let mut en = Enum::new(name.clone(), size as u32)?;
+                let mut seen = Vec::new();
+                for &idx in die.children.iter() {
+                    let child = &self.dies[idx];
+                    if child.tag != TAG_ENUMERATOR {
+                        continue;
+                    }
+                    let value = match child.attr(AT_CONST_VALUE) {
+                        Some(&Value::Udata(value)) => value as u128,
+                        Some(&Value::Sdata(value)) => value as i128 as u128,
+                        Some(&Value::Data16(value)) => value,
+                        _ => return Err(DwarfError::new(format!(
+                            "enumerator of `{}` has no value", name))),
+                    } & mask;
+                    if !seen.contains(&value) {
+                        seen.push(value);
+                        let variant = self.string(idx, AT_NAME, sections)?.unwrap_or_default();
+                        en.add_variant(variant, value, Ty::Void)?;
+                    }
+                }
+                if en.variants.is_empty() {
+                    return Err(DwarfError::new(format!("enum `{}` has no enumerators", name)));
+                }
+                Ok(Ty::Enum(en))
+            }
+            TAG_STRUCTURE_TYPE | TAG_CLASS_TYPE | TAG_UNION_TYPE => {
+                if die.flag(AT_DECLARATION) {
+                    return Err(DwarfError::new(format!("type `{}` is incomplete", name)));
+                }
+                let size = to_usize(die.udata(AT_BYTE_SIZE).unwrap_or(0))?;
+                let align = die.udata(AT_ALIGNMENT).map(to_usize).transpose()?;
+                let is_union = die.tag == TAG_UNION_TYPE;
+                let children = die.children.clone();
+                let members = self.members(&children, sections)?;
+                let variant_part = children.iter().copied()
+                    .find(|&child| self.dies[child].tag == TAG_VARIANT_PART);
+                if let Some(variant_part) = variant_part {
+                    return self.variant_union(&name, variant_part, members, size, align, sections);
+                }
+                if is_union {
+                    let mut un = Union::new(name.clone());
+                    for member in members {
+                        un.add_variant(member.name, member.private, member.ty)?;
+                    }
+                    un.layout = exact_layout(&name, size, align, un.layout.align())?;
+                    Ok(Ty::Union(un))
+                } else {
+                    build_struct(&name, members, size, align).map(Ty::Struct)
+                }
+            }
+            tag => Err(DwarfError::new(format!(
+                "type `{}` with tag 0x{:x} is not supported", name, tag))),
+        }
+    }
+
+    fn members(&mut self, children: &[usize], sections: &Sections) -> DwarfResult<Vec<Member>> {
+        let mut members = Vec::new();
+        for &child in children.iter() {
+            let die = &self.dies[child];
+            if die.tag != TAG_MEMBER || die.flag(AT_DECLARATION) {
+                continue;
+            }
+            if die.attr(AT_BIT_SIZE).is_some() || die.attr(AT_DATA_BIT_OFFSET).is_some() {
+                return Err(DwarfError::new("bit-fields are not supported"));
+            }
+            let offset = member_offset(die)?;
+            let private = die.udata(AT_ACCESSIBILITY) == Some(ACCESS_PRIVATE);
+            let name = self.string(child, AT_NAME, sections)?.unwrap_or_default();
+            let ty = match self.type_ref(child)? {
+                Some(target) => self.ty_of(target, sections)?,
+                None => return Err(DwarfError::new("member without a type")),
+            };
+            members.push(Member { name, offset, private, ty });
+        }
+        Ok(members)
+    }
+
+    /// Rust enums are described as a `variant_part` holding the tag member
+    /// and one `variant` per discriminant value. Each variant becomes a
+    /// struct with the tag written at its offset next to the variant's
+    /// fields, and the enum is the union of these structs.
+    /// The variant without a value is the dataful variant of a niche-encoded
+    /// enum, whose fields already cover the niche.
+    fn variant_union(
+        &mut self, name: &str, variant_part: usize, common: Vec<Member>,
+        size: usize, align: Option<usize>, sections: &Sections,
+    ) -> DwarfResult<Ty> {
+        let tag = match self.dies[variant_part].attr(AT_DISCR) {
+            Some(&Value::Ref(offset)) => {
+                let tag = self.die_at(offset)?;
+                let offset = member_offset(&self.dies[tag])?;
+                let size = match self.type_ref(tag)? {
+                    Some(ty) => layout_of(&self.ty_of(ty, sections)?)?.size(),
+                    None => return Err(DwarfError::new(format!("tag of `{}` has no type", name))),
+                };
+                let tag_name = self.string(tag, AT_NAME, sections)?.unwrap_or_default();
+                Some((tag_name, offset, size as u32))
+            }
+            _ => None,
+        };
+        let variants = self.dies[variant_part].children.iter().copied()
+            .filter(|&child| self.dies[child].tag == TAG_VARIANT)
+            .collect::<Vec<_>>();
+
+        let mut un = Union::new(name);
+        for variant in variants {
+            if self.dies[variant].attr(AT_DISCR_LIST).is_some() {
+                return Err(DwarfError::new(format!(
+                    "discriminant lists in `{}` are not supported", name)));
+            }
+            let mut fields = common.clone();
+            let mut variant_name = None;
+            let children = self.dies[variant].children.clone();
+            for member in self.members(&children, sections)? {
+                match member.ty {
+                    // the variant's own fields are relative to the enum
+                    Ty::Struct(st) => {
+                        variant_name = Some(member.name);
+                        for field in st.fields {
+                            let offset = member.offset.checked_add(field.offset)
+                                .ok_or_else(|| DwarfError::new(format!(
+                                    "fields of `{}` are out of bounds", name)))?;
+                            fields.push(Member {
+                                name: field.name,
+                                offset,
+                                private: field.private,
+                                ty: field.ty,
+                            });
+                        }
+                    }
+                    ty => fields.push(Member { ty, ..member }),
+                }
+            }
+            let disc = match self.dies[variant].attr(AT_DISCR_VALUE) {
+                Some(&Value::Udata(value)) => Some(value as u128),
+                Some(&Value::Sdata(value)) => Some(value as i128 as u128),
+                Some(&Value::Data16(value)) => Some(value),
+                _ => None,
+            };
+            let variant_name = variant_name
+                .unwrap_or_else(|| format!("<variant{}>", un.variants.len()));
+            if let (Some(disc), Some((tag_name, offset, tag_size))) = (disc, &tag) {
+                let mask = u128::MAX >> (128 - tag_size * 8);
+                let mut tag_ty = Enum::new(format!("{}::<tag>", name), *tag_size)?;
+                tag_ty.add_variant(variant_name.clone(), disc & mask, Ty::Void)?;
+                fields.push(Member {
+                    name: tag_name.clone(),
+                    offset: *offset,
+                    private: false,
+                    ty: Ty::Enum(tag_ty),
+                });
+            }
+            let struct_name = format!("{}::{}", name, variant_name);
+            let st = build_struct(&struct_name, fields, size, align)?;
+            un.add_variant(variant_name, false, Ty::Struct(st))?;
+        }
+        // a zero-variant enum, e.g. `enum Void {}`, is an empty and thus
+        // uninhabited union
+        un.layout = exact_layout(name, size, align, un.layout.align())?;
+        Ok(Ty::Union(un))
+    }
+}
+
+#[derive(Clone)]
+struct Member {
+    name: String,
+    offset: usize,
+    private: bool,
+    ty: Ty,
+}
+
+fn member_offset(die: &Die) -> DwarfResult<usize> {
+    match die.attr(AT_DATA_MEMBER_LOCATION) {
+        None => Ok(0),
+        Some(&Value::Udata(offset)) => to_usize(offset),
+        Some(&Value::Sdata(offset)) => to_usize(offset as u64),
+        Some(Value::Block(expr)) => {
+            let mut expr = Reader::new(expr, false);
+            if expr.u8()? != OP_PLUS_UCONST {
+                return Err(DwarfError::new("unsupported member location expression"));
+            }
+            to_usize(expr.uleb()?)
+        }
+        Some(_) => Err(DwarfError::new("unsupported member location")),
+    }
+}
+
+/// Builds a struct with fields at the offsets from the debug info. Without
+/// an explicit alignment, the struct is treated as packed if any field is
+/// placed below its natural alignment.
+fn build_struct(
+    name: &str, mut members: Vec<Member>, size: usize, align: Option<usize>,
+) -> DwarfResult<Struct> {
+    members.sort_by_key(|member| member.offset);
+    let mut misaligned = false;
+    for member in members.iter() {
+        misaligned |= member.offset % layout_of(&member.ty)?.align() != 0;
+    }
+    let pack = if misaligned { Some(1) } else { None };
+    let mut st = Struct::with_repr(name, pack, None)?;
+    let mut end = 0;
+    for member in members {
+        if member.offset < end {
+            return Err(DwarfError::new(format!("fields of `{}` overlap", name)));
+        }
+        end = member.offset.checked_add(layout_of(&member.ty)?.size())
+            .ok_or_else(|| DwarfError::new(format!("fields of `{}` are out of bounds", name)))?;
+        st.add_field_at(member.name, member.private, member.offset, member.ty)?;
+    }
+    st.layout = exact_layout(name, size, align, st.layout.align())?;
+    Ok(st)
+}
+
+fn exact_layout(
+    name: &str, size: usize, align: Option<usize>, natural: usize,
+) -> DwarfResult<Layout> {
+    let align = align.unwrap_or(natural);
+    Layout::from_size_align(size, align)
+        .ok()
+        .filter(|layout| layout.size() % layout.align() == 0)
+        .ok_or_else(|| DwarfError::new(format!(
+            "`{}` has size {} which is not a multiple of its alignment {}", name, size, align)))
+}
+
+struct Format {
+    version: u64,
+    offset_size: usize,
+    address_size: usize,
+    unit_start: usize,
+}
+
+fn parse_abbrevs(sections: &Sections, offset: u64) -> DwarfResult<HashMap<u64, Abbrev>> {
+    let mut reader = Reader::new(&sections.abbrev, sections.big_endian).at(to_usize(offset)?);
+    let mut abbrevs = HashMap::new();
+    loop {
+        let code = reader.uleb()?;
+        if code == 0 {
+            return Ok(abbrevs);
+        }
+        let tag = reader.uleb()?;
+        let has_children = reader.u8()? != 0;
+        let mut attrs = Vec::new();
+        loop {
+            let name = reader.uleb()?;
+            let form = reader.uleb()?;
+            if name == 0 && form == 0 {
+                break;
+            }
+            let implicit = if form == 0x21 { reader.sleb()? } else { 0 };
+            attrs.push(AttrSpec { name, form, implicit });
+        }
+        abbrevs.insert(code, Abbrev { tag, has_children, attrs });
+    }
+}
+
+fn read_value(reader: &mut Reader, form: u64, implicit: i64, format: &Format) -> DwarfResult<Value> {
+    let unit_ref = |offset: u64| -> DwarfResult<Value> {
+        format.unit_start.checked_add(to_usize(offset)?).map(Value::Ref)
+            .ok_or_else(|| DwarfError::new(format!("reference 0x{:x} overflows", offset)))
+    };
+    let value = match form {
+        // addr, addrx, addrx1-4
+        0x01 => { reader.uint(format.address_size)?; Value::Other }
+        0x1b | 0x1f01 => { reader.uleb()?; Value::Other }
+        0x29 => { reader.bytes(1)?; Value::Other }
+        0x2a => { reader.bytes(2)?; Value::Other }
+        0x2b => { reader.bytes(3)?; Value::Other }
+        0x2c => { reader.bytes(4)?; Value::Other }
+        // block2, block4, block, block1, exprloc
+        0x03 => { let len = reader.u16()?; Value::Block(reader.bytes(to_usize(len)?)?.to_vec()) }
+        0x04 => { let len = reader.u32()?; Value::Block(reader.bytes(to_usize(len)?)?.to_vec()) }
+        0x09 | 0x18 => {
+            let len = reader.uleb()?;
+            Value::Block(reader.bytes(to_usize(len)?)?.to_vec())
+        }
+        0x0a => { let len = reader.u8()?; Value::Block(reader.bytes(len as usize)?.to_vec()) }
+        // data1, data2, data4, data8, data16
+        0x0b => Value::Udata(reader.u8()? as u64),
+        0x05 => Value::Udata(reader.u16()?),
+        0x06 => Value::Udata(reader.u32()?),
+        0x07 => Value::Udata(reader.u64()?),
+        0x1e => {
+            let low = reader.u64()? as u128;
+            let high = reader.u64()? as u128;
+            if reader.big_endian {
+                Value::Data16((low << 64) | high)
+            } else {
+                Value::Data16((high << 64) | low)
+            }
+        }
+        0x0d => Value::Sdata(reader.sleb()?),
+        0x0f => Value::Udata(reader.uleb()?),
+        0x21 => Value::Sdata(implicit),
+        // string, strp, line_strp, strx, strx1-4
+        0x08 => Value::Str(reader.cstr()?),
+        0x0e => Value::StrOffset(reader.uint(format.offset_size)?),
+        0x1f => Value::LineStrOffset(reader.uint(format.offset_size)?),
+        0x1a | 0x1f02 => Value::StrIndex(reader.uleb()?),
+        0x25 => Value::StrIndex(reader.uint(1)?),
+        0x26 => Value::StrIndex(reader.uint(2)?),
+        0x27 => Value::StrIndex(reader.uint(3)?),
+        0x28 => Value::StrIndex(reader.uint(4)?),
+        // flag, flag_present
+        0x0c => Value::Flag(reader.u8()? != 0),
+        0x19 => Value::Flag(true),
+        // ref1, ref2, ref4, ref8, ref_udata, ref_addr
+        0x11 => unit_ref(reader.uint(1)?)?,
+        0x12 => unit_ref(reader.u16()?)?,
+        0x13 => unit_ref(reader.u32()?)?,
+        0x14 => unit_ref(reader.u64()?)?,
+        0x15 => unit_ref(reader.uleb()?)?,
+        0x10 => {
+            let size = if format.version == 2 { format.address_size } else { format.offset_size };
+            Value::Ref(to_usize(reader.uint(size)?)?)
+        }
+        0x17 => Value::SecOffset(reader.uint(format.offset_size)?),
+        // strp_sup, GNU_ref_alt, GNU_strp_alt, ref_sup4, ref_sup8
+        0x1d | 0x1f20 | 0x1f21 => { reader.uint(format.offset_size)?; Value::Other }
+        0x1c => { reader.u32()?; Value::Other }
+        0x24 => { reader.u64()?; Value::Other }
+        // ref_sig8
+        0x20 => { reader.u64()?; Value::Other }
+        // loclistx, rnglistx
+        0x22 | 0x23 => { reader.uleb()?; Value::Other }
+        0x16 => {
+            let form = reader.uleb()?;
+            return read_value(reader, form, implicit, format);
+        }
+        _ => return Err(DwarfError::new(format!("unknown attribute form 0x{:x}", form))),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two DWARF 5 compile units, `a::Alpha` and `b::Beta`, whose names are
+    /// `DW_FORM_strx` indices relative to each unit's `DW_AT_str_offsets_base`.
+    const STRX: &[u8] = include_bytes!("../tests/fixtures/dwarf5_strx.o");
+    /// A `rustc -g --emit=obj` object, x86_64 with RELA relocations, of
+    /// `rust_enums::{Tagged, Niche, Dataful, Link}`:
+    /// `enum Tagged { A(u8), B(u32), C }`, `enum Niche { Some(bool), None }`,
+    /// `enum Dataful { Data(u8, bool), Empty, Other }` and
+    /// `struct Link { next: *const Link, cell: &'static u16 }`, with a
+    /// `[[u16; 3]; 2]` parameter.
+    const RUST_ENUMS: &[u8] = include_bytes!("../tests/fixtures/rust_enums.o");
+    /// A `gcc -g -c` object, x86_64 with RELA relocations, of
+    /// `typedef volatile const unsigned short grid[2][3]` through a chain of
+    /// typedefs, `struct Grid { u8 tag; grid cells; }`, the enums
+    /// `Color { RED = 1, GREEN = 2, BLUE = 0x80 }` and packed
+    /// `Small { LO = -1, HI = 100 }`, `struct Node` with pointers to itself,
+    /// `const unsigned int` and `void`, and `struct Bits` with bit-fields.
+    const C_TYPES: &[u8] = include_bytes!("../tests/fixtures/c_types.o");
+    /// The same source built with `gcc -m32`, with 4-byte pointers.
+    const C_TYPES_32: &[u8] = include_bytes!("../tests/fixtures/c_types_32.o");
+
+    fn fields(ty: &Ty) -> Vec<(&str, usize)> {
+        let Ty::Struct(st) = ty else { panic!("not a struct") };
+        st.fields.iter().map(|field| (field.name.as_str(), field.offset)).collect()
+    }
+
+    /// A variant's name, its fields and the value of its tag, if it has one.
+    type Variant<'t> = (&'t str, Vec<(&'t str, usize)>, Option<u128>);
+
+    fn variants(ty: &Ty) -> Vec<Variant<'_>> {
+        let Ty::Union(un) = ty else { panic!("not a union") };
+        un.variants.iter().map(|variant| {
+            let Ty::Struct(ref st) = variant.ty else { panic!("not a struct") };
+            let tag = st.fields.iter().find_map(|field| match field.ty {
+                Ty::Enum(ref en) => Some(en.variants[0].disc),
+                _ => None,
+            });
+            let fields = st.fields.iter()
+                .filter(|field| !matches!(field.ty, Ty::Enum(_)))
+                .map(|field| (field.name.as_str(), field.offset))
+                .collect();
+            (variant.name.as_str(), fields, tag)
+        }).collect()
+    }
+
+    #[test]
+    fn variant_unions() {
+        let types = import_elf(RUST_ENUMS).unwrap().types;
+        assert_eq!(variants(&types["rust_enums::Tagged"]), [
+            ("A", vec![("__0", 1)], Some(0)),
+            ("B", vec![("__0", 4)], Some(1)),
+            ("C", vec![], Some(2)),
+        ]);
+        assert_eq!(layout_of(&types["rust_enums::Tagged"]).unwrap().size(), 8);
+        // the dataful variant has no tag value, its fields hold the niche
+        assert_eq!(variants(&types["rust_enums::Niche"]), [
+            ("Some", vec![("__0", 0)], None),
+            ("None", vec![], Some(2)),
+        ]);
+        assert_eq!(variants(&types["rust_enums::Dataful"]), [
+            ("Data", vec![("__1", 0), ("__0", 1)], None),
+            ("Empty", vec![], Some(2)),
+            ("Other", vec![], Some(3)),
+        ]);
+        let Ty::Union(ref niche) = types["rust_enums::Niche"] else { panic!("not a union") };
+        let Ty::Struct(ref some) = niche.variants[0].ty else { panic!("not a struct") };
+        assert!(some.fields[0].ty == Ty::Bool);
+    }
+
+    #[test]
+    fn arrays_and_typedef_chains() {
+        let types = import_elf(C_TYPES).unwrap().types;
+        // two subranges of one array type, under typedefs and qualifiers
+        let grid = Ty::Array(Box::new(Array {
+            element: Ty::Array(Box::new(Array { element: Ty::Int(2), count: 3 })),
+            count: 2,
+        }));
+        assert!(types["grid"] == grid);
+        assert!(types["vchalf"] == Ty::Int(2));
+        assert_eq!(fields(&types["Grid"]), [("tag", 0), ("cells", 2)]);
+        assert_eq!(layout_of(&types["Grid"]).unwrap().size(), 14);
+        // the relocations of the object are applied, or no name would be right
+        assert!(types.contains_key("u8") && types.contains_key("chalf"));
+    }
+
+    #[test]
+    fn enumerations() {
+        let types = import_elf(C_TYPES).unwrap().types;
+        // a C enum may hold any value of its size, as parse_c has it too
+        assert!(types["Color"] == Ty::Int(4));
+        assert!(types["Small"] == Ty::Int(1));
+    }
+
+    #[test]
+    fn pointers() {
+        let imported = import_elf(C_TYPES).unwrap();
+        let Ty::Struct(ref node) = imported.types["Node"] else { panic!("not a struct") };
+        let pointers = node.fields.iter().filter_map(|field| match field.ty {
+            Ty::Ptr(ref ptr) => Some((field.name.as_str(), ptr.kind == RefKind::Shared, ptr.align)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        // a pointer to the struct being converted gets no alignment from it
+        assert_eq!(pointers, [("next", false, 1), ("value", true, 4), ("any", false, 1)]);
+        assert_eq!(layout_of(&imported.types["Node"]).unwrap().size(), 32);
+        assert_eq!(imported.skipped["Bits"].message, "bit-fields are not supported");
+
+        // `&'static u16` is a reference, which has no layout, like the one
+        // `InspectTy` gives `&u16`
+        let imported = import_elf(RUST_ENUMS).unwrap();
+        assert_eq!(imported.skipped["rust_enums::Link"].message, "reference is not supported");
+
+        // a 32-bit ELF file has pointers of another size than the host's
+        let imported = import_elf(C_TYPES_32).unwrap();
+        assert_eq!(imported.skipped["Node"].message, "pointers of 4 bytes are not supported");
+        assert_eq!(fields(&imported.types["Grid"]), [("tag", 0), ("cells", 2)]);
+    }
+
+    #[test]
+    fn str_offsets_base_per_unit() {
+        let types = import_elf(STRX).unwrap().types;
+        assert_eq!(fields(&types["a::Alpha"]), [("a", 0), ("b", 4)]);
+        assert_eq!(fields(&types["b::Beta"]), [("x", 0), ("y", 8)]);
+    }
+
+    #[test]
+    fn damaged_input_is_an_error() {
+        for len in 0..STRX.len() {
+            let _ = import_elf(&STRX[..len]);
+        }
+        let mut data = STRX.to_vec();
+        for idx in 0..data.len() {
+            data[idx] ^= 0xff;
+            let _ = import_elf(&data);
+            data[idx] ^= 0xff;
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use core::fmt;
+
+use crate::validate::Problem;
+
+/// Why a type couldn't be laid out, compiled, printed or checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// `Ty::Int` of a size other than 1, 2, 4, 8 or 16 bytes.
+    InvalidIntSize(u32),
+    /// The size of a type doesn't fit in `isize`.
+    LayoutOverflow,
+    /// A `repr(packed(N))` or `repr(align(N))` that isn't a power of two.
+    InvalidAlign(usize),
+    /// A field placed before the end of the field preceding it.
+    OverlappingField { offset: usize, end: usize },
+    /// A type without values, named by the type. `Compiler::compile`
+    /// handles these, but they can't be part of a larger program.
+    Uninhabited(String),
+    /// A part of the type system that isn't implemented yet.
+    Unsupported(&'static str),
+    /// Problems `Ty::validate` found, see `Compiler::compile`.
+    InvalidTy(Vec<Problem>),
+    /// A program the compiler wouldn't emit, e.g. with a misplaced patch.
+    MalformedProgram(String),
+    Fmt(fmt::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidIntSize(size) => write!(f, "invalid int size {}", size),
+            Error::LayoutOverflow => write!(f, "type is too large"),
+            Error::InvalidAlign(align) => write!(f, "alignment {} is not a power of two", align),
+            Error::OverlappingField { offset, end } => write!(f,
+                "field at offset {} overlaps the previous field ending at {}", offset, end),
+            Error::Uninhabited(name) => write!(f, "`{}` is uninhabited", name),
+            Error::Unsupported(what) => write!(f, "{} is not supported", what),
+            Error::InvalidTy(problems) => {
+                let problems = problems.iter().map(|problem| problem.to_string())
+                    .collect::<Vec<_>>();
+                write!(f, "invalid type: {}", problems.join("; "))
+            }
+            Error::MalformedProgram(why) => write!(f, "malformed program: {}", why),
+            Error::Fmt(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(err: fmt::Error) -> Self {
+        Error::Fmt(err)
+    }
+}
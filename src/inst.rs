@@ -1,6 +1,8 @@
 use core::fmt;
+use std::collections::{BTreeMap, HashMap};
 
-use crate::ty::Endian;
+use crate::error::Error;
+use crate::ty::{Endian, Ty};
 
 fn write_target_uint(endianness: Endian, target: &mut [u8], data: u128) {
     // This u128 holds an "any-size uint" (since smaller uints can fits in it)
@@ -18,13 +20,19 @@ pub type InstPtr = u32;
 pub enum Inst {
     Accept,
     Uninit,
-    // TODO: implement references and pointers
-    #[allow(dead_code)]
+    /// `count` uninit bytes, e.g. padding.
+    UninitRun(u32),
     Pointer(InstrPointer),
+    // TODO: implement references
     #[allow(dead_code)]
     Ref(InstrRef),
     Byte(InstByte),
     ByteRange(InstByteRange),
+    ByteRangeRun(InstByteRangeRun),
+    Opaque(InstOpaque),
+    AnyBytes(InstAnyBytes),
+    Repeat(InstRepeat),
+    Span(InstSpan),
     Split(InstSplit),
     // JoinLast,
     JoinGoto(InstPtr),
@@ -36,9 +44,13 @@ impl fmt::Debug for Inst {
         match self {
             Accept => write!(f, "Accept"),
             Uninit => write!(f, "Uninit"),
+            UninitRun(count) => write!(f, "UninitRun(count={})", count),
             Pointer(ref ptr) => {
-                write!(f, "Pointer(pointer_size={}, data_align={})",
-                    ptr.data_align, ptr.pointer_size)
+                write!(f, "Pointer(")?;
+                if ptr.private {
+                    write!(f, "private, ")?;
+                }
+                write!(f, "{}, count={})", ptr.range, ptr.count)
             }
             Ref(ref d_ref) => {
                 let ref_type = match &d_ref.ref_type {
@@ -66,6 +78,36 @@ impl fmt::Debug for Inst {
                 write!(f, "0x{:02x}-0x{:02x})",
                     range.range.start, range.range.end)
             }
+            ByteRangeRun(ref run) => {
+                write!(f, "ByteRangeRun(")?;
+                if run.private {
+                    write!(f, "private, ")?;
+                }
+                write!(f, "{}, count={})", run.range, run.count)
+            }
+            Opaque(ref opaque) => {
+                write!(f, "Opaque({:016x}, size={})", opaque.id, opaque.size)
+            }
+            AnyBytes(ref any) => {
+                write!(f, "AnyBytes(")?;
+                if any.provenance {
+                    write!(f, "provenance, ")?;
+                }
+                write!(f, "count={})", any.count)
+            }
+            Repeat(ref repeat) => {
+                write!(f, "Repeat(count={}, end={})", repeat.count, repeat.end)
+            }
+            Span(ref span) => {
+                write!(f, "Span(ty={}, ", span.ty)?;
+                if span.private {
+                    write!(f, "private, ")?;
+                }
+                if span.cell {
+                    write!(f, "cell, ")?;
+                }
+                write!(f, "size={}, end={})", span.size, span.end)
+            }
             Split(ref split) => {
                 write!(f, "Split(alt={})", split.alternate)
             }
@@ -92,20 +134,75 @@ impl Inst {
     pub fn new_invalid_goto() -> Self {
         Inst::JoinGoto(InstPtr::MAX)
     }
-    pub fn patch_split(&mut self, alternate: InstPtr) {
+    pub fn new_invalid_repeat(count: u32) -> Self {
+        Inst::Repeat(InstRepeat {
+            count,
+            end: InstPtr::MAX,
+        })
+    }
+    pub fn new_invalid_span(ty: u32, private: bool, cell: bool, size: usize) -> Self {
+        Inst::Span(InstSpan {
+            ty,
+            private,
+            cell,
+            size,
+            end: InstPtr::MAX,
+        })
+    }
+    pub fn patch_split(&mut self, alternate: InstPtr) -> Result<(), Error> {
         match self {
             Inst::Split(ref mut split) => {
                 split.alternate = alternate;
+                Ok(())
             }
-            _ => panic!("invalid use of patch_split")
+            _ => Err(Error::MalformedProgram(format!("patch_split on {:?}", self)))
         }
     }
-    pub fn patch_goto(&mut self, addr: InstPtr) {
+    pub fn patch_goto(&mut self, addr: InstPtr) -> Result<(), Error> {
         match self {
             Inst::JoinGoto(ref mut goto) => {
-                *goto = addr
+                *goto = addr;
+                Ok(())
+            }
+            _ => Err(Error::MalformedProgram(format!("patch_goto on {:?}", self)))
+        }
+    }
+    pub fn patch_span(&mut self, end: InstPtr) -> Result<(), Error> {
+        match self {
+            Inst::Span(ref mut span) => {
+                span.end = end;
+                Ok(())
+            }
+            _ => Err(Error::MalformedProgram(format!("patch_span on {:?}", self)))
+        }
+    }
+    pub fn patch_repeat(&mut self, end: InstPtr) -> Result<(), Error> {
+        match self {
+            Inst::Repeat(ref mut repeat) => {
+                repeat.end = end;
+                Ok(())
             }
-            _ => panic!("invalid use of patch_goto")
+            _ => Err(Error::MalformedProgram(format!("patch_repeat on {:?}", self)))
+        }
+    }
+    /// `count` bytes in `range`, as a single `ByteRange` if there is one.
+    pub fn byte_run(private: bool, range: RangeInclusive, count: u32) -> Self {
+        if count == 1 {
+            Inst::ByteRange(InstByteRange { private, range, alternate: None })
+        } else {
+            Inst::ByteRangeRun(InstByteRangeRun { private, range, count })
+        }
+    }
+    /// The number of bytes of a run, `None` for instructions that read a
+    /// single byte or none.
+    fn run_len(&self) -> Option<u32> {
+        match *self {
+            Inst::UninitRun(count) => Some(count),
+            Inst::ByteRangeRun(ref run) => Some(run.count),
+            Inst::Pointer(ref ptr) => Some(ptr.count),
+            Inst::Opaque(ref opaque) => Some(opaque.size),
+            Inst::AnyBytes(ref any) => Some(any.count),
+            _ => None,
         }
     }
 }
@@ -118,8 +215,15 @@ pub enum AcceptState {
     NeverReadPrivate,
     NeverWritePrivate,
     NeverTagMismatch,
+    /// dst is an integer and src a pointer, whose provenance would be lost.
+    NeverStripsProvenance,
     NeverOutOfRange(RangeInclusive, RangeInclusive),
     NeverUnreachable,
+    /// dst has no values, so no src value fits it.
+    NeverUninhabited,
+    /// Behind shared references, only one of dst and src is in an
+    /// `UnsafeCell`, so one side could mutate what the other assumes frozen.
+    NeverCellMismatch,
     MaybeCheckRange(RangeInclusive, RangeInclusive),
 }
 
@@ -129,11 +233,67 @@ impl AcceptState {
     }
 }
 
+impl fmt::Display for AcceptState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use AcceptState::*;
+        match self {
+            Always => write!(f, "always"),
+            NeverReadUninit => write!(f, "never: reads uninit"),
+            NeverReadPrivate => write!(f, "never: reads private"),
+            NeverWritePrivate => write!(f, "never: writes private"),
+            NeverTagMismatch => write!(f, "never: tag mismatch"),
+            NeverStripsProvenance => write!(f, "never: strips provenance"),
+            NeverOutOfRange(dst, src) => write!(f, "never: {} outside of {}", src, dst),
+            NeverUnreachable => write!(f, "never: unreachable"),
+            NeverUninhabited => write!(f, "never: dst is uninhabited"),
+            NeverCellMismatch => write!(f, "never: interior mutability differs"),
+            MaybeCheckRange(dst, src) => write!(f, "maybe: {} partly outside of {}", src, dst),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum StepByte {
     Uninit,
     Byte(bool, u8),
     ByteRange(bool, RangeInclusive),
+    /// A byte of a pointer, see `InstrPointer`.
+    Pointer(bool, RangeInclusive),
+    /// Byte `offset` of the opaque type `id`, see `InstOpaque`.
+    Opaque(u64, u32),
+    /// Any byte or uninit, and a pointer byte if it keeps provenance,
+    /// see `InstAnyBytes`.
+    Any(bool),
+}
+
+impl fmt::Display for StepByte {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let private = match self {
+            StepByte::Uninit => return write!(f, "uninit"),
+            StepByte::Byte(private, byte) => {
+                write!(f, "0x{:02x}", byte)?;
+                private
+            }
+            StepByte::ByteRange(private, range) => {
+                write!(f, "{}", range)?;
+                private
+            }
+            StepByte::Pointer(private, range) => {
+                write!(f, "pointer {}", range)?;
+                private
+            }
+            StepByte::Opaque(id, offset) => {
+                return write!(f, "opaque {:016x} byte {}", id, offset);
+            }
+            StepByte::Any(provenance) => {
+                return f.write_str(if *provenance { "any or pointer" } else { "any" });
+            }
+        };
+        if *private {
+            write!(f, " private")?;
+        }
+        Ok(())
+    }
 }
 
 
@@ -151,6 +311,12 @@ impl core::convert::From<core::ops::RangeInclusive<u8>> for RangeInclusive {
         }
     }
 }
+impl fmt::Display for RangeInclusive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:02x}-0x{:02x}", self.start, self.end)
+    }
+}
+
 impl RangeInclusive {
     pub fn contains_range(&self, small: RangeInclusive) -> bool {
         self.start <= small.start && self.end >= small.end
@@ -177,16 +343,44 @@ impl StepByte {
         match (self, source) {
             // Uninit bytes can accpet anything
             (Uninit, _) => Always,
-            // Nothing can accept uninit
+            // Only padding and union bytes can accept uninit
+            (&Any(_), Uninit) => Always,
             (_, Uninit) => NeverReadUninit,
+            // An opaque type can only be transmuted into itself
+            (&Opaque(a, a_offset), &Opaque(b, b_offset)) if a == b && a_offset == b_offset => {
+                Always
+            }
             // Cannot write private memory
-            (&ByteRange(true, _), _) | (&Byte(true, _), _) => {
+            (&ByteRange(true, _), _) | (&Byte(true, _), _) | (&Pointer(true, _), _)
+            | (&Opaque(..), _) => {
                 NeverWritePrivate
             }
             // Cannot read private memory
-            (_, &ByteRange(true, _)) | (_, &Byte(true, _)) => {
+            (_, &ByteRange(true, _)) | (_, &Byte(true, _)) | (_, &Pointer(true, _))
+            | (_, &Opaque(..)) => {
                 NeverReadPrivate
             }
+            // Union bytes hold anything public, pointers only if they keep
+            // their provenance, and may be uninit when read
+            (&Any(dst), &Any(src)) => {
+                if src && !dst { NeverStripsProvenance } else { Always }
+            }
+            (&Any(provenance), &Pointer(false, _)) => {
+                if provenance { Always } else { NeverStripsProvenance }
+            }
+            (&Any(_), _) => Always,
+            (_, &Any(_)) => NeverReadUninit,
+            // Integers don't carry the provenance of a pointer, the other
+            // way around makes a pointer without provenance
+            (&Byte(false, _), &Pointer(false, _)) | (&ByteRange(false, _), &Pointer(false, _)) => {
+                NeverStripsProvenance
+            }
+            (&Pointer(false, a), &Pointer(false, b)) | (&Pointer(false, a), &ByteRange(false, b)) => {
+                accept_range(a, b)
+            }
+            (&Pointer(false, range), &Byte(false, byte)) => {
+                accept_range(range, (byte..=byte).into())
+            }
             // Constant tags must match
             (&Byte(false, a), &Byte(false, b)) => {
                 if a != b {
@@ -213,56 +407,199 @@ impl StepByte {
 pub struct ProgFork {
     ip: InstPtr,
     pos: usize,
+    run: u32,
+    repeats: Vec<RepeatState>,
+}
+
+/// A `Repeat` the program is in: the `Repeat` instruction, the end of its
+/// body, and how many more times the body runs after this one.
+#[derive(Clone, PartialEq, Eq)]
+struct RepeatState {
+    start: InstPtr,
+    end: InstPtr,
+    left: u32,
+}
+
+/// Enters the `Repeat` at `ip`, or skips it if it runs zero times.
+fn enter_repeat(ip: &mut InstPtr, repeat: &InstRepeat, repeats: &mut Vec<RepeatState>) {
+    if repeat.count == 0 {
+        *ip = repeat.end;
+    } else {
+        repeats.push(RepeatState { start: *ip, end: repeat.end, left: repeat.count - 1 });
+        *ip += 1;
+    }
+}
+
+/// At the end of the innermost `Repeat` body, moves `ip` back to its
+/// start, or past the `Repeat` once the body ran `count` times.
+/// Returns whether `ip` was at the end.
+fn leave_repeat(ip: &mut InstPtr, repeats: &mut Vec<RepeatState>) -> bool {
+    match repeats.last_mut() {
+        Some(state) if state.end == *ip => {
+            if state.left > 0 {
+                state.left -= 1;
+                *ip = state.start + 1;
+            } else {
+                repeats.pop();
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Bytes `ProgCursor::peel` gave instructions of their own at `start`, the
+/// `Repeat`s they were read in and how many there are.
+struct Peel {
+    repeats: Vec<RepeatState>,
+    start: InstPtr,
+    len: u32,
+}
+
+/// The alternate an instruction was copied onto, see
+/// `ProgCursor::alternate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Alternate {
+    /// none, it reads the values its bytes were narrowed to
+    Narrowed,
+    /// one `synthetic_fork` split off a byte, holding the values the byte
+    /// was narrowed by
+    Byte,
+    /// the one `narrow_run` split off at the given instruction, or one split
+    /// off in it: it holds the values of the run other paths narrow again
+    Run(InstPtr),
 }
 
 pub enum LayoutStep {
     Byte {
         ip: InstPtr,
         pos: usize,
-        byte: StepByte
+        byte: StepByte,
+        /// Bytes left of the run at `ip`, this one included, which all
+        /// read as `byte`. 1 outside of runs.
+        run: u32,
     },
     Fork(ProgFork),
 }
 
-pub struct Program {
-    pub insts: Vec<Inst>,
-    ip: InstPtr,
-    pos: usize,
-    name: &'static str,
-    took_fork: Option<InstPtr>,
-    current: Option<LayoutStep>,
+/// One way through a program: the instructions whose alternate was taken
+/// to get there, and the bytes produced on the way.
+#[derive(Clone)]
+pub struct ProgPath {
+    pub alternates: Vec<InstPtr>,
+    pub bytes: Vec<(InstPtr, StepByte)>,
 }
 
-// impl Clone for Program {
-//     fn clone(&self) -> Self {
-//         Self {
-//             current: self.current.clone(),
-//             ..*self
-//         }
-//     }
-// }
+/// A compiled type. It doesn't change once compiled, so it can be shared
+/// by any number of checks, each walking it with a `ProgCursor`.
+pub struct Program {
+    insts: Vec<Inst>,
+    /// The types of the spans, see `InstSpan::ty`.
+    types: Vec<Ty>,
+    name: String,
+    /// Whether each instruction is inside an `UnsafeCell`, see
+    /// `ProgCursor::in_cell`.
+    cells: Vec<bool>,
+    /// Whether each instruction is in a `Repeat` body, see
+    /// `ProgCursor::is_repeated`.
+    repeated: Vec<bool>,
+}
 
 impl Program {
-    pub fn new(insts: Vec<Inst>, name: &'static str) -> Self {
+    pub fn new(insts: Vec<Inst>, types: Vec<Ty>, name: impl Into<String>) -> Self {
         Self {
+            cells: cell_coverage(&insts),
+            repeated: repeat_coverage(&insts, 0),
             insts,
+            types,
+            name: name.into(),
+        }
+    }
+    /// Starts a walk through the program.
+    pub fn cursor(&self) -> ProgCursor<'_> {
+        ProgCursor {
+            program: self,
+            added: Vec::new(),
+            added_cells: Vec::new(),
+            added_repeated: Vec::new(),
+            patched: HashMap::new(),
+            origins: Vec::new(),
+            alternates: Vec::new(),
             ip: 0,
             pos: 0,
-            name,
+            run: 0,
+            repeats: Vec::new(),
             took_fork: None,
             current: None,
+            step_ip: 0,
+            step_pos: 0,
+            step_run: 0,
+            step_repeats: Vec::new(),
+            read_run: 0,
+            read_repeats: Vec::new(),
+            peels: BTreeMap::new(),
+            split_off: Vec::new(),
         }
     }
-    fn positions(&self) -> Vec<usize> {
-        let mut positions = (0..self.insts.len()).map(|_| 0_usize).collect::<Vec<_>>();
-
+    pub fn insts(&self) -> &[Inst] {
+        &self.insts
+    }
+    pub fn types(&self) -> &[Ty] {
+        &self.types
+    }
+    /// The same program under another name.
+    pub fn renamed(self, name: impl Into<String>) -> Self {
+        Self { name: name.into(), ..self }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// A program without instructions has no path to `Accept`, it is
+    /// what uninhabited types compile to.
+    pub fn is_uninhabited(&self) -> bool {
+        self.insts.is_empty()
+    }
+    /// Checks that every jump target and span type is in bounds, every
+    /// `Repeat` body ends after it and the program ends with `Accept` or is
+    /// empty, which is all the interpreter relies on for loaded programs.
+    pub fn is_well_formed(insts: &[Inst], types: &[Ty]) -> bool {
+        let len = insts.len() as u64;
+        let in_bounds = |ip: InstPtr| (ip as u64) < len;
+        let valid = insts.iter().enumerate().all(|(ip, inst)| match inst {
+            Inst::ByteRange(range) => range.alternate.is_none_or(in_bounds),
+            Inst::Repeat(repeat) => in_bounds(repeat.end) && repeat.end as usize > ip,
+            Inst::Span(span) => in_bounds(span.end) && (span.ty as usize) < types.len(),
+            Inst::Split(split) => in_bounds(split.alternate),
+            &Inst::JoinGoto(addr) => in_bounds(addr),
+            _ => true,
+        });
+        valid && matches!(insts.last(), None | Some(Inst::Accept))
+    }
+    /// The position of the first byte each instruction reads. A `Repeat`
+    /// body is placed at its first iteration and runs step over all of
+    /// their bytes, so what follows them is at its position in the type.
+    pub fn positions(&self) -> Vec<usize> {
+        let mut positions = vec![0; self.insts.len()];
+        if self.is_uninhabited() {
+            return positions;
+        }
         let mut pos = 0;
         let mut ip = 0;
         let mut to_visit = Vec::<(InstPtr, usize)>::new();
         let mut stack = Vec::new();
+        // the end, start position and count of each `Repeat` entered
+        let mut repeats = Vec::<(InstPtr, usize, u32)>::new();
         loop {
+            while let Some(&(end, start, count)) = repeats.last() {
+                if end != ip {
+                    break;
+                }
+                pos = start + (pos - start) * count as usize;
+                repeats.pop();
+            }
             positions[ip as usize] = pos;
-            match &self.insts[ip as usize] {
+            let inst = &self.insts[ip as usize];
+            match inst {
                 Inst::Accept => {
                     if let Some((oip, opos)) = to_visit.pop() {
                         pos = opos;
@@ -276,7 +613,7 @@ impl Program {
                     stack.push(pos);
                 }
                 Inst::JoinGoto(_) => {
-                    pos = stack.pop().expect("invalid state");
+                    pos = stack.pop().unwrap_or(pos);
                 }
                 Inst::Uninit | Inst::Byte(_) => {
                     pos += 1;
@@ -287,68 +624,269 @@ impl Program {
                     }
                     pos += 1;
                 }
-                _ => {}
+                Inst::Repeat(repeat) if repeat.count == 0 => {
+                    ip = repeat.end;
+                    continue;
+                }
+                Inst::Repeat(repeat) => {
+                    repeats.push((repeat.end, pos, repeat.count));
+                }
+                _ => {
+                    pos += inst.run_len().unwrap_or(0) as usize;
+                }
             }
             ip += 1;
         }
         positions
     }
+    /// Writes the instructions as nodes of a Graphviz digraph, labeled with
+    /// their positions, and the ways to go on from each as edges.
+    #[allow(dead_code)]
     pub fn print_dot<W: fmt::Write>(&self, dst: &mut W) -> fmt::Result {
-        let name = self.name;
+        let name = &self.name;
         let positions = self.positions();
 
-        write!(dst, "  {}_accepting [shape=rectangle, label=\"accepting {}\"];\n", name, name)?;
+        writeln!(dst, "  \"{}_accepting\" [shape=rectangle, label=\"accepting {}\"];", name, name)?;
         for (ip, inst) in self.insts.iter().enumerate() {
             let ip = ip as InstPtr;
             let pos = positions[ip as usize];
 
-            write!(dst, "  {}_ip_{} [shape=ellipse, label=\"pos={}, ip{}\"];\n", name, ip, pos, ip)?;
-            match inst {
+            writeln!(dst, "  \"{}_ip_{}\" [shape=ellipse, label=\"pos={}, ip{}\"];", name, ip, pos, ip)?;
+            let label = match inst {
                 Inst::Accept => {
-                    write!(dst, "  {}_ip_{} -> {}_accepting;\n",
-                        name, ip, name)?;
-                }
-                // Inst::Join => {
-                //     write!(dst, "  {}_ip_{} -> {}_ip_{};\n",
-                //         name, ip, name, ip + 1)?;
-                // }
-                Inst::Uninit => {
-                    write!(dst, "  {}_ip_{} -> {}_ip_{} [label=\"uninit\"];\n",
-                        name, ip, name, ip + 1)?;
-                }
-                Inst::Byte(byte) => {
-                    write!(dst, "  {}_ip_{} -> {}_ip_{} [label=\"byte=0x{:02x}\"];\n",
-                        name, ip, name, ip + 1, byte.byte)?;
+                    writeln!(dst, "  \"{}_ip_{}\" -> \"{}_accepting\";", name, ip, name)?;
+                    continue;
                 }
+                Inst::Uninit => "uninit".to_string(),
+                Inst::UninitRun(count) => format!("uninit x{}", count),
+                Inst::Byte(byte) => format!("byte=0x{:02x}", byte.byte),
                 Inst::ByteRange(range) => {
-                    write!(dst, "  {}_ip_{} -> {}_ip_{} [label=\"range=0x{:02x}-0x{:02x}\"];\n",
-                        name, ip, name, ip + 1, range.range.start, range.range.end)?;
                     if let Some(alt) = range.alternate {
-                        write!(dst, "  {}_ip_{} -> {}_ip_{} [label=\"fork\"];\n",
+                        writeln!(dst, "  \"{}_ip_{}\" -> \"{}_ip_{}\" [label=\"fork\"];",
                             name, ip, name, alt)?;
                     }
+                    format!("range={}", range.range)
+                }
+                Inst::ByteRangeRun(run) => format!("range={} x{}", run.range, run.count),
+                Inst::Pointer(ptr) => format!("pointer={} x{}", ptr.range, ptr.count),
+                Inst::Ref(_) => "ref".to_string(),
+                Inst::Opaque(opaque) => format!("opaque={:016x} x{}", opaque.id, opaque.size),
+                Inst::AnyBytes(any) => format!("any x{}", any.count),
+                Inst::Repeat(repeat) => {
+                    writeln!(dst, "  \"{}_ip_{}\" -> \"{}_ip_{}\" [label=\"done\"];",
+                        name, ip, name, repeat.end)?;
+                    format!("repeat x{}", repeat.count)
                 }
+                Inst::Span(span) => format!("span ty{}", span.ty),
                 Inst::Split(split) => {
-                    write!(dst, "  {}_ip_{} -> {}_ip_{};\n",
+                    writeln!(dst, "  \"{}_ip_{}\" -> \"{}_ip_{}\";",
                         name, ip, name, split.alternate)?;
-                    write!(dst, "  {}_ip_{} -> {}_ip_{};\n",
+                    writeln!(dst, "  \"{}_ip_{}\" -> \"{}_ip_{}\";",
                         name, ip, name, ip + 1)?;
+                    continue;
                 }
                 Inst::JoinGoto(addr) => {
-                    write!(dst, "  {}_ip_{} -> {}_ip_{} [label=\"goto\"];\n",
+                    writeln!(dst, "  \"{}_ip_{}\" -> \"{}_ip_{}\" [label=\"goto\"];",
                         name, ip, name, addr)?;
+                    continue;
                 }
-                _ => { unimplemented!() }
-            }
+            };
+            writeln!(dst, "  \"{}_ip_{}\" -> \"{}_ip_{}\" [label=\"{}\"];",
+                name, ip, name, ip + 1, label)?;
         }
         Ok(())
     }
+    /// Lists up to `limit` paths through the program, ordered by the
+    /// alternates taken. There can be exponentially many of them.
+    pub fn paths(&self, limit: usize) -> Vec<ProgPath> {
+        let mut paths = Vec::new();
+        if self.is_uninhabited() {
+            return paths;
+        }
+        let start = ProgPath { alternates: Vec::new(), bytes: Vec::new() };
+        let mut pending = vec![(0, start, Vec::new())];
+        while paths.len() < limit {
+            let (mut ip, mut path, mut repeats) = match pending.pop() {
+                Some(pending) => pending,
+                None => break,
+            };
+            loop {
+                if leave_repeat(&mut ip, &mut repeats) {
+                    continue;
+                }
+                let inst = &self.insts[ip as usize];
+                let alternate = match inst {
+                    Inst::ByteRange(range) => range.alternate,
+                    Inst::Split(split) => Some(split.alternate),
+                    _ => None,
+                };
+                if let Some(alternate) = alternate {
+                    let mut other = path.clone();
+                    other.alternates.push(ip);
+                    pending.push((alternate, other, repeats.clone()));
+                }
+                match inst {
+                    Inst::Accept => break,
+                    Inst::Uninit => path.bytes.push((ip, StepByte::Uninit)),
+                    &Inst::UninitRun(count) => {
+                        path.bytes.extend((0..count).map(|_| (ip, StepByte::Uninit)));
+                    }
+                    Inst::Byte(byte) => {
+                        path.bytes.push((ip, StepByte::Byte(byte.private, byte.byte)));
+                    }
+                    Inst::ByteRange(range) => {
+                        path.bytes.push((ip, StepByte::ByteRange(range.private, range.range)));
+                    }
+                    Inst::ByteRangeRun(run) => {
+                        path.bytes.extend((0..run.count)
+                            .map(|_| (ip, StepByte::ByteRange(run.private, run.range))));
+                    }
+                    Inst::Pointer(ptr) => {
+                        path.bytes.extend((0..ptr.count)
+                            .map(|_| (ip, StepByte::Pointer(ptr.private, ptr.range))));
+                    }
+                    Inst::Opaque(opaque) => {
+                        path.bytes.extend((0..opaque.size)
+                            .map(|offset| (ip, StepByte::Opaque(opaque.id, offset))));
+                    }
+                    Inst::AnyBytes(any) => {
+                        path.bytes.extend((0..any.count)
+                            .map(|_| (ip, StepByte::Any(any.provenance))));
+                    }
+                    Inst::Repeat(repeat) => {
+                        enter_repeat(&mut ip, repeat, &mut repeats);
+                        continue;
+                    }
+                    &Inst::JoinGoto(addr) => {
+                        ip = addr;
+                        continue;
+                    }
+                    Inst::Span(_) | Inst::Split(_) | Inst::Ref(_) => {}
+                }
+                ip += 1;
+            }
+            paths.push(path);
+        }
+        paths.sort_by(|a, b| a.alternates.cmp(&b.alternates));
+        paths
+    }
     pub fn accept_state(&self, start: usize) -> impl Iterator<Item=AcceptState> + '_ {
-        self.insts[start..].iter().map(|inst| match inst {
-            Inst::Split(_) | Inst::JoinGoto(_) | Inst::Accept =>
-                AcceptState::Always,
-            _ => AcceptState::NeverUnreachable,
-        })
+        accept_state(&self.insts, start)
+    }
+}
+
+/// Where a walk through a `Program` is. `synthetic_fork` and `peel` add
+/// instructions as the check goes, which the cursor keeps to itself: they
+/// are numbered on from the program's and the few compiled instructions
+/// changed are patched over, the program itself stays borrowed.
+pub struct ProgCursor<'p> {
+    program: &'p Program,
+    /// The instructions added, from `program.insts().len()` on.
+    added: Vec<Inst>,
+    /// `in_cell` and `is_repeated` of the instructions added.
+    added_cells: Vec<bool>,
+    added_repeated: Vec<bool>,
+    /// The compiled instructions changed, by their ip.
+    patched: HashMap<InstPtr, Inst>,
+    /// The compiled instruction each one added is a copy of, see `origin`.
+    origins: Vec<InstPtr>,
+    /// The alternate each one added was copied onto, see `alternate`.
+    alternates: Vec<Alternate>,
+    ip: InstPtr,
+    pos: usize,
+    /// bytes of the run at `ip` already read
+    run: u32,
+    /// The `Repeat`s `ip` is in, innermost last.
+    repeats: Vec<RepeatState>,
+    took_fork: Option<InstPtr>,
+    current: Option<LayoutStep>,
+    /// Where the search for `current` started, see `spans`.
+    step_ip: InstPtr,
+    step_pos: usize,
+    step_run: u32,
+    step_repeats: Vec<RepeatState>,
+    /// Where the last byte was read, see `peel`.
+    read_run: u32,
+    read_repeats: Vec<RepeatState>,
+    /// The bytes given an instruction of their own by their instruction
+    /// and offset in its run, see `peel`.
+    peels: BTreeMap<(InstPtr, u32), Vec<Peel>>,
+    /// The alternates `synthetic_fork` split off, see `is_split_off`.
+    split_off: Vec<InstPtr>,
+}
+
+impl<'p> ProgCursor<'p> {
+    pub fn name(&self) -> &str {
+        self.program.name()
+    }
+    /// The program walked through.
+    pub fn program(&self) -> &'p Program {
+        self.program
+    }
+    /// The number of the program's instructions and the ones added since.
+    pub fn inst_count(&self) -> usize {
+        self.program.insts.len() + self.added.len()
+    }
+    /// The instruction at `ip`, as patched or added since.
+    pub fn get(&self, ip: InstPtr) -> Option<&Inst> {
+        inst_at(self.program, &self.added, &self.patched, ip)
+    }
+    /// Like `get`, for an `ip` the cursor got to.
+    pub fn inst(&self, ip: InstPtr) -> &Inst {
+        self.get(ip).expect("instruction pointer out of range")
+    }
+    fn inst_mut(&mut self, ip: InstPtr) -> &mut Inst {
+        match (ip as usize).checked_sub(self.program.insts.len()) {
+            Some(added) => &mut self.added[added],
+            None => self.patched.entry(ip)
+                .or_insert_with(|| self.program.insts[ip as usize].clone()),
+        }
+    }
+    /// `accept_state` of the instructions added from `start` on.
+    fn added_accept_state(&self, start: InstPtr) -> impl Iterator<Item=AcceptState> + '_ {
+        accept_state(&self.added, start as usize - self.program.insts.len())
+    }
+    pub fn is_uninhabited(&self) -> bool {
+        self.program.is_uninhabited()
+    }
+    /// Whether the byte at `ip` is interior mutable, i.e. part of an
+    /// `UnsafeCell`. Padding and uninit bytes of a cell are too.
+    pub fn in_cell(&self, ip: InstPtr) -> bool {
+        match (ip as usize).checked_sub(self.program.insts.len()) {
+            Some(added) => self.added_cells.get(added).copied().unwrap_or(false),
+            None => self.program.cells[ip as usize],
+        }
+    }
+    /// Whether the instruction at `ip` is in a `Repeat` body, where it
+    /// stands for a byte at more than one position.
+    pub fn is_repeated(&self, ip: InstPtr) -> bool {
+        match (ip as usize).checked_sub(self.program.insts.len()) {
+            Some(added) => self.added_repeated.get(added).copied().unwrap_or(false),
+            None => self.program.repeated[ip as usize],
+        }
+    }
+    /// The compiled instruction `ip` is a copy of, or `ip` itself. From a
+    /// copy on, the program reads what it would from its origin, but for
+    /// the alternates `synthetic_fork` split off, which are their own.
+    pub fn origin(&self, ip: InstPtr) -> InstPtr {
+        let added = (ip as usize).checked_sub(self.program.insts.len());
+        added.and_then(|added| self.origins.get(added).copied()).unwrap_or(ip)
+    }
+    /// The alternate `ip` was copied onto, or from. A copy there only
+    /// reads like its origin from where the alternate starts, the bytes
+    /// before it hold other values than on the paths they were narrowed on.
+    pub fn alternate(&self, ip: InstPtr) -> Alternate {
+        let added = (ip as usize).checked_sub(self.program.insts.len());
+        added.and_then(|added| self.alternates.get(added).copied()).unwrap_or(Alternate::Narrowed)
+    }
+    /// Whether `ip` is an alternate `synthetic_fork` split off a src byte,
+    /// holding the values the byte was narrowed by.
+    pub fn is_split_off(&self, ip: InstPtr) -> bool {
+        self.split_off.contains(&ip)
+    }
+    /// The position of the next byte.
+    pub fn position(&self) -> usize {
+        if self.current.is_some() { self.step_pos } else { self.pos }
     }
     pub fn synthetic_fork(&mut self, ip: Option<InstPtr>,
         accepts: AcceptState, marks: &mut Vec<AcceptState>
@@ -365,7 +903,7 @@ impl Program {
             Some(ip) => ip,
             _ => { return (original, None); }
         };
-        let mut previous = match &self.insts[ip as usize] {
+        let mut previous = match self.inst(ip) {
             Inst::ByteRange(range) => range.clone(),
             _ => { return (original, None); }
         };
@@ -373,38 +911,174 @@ impl Program {
             let missing_range = (src.start..=(dst.start - 1)).into();
             let location = self.copy_fork(ip);
             let alternate = previous.alternate.replace(location);
-            self.insts[location as usize] = Inst::ByteRange(InstByteRange {
+            *self.inst_mut(location) = Inst::ByteRange(InstByteRange {
                 private: previous.private,
                 range: missing_range,
                 alternate,
             });
-            marks.extend(self.accept_state(location as usize));
+            self.mark_split_off(ip, location, false);
+            marks.extend(self.added_accept_state(location));
         }
         if src.end > dst.end {
             let missing_range = ((dst.end + 1)..=src.end).into();
             let location = self.copy_fork(ip);
             let alternate = previous.alternate.replace(location);
-            self.insts[location as usize] = Inst::ByteRange(InstByteRange {
+            *self.inst_mut(location) = Inst::ByteRange(InstByteRange {
                 private: previous.private,
                 range: missing_range,
                 alternate,
             });
-            marks.extend(self.accept_state(location as usize));
+            self.mark_split_off(ip, location, false);
+            marks.extend(self.added_accept_state(location));
         }
+        self.cover_copies();
         previous.range = dst;
-        let fork = previous.alternate.map(|ip| ProgFork { ip, pos: self.pos });
-        self.insts[ip as usize] = Inst::ByteRange(previous);
+        let fork = previous.alternate.map(|ip| ProgFork {
+            ip,
+            pos: self.pos,
+            run: 0,
+            repeats: self.read_repeats.clone(),
+        });
+        *self.inst_mut(ip) = Inst::ByteRange(previous);
         // println!("after synthetic_fork: {:?}", self);
         (AcceptState::Always, fork)
     }
+    /// Narrows the `len` bytes `peel` gave instructions of their own at `ip`
+    /// to `dst` as a whole, rather than one alternate per byte. Their values
+    /// are split off into a single alternate, which holds the values in
+    /// `dst` too: dst rejects it at this position, other paths through `ip`
+    /// check it for themselves. Returns the alternate.
+    pub fn narrow_run(&mut self, ip: InstPtr, len: u32,
+        dst: RangeInclusive, marks: &mut Vec<AcceptState>
+    ) -> Option<InstPtr> {
+        let mut first = match *self.inst(ip) {
+            Inst::ByteRange(ref range) if len > 1 && range.alternate.is_none() => range.clone(),
+            _ => return None,
+        };
+        let location = self.copy_fork(ip);
+        self.mark_split_off(ip, location, true);
+        marks.extend(self.added_accept_state(location));
+        self.cover_copies();
+        first.range = dst;
+        first.alternate = Some(location);
+        *self.inst_mut(ip) = Inst::ByteRange(first);
+        match self.inst_mut(ip + 1) {
+            Inst::ByteRange(rest) => rest.range = dst,
+            Inst::ByteRangeRun(rest) => rest.range = dst,
+            _ => unreachable!("peel puts the rest of the bytes after the first"),
+        }
+        Some(location)
+    }
+    /// Gives the byte just read at `ip`, which stands for other positions
+    /// too, an instruction of its own and continues after it, so
+    /// `synthetic_fork` can narrow it at this position only. Reading the
+    /// byte at the same position again goes through its instruction.
+    /// The `len - 1` bytes of the run stepped through after it go in one
+    /// more instruction, so a run is narrowed as a whole.
+    ///
+    /// A run outside of `Repeat` bodies is split: the byte is followed by
+    /// the rest of the run and a goto back to the program. In a body, the
+    /// rest of the program is copied, as the body runs again after it.
+    pub fn peel(&mut self, ip: InstPtr, len: u32, marks: &mut Vec<AcceptState>) -> InstPtr {
+        let (private, range) = match *self.inst(ip) {
+            Inst::ByteRange(ref range) if self.is_repeated(ip) => (range.private, range.range),
+            Inst::ByteRangeRun(ref run) => (run.private, run.range),
+            _ => return ip,
+        };
+        let start = self.inst_count();
+        self.peels.entry((ip, self.read_run)).or_default().push(Peel {
+            repeats: self.read_repeats.clone(),
+            start: start as InstPtr,
+            len,
+        });
+        self.push_copy(Inst::ByteRange(InstByteRange { private, range, alternate: None }), ip);
+        if len > 1 {
+            self.push_copy(Inst::byte_run(private, range, len - 1), ip);
+        }
+        let after = self.inst_count() as InstPtr;
+        let mut from = self.ip;
+        if self.run > 0 {
+            let rest = match *self.inst(from) {
+                Inst::ByteRangeRun(ref run) => Inst::byte_run(private, range, run.count - self.run),
+                _ => unreachable!("only runs are read partly"),
+            };
+            self.push_copy(rest, from);
+            from += 1;
+        }
+        if self.repeats.is_empty() {
+            // straight to the program, not through the runs split before
+            while let Inst::JoinGoto(addr) = *self.inst(from) {
+                from = addr;
+            }
+            self.push_copy(Inst::JoinGoto(from), ip);
+        } else {
+            // the rest of each body, innermost first, then the runs left of it
+            for state in core::mem::take(&mut self.repeats).into_iter().rev() {
+                self.copy_until(from, Some(state.end));
+                if state.left > 0 {
+                    let repeat = self.inst_count() as InstPtr;
+                    self.push_copy(Inst::new_invalid_repeat(state.left), state.start);
+                    self.copy_until(state.start + 1, Some(state.end));
+                    let end = self.inst_count() as InstPtr;
+                    self.inst_mut(repeat).patch_repeat(end).expect("just pushed a repeat");
+                }
+                from = state.end;
+            }
+            self.copy_until(from, None);
+        }
+        self.cover_copies();
+        marks.extend(self.added_accept_state(start as InstPtr));
+        self.ip = after;
+        self.run = 0;
+        start as InstPtr
+    }
+    /// Pushes `inst`, a copy of the instruction at `from`.
+    fn push_copy(&mut self, inst: Inst, from: InstPtr) {
+        let (cell, origin) = (self.in_cell(from), self.origin(from));
+        let alternate = self.alternate(from);
+        self.added.push(inst);
+        self.added_cells.push(cell);
+        self.origins.push(origin);
+        self.alternates.push(alternate);
+    }
+    /// Marks the copies `peel` and `synthetic_fork` made that are in
+    /// `Repeat` bodies. The copies themselves never start in one.
+    fn cover_copies(&mut self) {
+        let covered = self.added_repeated.len();
+        let first = (self.program.insts.len() + covered) as InstPtr;
+        self.added_repeated.extend(repeat_coverage(&self.added[covered..], first));
+    }
+    /// Records the copy at `location` of the byte at `ip` as an alternate
+    /// split off, which is its own origin, with the instructions copied
+    /// along. A run's alternate, and those split off in it, are each told
+    /// apart, see `Alternate::Run`.
+    fn mark_split_off(&mut self, ip: InstPtr, location: InstPtr, run: bool) {
+        let alternate = match self.alternate(ip) {
+            _ if run => Alternate::Run(location),
+            Alternate::Run(_) => Alternate::Run(location),
+            _ => Alternate::Byte,
+        };
+        self.split_off.push(location);
+        let added = location as usize - self.program.insts.len();
+        self.origins[added] = location;
+        self.alternates[added..].fill(alternate);
+    }
     fn copy_fork(&mut self, start: InstPtr) -> InstPtr {
+        self.copy_until(start, None)
+    }
+    /// Copies the instructions from `start` up to `stop`, or to `Accept`,
+    /// leaving the alternations `start` is in by their gotos.
+    fn copy_until(&mut self, start: InstPtr, stop: Option<InstPtr>) -> InstPtr {
         let mut depth = 0;
-        let dst = self.insts.len();
+        let dst = self.inst_count();
         let mut pos = start as usize;
         let mut offset = (dst - pos) as InstPtr;
         let mut more_forks = Vec::new();
         loop {
-            let mut inst = self.insts[pos].clone();
+            if depth == 0 && stop == Some(pos as InstPtr) {
+                break;
+            }
+            let mut inst = self.inst(pos as InstPtr).clone();
             match &mut inst {
                 Inst::Split(ref mut split) => {
                     depth += 1;
@@ -413,7 +1087,7 @@ impl Program {
                 Inst::JoinGoto(ref mut goto) => {
                     if depth == 0 {
                         pos = *goto as usize;
-                        offset = (self.insts.len() - pos) as InstPtr;
+                        offset = (self.inst_count() - pos) as InstPtr;
                         continue;
                     }
                     depth -= 1;
@@ -421,22 +1095,29 @@ impl Program {
                 }
                 Inst::ByteRange(ref range) => {
                     if let Some(alt) = range.alternate {
-                        more_forks.push((pos, alt));
+                        more_forks.push((self.inst_count() as InstPtr, alt));
                     }
                 }
+                Inst::Span(ref mut span) => {
+                    span.end += offset;
+                }
+                Inst::Repeat(ref mut repeat) => {
+                    repeat.end += offset;
+                }
                 Inst::Accept => {
-                    self.insts.push(inst);
+                    self.push_copy(inst, pos as InstPtr);
                     break;
                 }
                 _ => {  }
             }
-            self.insts.push(inst);
+            // the cell spans around `start` aren't copied, their coverage is
+            self.push_copy(inst, pos as InstPtr);
             pos += 1;
         }
         for (pos, alt) in more_forks {
             let dst = self.copy_fork(alt);
-            match self.insts[pos] {
-                Inst::ByteRange(ref mut range) => {
+            match self.inst_mut(pos) {
+                Inst::ByteRange(range) => {
                     range.alternate = Some(dst);
                 }
                 _ => unreachable!("we should point to ByteRange")
@@ -444,14 +1125,93 @@ impl Program {
         }
         dst as InstPtr
     }
+    /// Spans starting at the current position and where they start,
+    /// outermost first.
+    pub fn spans(&self) -> Vec<(InstPtr, InstSpan)> {
+        self.span_walk().into_iter().map(|(ip, span, _)| (ip, span)).collect()
+    }
+    /// Like `spans`, with the `Repeat`s each one is in.
+    fn span_walk(&self) -> Vec<(InstPtr, InstSpan, Vec<RepeatState>)> {
+        let (mut ip, run, repeats) = if self.current.is_some() {
+            (self.step_ip, self.step_run, &self.step_repeats)
+        } else {
+            (self.ip, self.run, &self.repeats)
+        };
+        let mut spans = Vec::new();
+        // no value starts in the middle of a run
+        if run > 0 {
+            return spans;
+        }
+        let mut repeats = repeats.clone();
+        loop {
+            if leave_repeat(&mut ip, &mut repeats) {
+                continue;
+            }
+            match self.get(ip) {
+                Some(Inst::Span(span)) => spans.push((ip, span.clone(), repeats.clone())),
+                Some(&Inst::JoinGoto(addr)) => {
+                    ip = addr;
+                    continue;
+                }
+                Some(Inst::Repeat(repeat)) => {
+                    enter_repeat(&mut ip, repeat, &mut repeats);
+                    continue;
+                }
+                _ => break,
+            }
+            ip += 1;
+        }
+        spans
+    }
+    /// Moves past `span`, one of `spans` starting at `start`, without
+    /// stepping through it.
+    pub fn skip_span(&mut self, start: InstPtr, span: &InstSpan) {
+        let repeats = self.span_walk().into_iter()
+            .find(|(ip, ..)| *ip == start)
+            .map(|(.., repeats)| repeats)
+            .unwrap_or_default();
+        if self.current.take().is_some() {
+            self.took_fork = None;
+            self.pos = self.step_pos;
+        }
+        self.repeats = repeats;
+        self.ip = span.end;
+        self.pos += span.size;
+        self.run = 0;
+    }
+    /// Moves past `count` more bytes of the run the last byte was read
+    /// from, at most the rest of it, see `LayoutStep::Byte::run`.
+    pub fn skip_run(&mut self, count: u32) {
+        // a run read to its end already left it
+        if count == 0 || self.run == 0 {
+            return;
+        }
+        let len = match self.get(self.ip).and_then(Inst::run_len) {
+            Some(len) => len,
+            None => return,
+        };
+        let count = count.min(len - self.run);
+        self.run += count;
+        self.pos += count as usize;
+        if self.run >= len {
+            self.run = 0;
+            self.ip += 1;
+        }
+    }
+    /// The type of the value `span` marks.
+    pub fn span_ty(&self, span: &InstSpan) -> &Ty {
+        &self.program.types[span.ty as usize]
+    }
     pub fn save_fork(&self) -> ProgFork {
         // println!("{} save fork ip={} pos={}", self.name, self.ip, self.pos);
-        ProgFork { ip: self.ip, pos: self.pos }
+        ProgFork { ip: self.ip, pos: self.pos, run: self.run, repeats: self.repeats.clone() }
     }
     pub fn restore_fork(&mut self, fork: ProgFork) {
         // println!("{} restore fork ip={} pos={}", self.name, fork.ip, fork.pos);
         self.ip = fork.ip;
         self.pos = fork.pos;
+        self.run = fork.run;
+        self.repeats = fork.repeats;
     }
     pub fn next_fork(&mut self) -> Option<ProgFork> {
         if self.current.is_none() {
@@ -474,9 +1234,23 @@ impl Program {
     // (1 2|4 5)7
     // pub fn split_byte(&mut self) -> Option<>
     fn advance(&mut self) {
-        while let Some(inst) = self.insts.get(self.ip as usize) {
+        self.step_ip = self.ip;
+        self.step_pos = self.pos;
+        self.step_run = self.run;
+        self.step_repeats.clone_from(&self.repeats);
+        loop {
+            if leave_repeat(&mut self.ip, &mut self.repeats) || self.enter_peel() {
+                continue;
+            }
+            // not `get`, which would borrow all of `self`
+            let inst = match inst_at(self.program, &self.added, &self.patched, self.ip) {
+                Some(inst) => inst,
+                None => return,
+            };
             // print!("{} ip={} inst={:?} ", self.name, self.ip, inst);
             // println!("stack={:?}", self.stack);
+            self.read_run = self.run;
+            self.read_repeats.clone_from(&self.repeats);
             let rv = match inst {
                 Inst::Accept => {
                     self.current = None;
@@ -488,13 +1262,16 @@ impl Program {
                             Some(LayoutStep::Byte {
                                 ip: self.ip,
                                 pos: self.pos,
-                                byte: StepByte::ByteRange(range.private, range.range)
+                                byte: StepByte::ByteRange(range.private, range.range),
+                                run: 1,
                             })
                         } else {
                             self.took_fork = Some(self.ip);
                             self.current = Some(LayoutStep::Fork(ProgFork {
                                 ip: alternate,
                                 pos: self.pos,
+                                run: 0,
+                                repeats: self.repeats.clone(),
                             }));
                             return;
                         }
@@ -502,7 +1279,8 @@ impl Program {
                         Some(LayoutStep::Byte {
                             ip: self.ip,
                             pos: self.pos,
-                            byte: StepByte::ByteRange(range.private, range.range)
+                            byte: StepByte::ByteRange(range.private, range.range),
+                            run: 1,
                         })
                     }
                 }
@@ -510,30 +1288,68 @@ impl Program {
                     Some(LayoutStep::Byte {
                         ip: self.ip,
                         pos: self.pos,
-                        byte: StepByte::Uninit
+                        byte: StepByte::Uninit,
+                        run: 1,
                     })
                 },
+                &Inst::UninitRun(count) => {
+                    if self.step_run(count, true, |_| StepByte::Uninit) {
+                        return;
+                    }
+                    continue;
+                }
+                Inst::ByteRangeRun(ref run) => {
+                    let (private, range) = (run.private, run.range);
+                    if self.step_run(run.count, true, |_| StepByte::ByteRange(private, range)) {
+                        return;
+                    }
+                    continue;
+                }
+                Inst::Span(_) => None,
                 Inst::Split(ref split) => {
+                    // the alternate is in the same `Repeat` bodies
                     Some(LayoutStep::Fork(ProgFork {
                         ip: split.alternate,
                         pos: self.pos,
+                        run: 0,
+                        repeats: self.repeats.clone(),
                     }))
                 }
+                Inst::Repeat(ref repeat) => {
+                    enter_repeat(&mut self.ip, repeat, &mut self.repeats);
+                    continue;
+                }
                 Inst::Byte(ref byte) => {
                     Some(LayoutStep::Byte {
                         ip: self.ip,
                         pos: self.pos,
-                        byte: StepByte::Byte(byte.private, byte.byte)
+                        byte: StepByte::Byte(byte.private, byte.byte),
+                        run: 1,
                     })
                 }
-                Inst::Ref(ref _ref) => {
-                    println!("ref unimplemented");
-                    None
+                Inst::Pointer(ref ptr) => {
+                    let (private, range) = (ptr.private, ptr.range);
+                    if self.step_run(ptr.count, true, |_| StepByte::Pointer(private, range)) {
+                        return;
+                    }
+                    continue;
+                }
+                Inst::Opaque(ref opaque) => {
+                    let id = opaque.id;
+                    if self.step_run(opaque.size, false, |offset| StepByte::Opaque(id, offset)) {
+                        return;
+                    }
+                    continue;
                 }
-                Inst::Pointer(ref _ptr) => {
-                    println!("ptr unimplemented");
-                    None
+                Inst::AnyBytes(ref any) => {
+                    let provenance = any.provenance;
+                    if self.step_run(any.count, true, |_| StepByte::Any(provenance)) {
+                        return;
+                    }
+                    continue;
                 }
+                // `Execution::check` rejects programs with these up front
+                Inst::Ref(_) => None,
                 &Inst::JoinGoto(addr) => {
                     self.ip = addr;
                     continue;
@@ -549,26 +1365,143 @@ impl Program {
             }
         }
     }
+    /// Moves to the instructions `peel` gave the byte at the current
+    /// position, if there are any.
+    fn enter_peel(&mut self) -> bool {
+        if self.peels.is_empty() {
+            return false;
+        }
+        // the closest bytes peeled at or before the current one, which may
+        // be in the middle of them
+        let peeled = self.peels.range((self.ip, 0)..=(self.ip, self.run)).rev()
+            .find_map(|(&(_, offset), peels)| peels.iter()
+                .find(|peel| peel.repeats == self.repeats)
+                .map(|peel| (offset, peel.start, peel.len)));
+        match peeled {
+            Some((offset, start, len)) if self.run - offset < len => {
+                let skipped = self.run - offset;
+                self.ip = if skipped == 0 { start } else { start + 1 };
+                self.run = skipped.saturating_sub(1);
+                self.repeats.clear();
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Steps through the run of `size` bytes at `ip`, one byte at a time,
+    /// see `run`. `uniform` runs read the same byte throughout, which the
+    /// caller may step through in bulk up to the next peeled one.
+    /// Returns whether a byte was read.
+    fn step_run(&mut self, size: u32, uniform: bool, byte: impl FnOnce(u32) -> StepByte) -> bool {
+        let left = if uniform && self.run < size {
+            let next_peel = self.peels.range((self.ip, self.run + 1)..(self.ip, size)).next();
+            next_peel.map_or(size, |(&(_, offset), _)| offset).saturating_sub(self.run)
+        } else {
+            1
+        };
+        let step = (self.run < size).then(|| LayoutStep::Byte {
+            ip: self.ip,
+            pos: self.pos,
+            byte: byte(self.run),
+            run: left,
+        });
+        self.run += 1;
+        if self.run >= size {
+            self.run = 0;
+            self.ip += 1;
+        }
+        if step.is_none() {
+            return false;
+        }
+        self.pos += 1;
+        self.current = step;
+        true
+    }
 }
 
-impl<'a> fmt::Debug for Program {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "FiniteAutomaton {{")?;
-        for (idx, inst) in self.insts.iter().enumerate() {
-            writeln!(f, "  {:03} {:?}", idx, inst)?;
+/// The instruction at `ip` of a cursor through `program` that added
+/// `added` and patched `patched`.
+fn inst_at<'a>(
+    program: &'a Program, added: &'a [Inst], patched: &'a HashMap<InstPtr, Inst>, ip: InstPtr,
+) -> Option<&'a Inst> {
+    match (ip as usize).checked_sub(program.insts.len()) {
+        Some(added_ip) => added.get(added_ip),
+        None => patched.get(&ip).or_else(|| program.insts.get(ip as usize)),
+    }
+}
+
+/// How each instruction from `start` on starts out: those that read no
+/// byte are always accepted, the others unreachable until a step gets there.
+fn accept_state(insts: &[Inst], start: usize) -> impl Iterator<Item=AcceptState> + '_ {
+    insts[start..].iter().map(|inst| match inst {
+        Inst::Span(_) | Inst::Split(_) | Inst::JoinGoto(_) | Inst::Repeat(_) | Inst::Accept =>
+            AcceptState::Always,
+        _ => AcceptState::NeverUnreachable,
+    })
+}
+
+/// Marks the instructions of `insts`, the first of them at `first`, that
+/// are in `Repeat` bodies starting among them, which nest like the arrays
+/// they were compiled from.
+fn repeat_coverage(insts: &[Inst], first: InstPtr) -> Vec<bool> {
+    let mut ends = Vec::new();
+    insts.iter().zip(first..).map(|(inst, ip)| {
+        ends.retain(|&end| end > ip);
+        let covered = !ends.is_empty();
+        if let Inst::Repeat(repeat) = inst {
+            ends.push(repeat.end);
         }
-        writeln!(f, "}}")?;
-        Ok(())
+        covered
+    }).collect()
+}
+
+/// Marks the instructions between each cell span and its end, which
+/// nest like the types they were compiled from.
+fn cell_coverage(insts: &[Inst]) -> Vec<bool> {
+    let mut ends = Vec::new();
+    insts.iter().enumerate().map(|(ip, inst)| {
+        let ip = ip as InstPtr;
+        ends.retain(|&end| end > ip);
+        let covered = !ends.is_empty();
+        match inst {
+            Inst::Span(span) if span.cell => ends.push(span.end),
+            _ => {}
+        }
+        covered
+    }).collect()
+}
+
+fn fmt_insts<'i>(insts: impl Iterator<Item=&'i Inst>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "FiniteAutomaton {{")?;
+    for (idx, inst) in insts.enumerate() {
+        writeln!(f, "  {:03} {:?}", idx, inst)?;
     }
+    writeln!(f, "}}")?;
+    Ok(())
 }
 
+impl fmt::Debug for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_insts(self.insts.iter(), f)
+    }
+}
+
+impl fmt::Debug for ProgCursor<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_insts((0..self.inst_count() as InstPtr).map(|ip| self.inst(ip)), f)
+    }
+}
+
+/// `count` bytes of a pointer, each in `range`. Unlike the bytes of an
+/// integer they carry the provenance of the pointer.
 #[derive(Clone)]
 pub struct InstrPointer {
-    pub pointer_size: u32,
-    pub data_align: u32,
+    pub private: bool,
+    pub range: RangeInclusive,
+    pub count: u32,
 }
 
-// TODO: implement references and pointers
+// TODO: implement references
 #[allow(dead_code)]
 #[derive(Clone)]
 pub enum RefKind {
@@ -579,6 +1512,7 @@ pub enum RefKind {
 #[derive(Clone)]
 pub struct InstrRef {
     pub ref_type: RefKind,
+    #[allow(dead_code)]
     pub pointer_size: u32,
     pub data_align: u32,
 }
@@ -632,3 +1566,51 @@ pub struct InstByteRange {
     pub range: RangeInclusive,
     pub alternate: Option<InstPtr>,
 }
+
+/// `count` bytes, each in `range`, e.g. of an integer or an array of them.
+#[derive(Clone)]
+pub struct InstByteRangeRun {
+    pub private: bool,
+    pub range: RangeInclusive,
+    pub count: u32,
+}
+
+/// Runs the instructions after it up to `end`, its body, `count` times,
+/// e.g. once for each element of an array.
+#[derive(Clone)]
+pub struct InstRepeat {
+    pub count: u32,
+    pub end: InstPtr,
+}
+
+/// Marks the instructions up to `end` as a value of a type, which is
+/// accepted as a whole by a span of the same type at the same position.
+#[derive(Clone)]
+pub struct InstSpan {
+    /// Index of the type in `Program::types`.
+    pub ty: u32,
+    /// Whether the value is in a private field, a private span can only be
+    /// accepted by another one.
+    pub private: bool,
+    /// Whether the value is an `UnsafeCell`, see `ProgCursor::in_cell`.
+    pub cell: bool,
+    pub size: usize,
+    pub end: InstPtr,
+}
+
+/// A run of `size` bytes of an opaque type, which hold any value, and are
+/// only accepted by the same bytes of the same type, see `Opaque::id`.
+#[derive(Clone)]
+pub struct InstOpaque {
+    pub id: u64,
+    pub size: u32,
+}
+
+/// A run of `count` bytes of a union under `UnionSemantics::Bytes` or
+/// `BytesWithProvenance`, which hold any public byte, uninit included.
+#[derive(Clone)]
+pub struct InstAnyBytes {
+    /// Whether pointer bytes keep their provenance.
+    pub provenance: bool,
+    pub count: u32,
+}
@@ -0,0 +1,971 @@
+use core::alloc::Layout;
+use core::fmt::{self, Write};
+use std::collections::BTreeMap;
+
+use crate::inst::{self, *};
+use crate::error::Error;
+use crate::ty::{self, *};
+use crate::{Reject, Verdict};
+
+/// Name and version of the document format written by `save`.
+/// The version is bumped whenever a reader of the previous version
+/// would misinterpret a document. Version 2 added `repr` to enums;
+/// documents without it still load. Version 3 added span instructions,
+/// which name their type by its index in the program's `types`. Version 4
+/// added `non_null` to pointers, which are `false` where it is missing.
+pub const FORMAT: &str = "transmute-vm";
+pub const FORMAT_VERSION: u64 = 4;
+
+#[derive(Debug, Clone)]
+pub struct JsonError {
+    pub message: String,
+}
+
+impl JsonError {
+    fn new(message: impl Into<String>) -> Self {
+        Self { message: message.into() }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<Error> for JsonError {
+    fn from(err: Error) -> Self {
+        Self::new(err.to_string())
+    }
+}
+
+type JsonResult<T> = Result<T, JsonError>;
+
+/// A JSON value. Numbers keep their literal text, so 128-bit
+/// discriminants survive a round trip.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+pub trait FromJson: Sized {
+    fn from_json(json: &Json) -> JsonResult<Self>;
+}
+
+/// Serializes `value` as a versioned document of the given `kind`.
+pub fn save<T: ToJson>(kind: &str, value: &T) -> String {
+    Json::Object(vec![
+        ("format".into(), FORMAT.to_json()),
+        ("version".into(), FORMAT_VERSION.to_json()),
+        ("kind".into(), kind.to_json()),
+        ("data".into(), value.to_json()),
+    ]).to_string()
+}
+
+/// Loads a document written by `save`, checking its format, version and kind.
+pub fn load<T: FromJson>(kind: &str, text: &str) -> JsonResult<T> {
+    let doc = Json::parse(text)?;
+    if doc.get("format")?.as_str()? != FORMAT {
+        return Err(JsonError::new("not a transmute-vm document"));
+    }
+    let version = doc.get("version")?.as_u64()?;
+    if !(1..=FORMAT_VERSION).contains(&version) {
+        return Err(JsonError::new(format!(
+            "unsupported format version {}, expected at most {}", version, FORMAT_VERSION)));
+    }
+    let found = doc.get("kind")?.as_str()?;
+    if found != kind {
+        return Err(JsonError::new(format!("expected a `{}` document, found `{}`", kind, found)));
+    }
+    T::from_json(doc.get("data")?)
+}
+
+impl Json {
+    pub fn object<const N: usize>(fields: [(&str, Json); N]) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+    pub fn get(&self, key: &str) -> JsonResult<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .ok_or_else(|| JsonError::new(format!("missing key `{}`", key))),
+            _ => Err(JsonError::new(format!("expected an object with key `{}`", key))),
+        }
+    }
+    pub fn as_str(&self) -> JsonResult<&str> {
+        match self {
+            Json::String(text) => Ok(text),
+            _ => Err(JsonError::new("expected a string")),
+        }
+    }
+    pub fn as_bool(&self) -> JsonResult<bool> {
+        match *self {
+            Json::Bool(value) => Ok(value),
+            _ => Err(JsonError::new("expected a boolean")),
+        }
+    }
+    pub fn as_array(&self) -> JsonResult<&[Json]> {
+        match self {
+            Json::Array(items) => Ok(items),
+            _ => Err(JsonError::new("expected an array")),
+        }
+    }
+    pub fn as_u128(&self) -> JsonResult<u128> {
+        match self {
+            Json::Number(text) => text.parse()
+                .map_err(|_| JsonError::new(format!("expected an unsigned integer, found {}", text))),
+            _ => Err(JsonError::new("expected a number")),
+        }
+    }
+    pub fn as_u64(&self) -> JsonResult<u64> {
+        u64::try_from(self.as_u128()?).map_err(|_| JsonError::new("number is too large"))
+    }
+    pub fn as_usize(&self) -> JsonResult<usize> {
+        usize::try_from(self.as_u128()?).map_err(|_| JsonError::new("number is too large"))
+    }
+    pub fn as_u32(&self) -> JsonResult<u32> {
+        u32::try_from(self.as_u128()?).map_err(|_| JsonError::new("number is too large"))
+    }
+    pub fn as_u8(&self) -> JsonResult<u8> {
+        u8::try_from(self.as_u128()?).map_err(|_| JsonError::new("number is too large"))
+    }
+    fn as_option<T>(&self, f: impl FnOnce(&Json) -> JsonResult<T>) -> JsonResult<Option<T>> {
+        match self {
+            Json::Null => Ok(None),
+            json => f(json).map(Some),
+        }
+    }
+
+    pub fn parse(text: &str) -> JsonResult<Json> {
+        let mut parser = JsonParser { text: text.as_bytes(), pos: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.text.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(text) => f.write_str(text),
+            Json::String(text) => write_json_string(f, text),
+            Json::Array(items) => {
+                f.write_char('[')?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 { f.write_char(',')?; }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(fields) => {
+                f.write_char('{')?;
+                for (idx, (key, value)) in fields.iter().enumerate() {
+                    if idx > 0 { f.write_char(',')?; }
+                    write_json_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_json_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for ch in text.chars() {
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            _ if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            _ => f.write_char(ch)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// How deeply arrays and objects may nest, which keeps the recursive
+/// parser, and everything walking what it parsed, off the end of the stack.
+const MAX_DEPTH: usize = 256;
+
+struct JsonParser<'t> {
+    text: &'t [u8],
+    pos: usize,
+    /// Arrays and objects the parser is in.
+    depth: usize,
+}
+
+impl<'t> JsonParser<'t> {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError::new(format!("{} at byte {}", message, self.pos))
+    }
+    fn skip_whitespace(&mut self) {
+        while self.text.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.text.get(self.pos) == Some(&byte);
+        if found { self.pos += 1; }
+        found
+    }
+    fn expect(&mut self, byte: u8) -> JsonResult<()> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", byte as char)))
+        }
+    }
+    fn keyword(&mut self, word: &str, value: Json) -> JsonResult<Json> {
+        if self.text[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid literal"))
+        }
+    }
+    fn value(&mut self) -> JsonResult<Json> {
+        self.skip_whitespace();
+        if matches!(self.text.get(self.pos), Some(b'[' | b'{')) {
+            if self.depth == MAX_DEPTH {
+                return Err(self.error("nested too deeply"));
+            }
+            self.depth += 1;
+            let value = self.nested();
+            self.depth -= 1;
+            return value;
+        }
+        match self.text.get(self.pos) {
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'-') | Some(b'0'..=b'9') => {
+                let start = self.pos;
+                self.pos += 1;
+                while self.text.get(self.pos).is_some_and(|b| {
+                    b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-')
+                }) {
+                    self.pos += 1;
+                }
+                let text = core::str::from_utf8(&self.text[start..self.pos]).unwrap();
+                Ok(Json::Number(text.to_string()))
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+    /// An array or object, see `value`.
+    fn nested(&mut self) -> JsonResult<Json> {
+        match self.text.get(self.pos) {
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.eat(b']') {
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    if self.eat(b']') { break; }
+                    self.expect(b',')?;
+                }
+                Ok(Json::Array(items))
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if self.eat(b'}') {
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    if self.eat(b'}') { break; }
+                    self.expect(b',')?;
+                }
+                Ok(Json::Object(fields))
+            }
+            _ => Err(self.error("expected an array or object")),
+        }
+    }
+    fn string(&mut self) -> JsonResult<String> {
+        if self.text.get(self.pos) != Some(&b'"') {
+            return Err(self.error("expected a string"));
+        }
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = *self.text.get(self.pos).ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self.text.get(self.pos)
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    let ch = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let hex = self.text.get(self.pos..self.pos + 4)
+                                .and_then(|hex| core::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .ok_or_else(|| self.error("invalid unicode escape"))?;
+                            self.pos += 4;
+                            char::from_u32(hex).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+                }
+                _ => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid utf-8 in string"))
+    }
+}
+
+impl ToJson for str {
+    fn to_json(&self) -> Json { Json::String(self.to_string()) }
+}
+impl ToJson for bool {
+    fn to_json(&self) -> Json { Json::Bool(*self) }
+}
+macro_rules! impl_to_json_int {
+    ($($ty:ty),*) => {
+        $(impl ToJson for $ty {
+            fn to_json(&self) -> Json { Json::Number(self.to_string()) }
+        })*
+    };
+}
+impl_to_json_int!(u8, u32, u64, u128, usize);
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        match self {
+            Some(value) => value.to_json(),
+            None => Json::Null,
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for BTreeMap<String, T> {
+    fn to_json(&self) -> Json {
+        Json::Object(self.iter().map(|(k, v)| (k.clone(), v.to_json())).collect())
+    }
+}
+
+impl<T: FromJson> FromJson for BTreeMap<String, T> {
+    fn from_json(json: &Json) -> JsonResult<Self> {
+        match json {
+            Json::Object(fields) => fields.iter()
+                .map(|(k, v)| Ok((k.clone(), T::from_json(v)?)))
+                .collect(),
+            _ => Err(JsonError::new("expected an object")),
+        }
+    }
+}
+
+/// An optional `repr(packed(N))` or `repr(align(N))` value.
+fn power_of_two_from_json(json: &Json) -> JsonResult<Option<usize>> {
+    match json.as_option(Json::as_usize)? {
+        Some(value) if !value.is_power_of_two() => {
+            Err(JsonError::new(format!("`{}` is not a power of two", value)))
+        }
+        value => Ok(value),
+    }
+}
+
+fn layout_from_json(json: &Json) -> JsonResult<Layout> {
+    let size = json.get("size")?.as_usize()?;
+    let align = json.get("align")?.as_usize()?;
+    Layout::from_size_align(size, align)
+        .map_err(|_| JsonError::new(format!("invalid layout size={} align={}", size, align)))
+}
+
+fn ref_kind_to_json(kind: &ty::RefKind) -> Json {
+    match kind {
+        ty::RefKind::Shared => "shared".to_json(),
+        ty::RefKind::Unique => "unique".to_json(),
+    }
+}
+
+fn ref_kind_from_json(json: &Json) -> JsonResult<ty::RefKind> {
+    match json.as_str()? {
+        "shared" => Ok(ty::RefKind::Shared),
+        "unique" => Ok(ty::RefKind::Unique),
+        other => Err(JsonError::new(format!("unknown reference kind `{}`", other))),
+    }
+}
+
+impl ToJson for Ty {
+    fn to_json(&self) -> Json {
+        match self {
+            Ty::Void => Json::object([("kind", "void".to_json())]),
+            Ty::Never => Json::object([("kind", "never".to_json())]),
+            Ty::Bool => Json::object([("kind", "bool".to_json())]),
+            Ty::Int(size) => Json::object([
+                ("kind", "int".to_json()),
+                ("size", size.to_json()),
+            ]),
+            Ty::Scalar(scalar) => Json::object([
+                ("kind", "scalar".to_json()),
+                ("size", scalar.size.to_json()),
+                ("start", scalar.start.to_json()),
+                ("end", scalar.end.to_json()),
+            ]),
+            Ty::Ptr(ptr) => Json::object([
+                ("kind", "ptr".to_json()),
+                ("ref_kind", ref_kind_to_json(&ptr.kind)),
+                ("align", ptr.align.to_json()),
+                ("non_null", ptr.non_null.to_json()),
+            ]),
+            Ty::Ref(r) => Json::object([
+                ("kind", "ref".to_json()),
+                ("ref_kind", ref_kind_to_json(&r.kind)),
+                ("size", r.size.to_json()),
+                ("align", r.align.to_json()),
+            ]),
+            Ty::Struct(st) => Json::object([
+                ("kind", "struct".to_json()),
+                ("name", st.name.to_json()),
+                ("size", st.layout.size().to_json()),
+                ("align", st.layout.align().to_json()),
+                ("pack", st.pack.to_json()),
+                ("repr_align", st.align.to_json()),
+                ("fields", Json::Array(st.fields.iter().map(|field| Json::object([
+                    ("name", field.name.to_json()),
+                    ("private", field.private.to_json()),
+                    ("offset", field.offset.to_json()),
+                    ("ty", field.ty.to_json()),
+                ])).collect())),
+            ]),
+            Ty::Array(arr) => Json::object([
+                ("kind", "array".to_json()),
+                ("count", arr.count.to_json()),
+                ("element", arr.element.to_json()),
+            ]),
+            Ty::UnsafeCell(inner) => Json::object([
+                ("kind", "unsafe_cell".to_json()),
+                ("inner", inner.to_json()),
+            ]),
+            Ty::Valid(valid) => Json::object([
+                ("kind", "valid".to_json()),
+                ("inner", valid.ty.to_json()),
+                ("ranges", Json::Array(valid.ranges.iter()
+                    .map(|&(start, end)| Json::Array(vec![start.to_json(), end.to_json()]))
+                    .collect())),
+            ]),
+            Ty::Enum(en) => Json::object([
+                ("kind", "enum".to_json()),
+                ("name", en.name.to_json()),
+                ("size", en.layout.size().to_json()),
+                ("align", en.layout.align().to_json()),
+                ("tag_size", en.tag_layout.size().to_json()),
+                ("repr", match en.repr {
+                    EnumRepr::C => "c",
+                    EnumRepr::Primitive => "primitive",
+                }.to_json()),
+                ("variants", Json::Array(en.variants.iter().map(|variant| Json::object([
+                    ("name", variant.name.to_json()),
+                    ("disc", variant.disc.to_json()),
+                    ("payload", variant.payload.to_json()),
+                ])).collect())),
+            ]),
+            Ty::Opaque(op) => Json::object([
+                ("kind", "opaque".to_json()),
+                ("name", op.name.to_json()),
+                ("size", op.layout.size().to_json()),
+                ("align", op.layout.align().to_json()),
+            ]),
+            Ty::Union(un) => Json::object([
+                ("kind", "union".to_json()),
+                ("name", un.name.to_json()),
+                ("size", un.layout.size().to_json()),
+                ("align", un.layout.align().to_json()),
+                ("pack", un.pack.to_json()),
+                ("repr_align", un.align.to_json()),
+                ("variants", Json::Array(un.variants.iter().map(|variant| Json::object([
+                    ("name", variant.name.to_json()),
+                    ("private", variant.private.to_json()),
+                    ("ty", variant.ty.to_json()),
+                ])).collect())),
+            ]),
+        }
+    }
+}
+
+/// Field and variant names were added without a version bump,
+/// documents that predate them load with unnamed members.
+fn member_name(json: &Json) -> JsonResult<&str> {
+    match json.get("name") {
+        Ok(name) => name.as_str(),
+        Err(_) => Ok(""),
+    }
+}
+
+/// Layouts are stored next to the fields, since imported types (e.g. from
+/// debug info) may have layouts that can't be recomputed from the fields.
+impl FromJson for Ty {
+    fn from_json(json: &Json) -> JsonResult<Self> {
+        let ty = match json.get("kind")?.as_str()? {
+            "void" => Ty::Void,
+            "never" => Ty::Never,
+            "bool" => Ty::Bool,
+            "int" => {
+                let size = json.get("size")?.as_u32()?;
+                if !matches!(size, 1 | 2 | 4 | 8 | 16) {
+                    return Err(JsonError::new(format!("invalid int size {}", size)));
+                }
+                Ty::Int(size)
+            }
+            "scalar" => {
+                let size = json.get("size")?.as_u32()?;
+                if !matches!(size, 1 | 2 | 4 | 8 | 16) {
+                    return Err(JsonError::new(format!("invalid int size {}", size)));
+                }
+                let (start, end) = (json.get("start")?.as_u128()?, json.get("end")?.as_u128()?);
+                Ty::Scalar(Scalar::new(size, start, end))
+            }
+            "ptr" => Ty::Ptr(Pointer {
+                kind: ref_kind_from_json(json.get("ref_kind")?)?,
+                align: json.get("align")?.as_usize()?,
+                // missing in documents written before `NonNull`
+                non_null: json.get("non_null").and_then(Json::as_bool).unwrap_or(false),
+            }),
+            "ref" => Ty::Ref(Reference {
+                kind: ref_kind_from_json(json.get("ref_kind")?)?,
+                size: json.get("size")?.as_usize()?,
+                align: json.get("align")?.as_usize()?,
+            }),
+            "struct" => {
+                let pack = power_of_two_from_json(json.get("pack")?)?;
+                let align = power_of_two_from_json(json.get("repr_align")?)?;
+                let mut st = Struct::with_repr(json.get("name")?.as_str()?, pack, align)?;
+                for field in json.get("fields")?.as_array()? {
+                    st.add_field_at(
+                        member_name(field)?,
+                        field.get("private")?.as_bool()?,
+                        field.get("offset")?.as_usize()?,
+                        Ty::from_json(field.get("ty")?)?,
+                    )?;
+                }
+                st.layout = layout_from_json(json)?;
+                Ty::Struct(st)
+            }
+            "array" => Ty::Array(Box::new(Array {
+                element: Ty::from_json(json.get("element")?)?,
+                count: json.get("count")?.as_usize()?,
+            })),
+            "unsafe_cell" => Ty::UnsafeCell(Box::new(Ty::from_json(json.get("inner")?)?)),
+            "valid" => {
+                let ranges = json.get("ranges")?.as_array()?.iter().map(|range| {
+                    match range.as_array()? {
+                        [start, end] => Ok((start.as_u128()?, end.as_u128()?)),
+                        _ => Err(JsonError::new("a valid range is `[start, end]`")),
+                    }
+                }).collect::<JsonResult<Vec<_>>>()?;
+                Ty::Valid(Box::new(Valid { ty: Ty::from_json(json.get("inner")?)?, ranges }))
+            }
+            "enum" => {
+                let tag_size = json.get("tag_size")?.as_u32()?;
+                if !matches!(tag_size, 1 | 2 | 4 | 8 | 16) {
+                    return Err(JsonError::new(format!("invalid tag size {}", tag_size)));
+                }
+                // missing in documents written before `repr(uN)` enums
+                let repr = match json.get("repr").and_then(Json::as_str) {
+                    Err(_) | Ok("c") => EnumRepr::C,
+                    Ok("primitive") => EnumRepr::Primitive,
+                    Ok(other) => return Err(JsonError::new(format!("unknown enum repr `{}`", other))),
+                };
+                let mut en = Enum::with_repr(json.get("name")?.as_str()?, tag_size, repr)?;
+                for variant in json.get("variants")?.as_array()? {
+                    en.add_variant(
+                        member_name(variant)?,
+                        variant.get("disc")?.as_u128()?,
+                        Ty::from_json(variant.get("payload")?)?,
+                    )?;
+                }
+                en.layout = layout_from_json(json)?;
+                Ty::Enum(en)
+            }
+            "union" => {
+                let pack = power_of_two_from_json(json.get("pack")?)?;
+                let align = power_of_two_from_json(json.get("repr_align")?)?;
+                let mut un = Union::with_repr(json.get("name")?.as_str()?, pack, align)?;
+                for variant in json.get("variants")?.as_array()? {
+                    un.add_variant(
+                        member_name(variant)?,
+                        variant.get("private")?.as_bool()?,
+                        Ty::from_json(variant.get("ty")?)?,
+                    )?;
+                }
+                un.layout = layout_from_json(json)?;
+                Ty::Union(un)
+            }
+            "opaque" => Ty::Opaque(Opaque::new(json.get("name")?.as_str()?, layout_from_json(json)?)),
+            other => return Err(JsonError::new(format!("unknown type kind `{}`", other))),
+        };
+        Ok(ty)
+    }
+}
+
+impl ToJson for Inst {
+    fn to_json(&self) -> Json {
+        match self {
+            Inst::Accept => Json::object([("op", "accept".to_json())]),
+            Inst::Uninit => Json::object([("op", "uninit".to_json())]),
+            Inst::UninitRun(count) => Json::object([
+                ("op", "uninit_run".to_json()),
+                ("count", count.to_json()),
+            ]),
+            Inst::Pointer(ptr) => Json::object([
+                ("op", "pointer".to_json()),
+                ("private", ptr.private.to_json()),
+                ("range", ptr.range.to_json()),
+                ("count", ptr.count.to_json()),
+            ]),
+            Inst::Ref(r) => Json::object([
+                ("op", "ref".to_json()),
+                ("ref_kind", match r.ref_type {
+                    inst::RefKind::Shared => "shared".to_json(),
+                    inst::RefKind::Unique => "unique".to_json(),
+                }),
+                ("pointer_size", r.pointer_size.to_json()),
+                ("data_align", r.data_align.to_json()),
+            ]),
+            Inst::Byte(byte) => Json::object([
+                ("op", "byte".to_json()),
+                ("private", byte.private.to_json()),
+                ("byte", byte.byte.to_json()),
+            ]),
+            Inst::ByteRange(range) => Json::object([
+                ("op", "byte_range".to_json()),
+                ("private", range.private.to_json()),
+                ("range", range.range.to_json()),
+                ("alternate", range.alternate.to_json()),
+            ]),
+            Inst::ByteRangeRun(run) => Json::object([
+                ("op", "byte_range_run".to_json()),
+                ("private", run.private.to_json()),
+                ("range", run.range.to_json()),
+                ("count", run.count.to_json()),
+            ]),
+            Inst::Opaque(opaque) => Json::object([
+                ("op", "opaque".to_json()),
+                ("id", opaque.id.to_json()),
+                ("size", opaque.size.to_json()),
+            ]),
+            Inst::AnyBytes(any) => Json::object([
+                ("op", "any_bytes".to_json()),
+                ("provenance", any.provenance.to_json()),
+                ("count", any.count.to_json()),
+            ]),
+            Inst::Repeat(repeat) => Json::object([
+                ("op", "repeat".to_json()),
+                ("count", repeat.count.to_json()),
+                ("end", repeat.end.to_json()),
+            ]),
+            Inst::Span(span) => Json::object([
+                ("op", "span".to_json()),
+                ("ty", span.ty.to_json()),
+                ("private", span.private.to_json()),
+                ("cell", span.cell.to_json()),
+                ("size", span.size.to_json()),
+                ("end", span.end.to_json()),
+            ]),
+            Inst::Split(split) => Json::object([
+                ("op", "split".to_json()),
+                ("alternate", split.alternate.to_json()),
+            ]),
+            Inst::JoinGoto(addr) => Json::object([
+                ("op", "join_goto".to_json()),
+                ("target", addr.to_json()),
+            ]),
+        }
+    }
+}
+
+impl FromJson for Inst {
+    fn from_json(json: &Json) -> JsonResult<Self> {
+        let inst = match json.get("op")?.as_str()? {
+            "accept" => Inst::Accept,
+            "uninit" => Inst::Uninit,
+            "uninit_run" => Inst::UninitRun(json.get("count")?.as_u32()?),
+            "pointer" => Inst::Pointer(InstrPointer {
+                private: json.get("private")?.as_bool()?,
+                range: RangeInclusive::from_json(json.get("range")?)?,
+                count: json.get("count")?.as_u32()?,
+            }),
+            "ref" => Inst::Ref(InstrRef {
+                ref_type: match json.get("ref_kind")?.as_str()? {
+                    "shared" => inst::RefKind::Shared,
+                    "unique" => inst::RefKind::Unique,
+                    other => {
+                        return Err(JsonError::new(format!("unknown reference kind `{}`", other)));
+                    }
+                },
+                pointer_size: json.get("pointer_size")?.as_u32()?,
+                data_align: json.get("data_align")?.as_u32()?,
+            }),
+            "byte" => Inst::Byte(InstByte {
+                private: json.get("private")?.as_bool()?,
+                byte: json.get("byte")?.as_u8()?,
+            }),
+            "byte_range" => Inst::ByteRange(InstByteRange {
+                private: json.get("private")?.as_bool()?,
+                range: RangeInclusive::from_json(json.get("range")?)?,
+                alternate: json.get("alternate")?.as_option(Json::as_u32)?,
+            }),
+            "byte_range_run" => Inst::ByteRangeRun(InstByteRangeRun {
+                private: json.get("private")?.as_bool()?,
+                range: RangeInclusive::from_json(json.get("range")?)?,
+                count: json.get("count")?.as_u32()?,
+            }),
+            "opaque" => Inst::Opaque(InstOpaque {
+                id: json.get("id")?.as_u64()?,
+                size: json.get("size")?.as_u32()?,
+            }),
+            "any_bytes" => Inst::AnyBytes(InstAnyBytes {
+                provenance: json.get("provenance")?.as_bool()?,
+                count: json.get("count")?.as_u32()?,
+            }),
+            "repeat" => Inst::Repeat(InstRepeat {
+                count: json.get("count")?.as_u32()?,
+                end: json.get("end")?.as_u32()?,
+            }),
+            "span" => Inst::Span(InstSpan {
+                ty: json.get("ty")?.as_u32()?,
+                private: json.get("private")?.as_bool()?,
+                // missing in documents written before cell spans
+                cell: json.get("cell").and_then(Json::as_bool).unwrap_or(false),
+                size: json.get("size")?.as_usize()?,
+                end: json.get("end")?.as_u32()?,
+            }),
+            "split" => Inst::Split(InstSplit {
+                alternate: json.get("alternate")?.as_u32()?,
+            }),
+            "join_goto" => Inst::JoinGoto(json.get("target")?.as_u32()?),
+            other => return Err(JsonError::new(format!("unknown instruction `{}`", other))),
+        };
+        Ok(inst)
+    }
+}
+
+impl ToJson for RangeInclusive {
+    fn to_json(&self) -> Json {
+        Json::object([("start", self.start.to_json()), ("end", self.end.to_json())])
+    }
+}
+
+impl FromJson for RangeInclusive {
+    fn from_json(json: &Json) -> JsonResult<Self> {
+        Ok(RangeInclusive {
+            start: json.get("start")?.as_u8()?,
+            end: json.get("end")?.as_u8()?,
+        })
+    }
+}
+
+impl ToJson for Program {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("name", self.name().to_json()),
+            ("insts", Json::Array(self.insts().iter().map(ToJson::to_json).collect())),
+            ("types", Json::Array(self.types().iter().map(ToJson::to_json).collect())),
+        ])
+    }
+}
+
+/// Only the compiled instructions are stored, a loaded program starts
+/// from the beginning.
+impl FromJson for Program {
+    fn from_json(json: &Json) -> JsonResult<Self> {
+        let insts = json.get("insts")?.as_array()?.iter()
+            .map(Inst::from_json)
+            .collect::<JsonResult<Vec<_>>>()?;
+        // missing in documents written before spans
+        let types = match json.get("types") {
+            Ok(types) => types.as_array()?.iter()
+                .map(Ty::from_json)
+                .collect::<JsonResult<Vec<_>>>()?,
+            Err(_) => Vec::new(),
+        };
+        if !Program::is_well_formed(&insts, &types) {
+            return Err(JsonError::new("program is not well-formed"));
+        }
+        Ok(Program::new(insts, types, json.get("name")?.as_str()?))
+    }
+}
+
+impl ToJson for AcceptState {
+    fn to_json(&self) -> Json {
+        use AcceptState::*;
+        let ranges = |state: &str, dst: &RangeInclusive, src: &RangeInclusive| Json::object([
+            ("state", state.to_json()),
+            ("dst", dst.to_json()),
+            ("src", src.to_json()),
+        ]);
+        let state = |state: &str| Json::object([("state", state.to_json())]);
+        match self {
+            Always => state("always"),
+            NeverReadUninit => state("never_read_uninit"),
+            NeverReadPrivate => state("never_read_private"),
+            NeverWritePrivate => state("never_write_private"),
+            NeverTagMismatch => state("never_tag_mismatch"),
+            NeverStripsProvenance => state("never_strips_provenance"),
+            NeverOutOfRange(dst, src) => ranges("never_out_of_range", dst, src),
+            NeverUnreachable => state("never_unreachable"),
+            NeverUninhabited => state("never_uninhabited"),
+            NeverCellMismatch => state("never_cell_mismatch"),
+            MaybeCheckRange(dst, src) => ranges("maybe_check_range", dst, src),
+        }
+    }
+}
+
+impl FromJson for AcceptState {
+    fn from_json(json: &Json) -> JsonResult<Self> {
+        use AcceptState::*;
+        let ranges = || -> JsonResult<(RangeInclusive, RangeInclusive)> {
+            Ok((
+                RangeInclusive::from_json(json.get("dst")?)?,
+                RangeInclusive::from_json(json.get("src")?)?,
+            ))
+        };
+        let state = match json.get("state")?.as_str()? {
+            "always" => Always,
+            "never_read_uninit" => NeverReadUninit,
+            "never_read_private" => NeverReadPrivate,
+            "never_write_private" => NeverWritePrivate,
+            "never_tag_mismatch" => NeverTagMismatch,
+            "never_strips_provenance" => NeverStripsProvenance,
+            "never_out_of_range" => { let (dst, src) = ranges()?; NeverOutOfRange(dst, src) }
+            "never_unreachable" => NeverUnreachable,
+            "never_uninhabited" => NeverUninhabited,
+            "never_cell_mismatch" => NeverCellMismatch,
+            "maybe_check_range" => { let (dst, src) = ranges()?; MaybeCheckRange(dst, src) }
+            other => return Err(JsonError::new(format!("unknown accept state `{}`", other))),
+        };
+        Ok(state)
+    }
+}
+
+impl ToJson for Reject {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("src", self.src.to_json()),
+            ("dst", self.dst.to_json()),
+            ("reason", self.reason.to_json()),
+        ])
+    }
+}
+
+impl FromJson for Reject {
+    fn from_json(json: &Json) -> JsonResult<Self> {
+        Ok(Reject {
+            src: json.get("src")?.as_option(Json::as_u32)?,
+            dst: json.get("dst")?.as_u32()?,
+            reason: AcceptState::from_json(json.get("reason")?)?,
+        })
+    }
+}
+
+impl ToJson for Verdict {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("src", self.src.to_json()),
+            ("dst", self.dst.to_json()),
+            ("accepted", self.accepted().to_json()),
+            ("rejects", Json::Array(self.rejects.iter().map(ToJson::to_json).collect())),
+        ])
+    }
+}
+
+impl FromJson for Verdict {
+    fn from_json(json: &Json) -> JsonResult<Self> {
+        Ok(Verdict {
+            src: json.get("src")?.as_str()?.to_string(),
+            dst: json.get("dst")?.as_str()?.to_string(),
+            rejects: json.get("rejects")?.as_array()?.iter()
+                .map(Reject::from_json)
+                .collect::<JsonResult<_>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::parse::rust::parse_rust;
+
+    fn types() -> BTreeMap<String, Ty> {
+        parse_rust("
+            #[repr(C)] struct S { #[valid(0, 2..=3)] a: u8, e: E, u: U, w: [W; 3] }
+            #[repr(u128)] enum E { A = 170141183460469231731687303715884105727, B = 0 }
+            #[repr(C)] union U { x: u16, y: bool }
+            #[repr(C, u8)] enum W { P(u32), Q { b: bool } }
+            #[repr(u8)] enum P { A(u32), B { x: u16 } }
+        ").unwrap()
+    }
+
+    #[test]
+    fn types_round_trip() {
+        let types = types();
+        let loaded: BTreeMap<String, Ty> = load("types", &save("types", &types)).unwrap();
+        assert_eq!(loaded.to_json(), types.to_json());
+        assert!(matches!(loaded["P"], Ty::Enum(ref en) if en.repr == EnumRepr::Primitive));
+    }
+
+    #[test]
+    fn program_round_trip() {
+        let program = Compiler::compile(&types()["S"], Endian::Little, "S").unwrap();
+        let loaded: Program = load("program", &save("program", &program)).unwrap();
+        assert_eq!(loaded.to_json(), program.to_json());
+    }
+
+    #[test]
+    fn versions() {
+        let text = save("types", &types());
+        let with_version = |version: u64| text.replacen(
+            &format!("\"version\":{}", FORMAT_VERSION), &format!("\"version\":{}", version), 1);
+        assert!(load::<BTreeMap<String, Ty>>("types", &with_version(1)).is_ok());
+        assert!(load::<BTreeMap<String, Ty>>("types", &with_version(0)).is_err());
+        assert!(load::<BTreeMap<String, Ty>>("types", &with_version(FORMAT_VERSION + 1)).is_err());
+        assert!(load::<Program>("program", &text).is_err());
+    }
+
+    #[test]
+    fn nesting_depth() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(Json::parse(&nested(1 << 20)).is_err());
+    }
+}
@@ -1,143 +1,700 @@
-#![feature(alloc_layout_extra)]
-#![feature(untagged_unions)]
+mod cache;
 mod compiler;
 mod derive;
+mod dwarf;
+mod error;
 mod inst;
+mod json;
+mod matrix;
+mod parse;
 mod print;
+mod report;
 mod ty;
+mod validate;
 // use print::Printer;
 use crate::print::Printer;
 use derive::{InspectTy, derive_ty};
-use crate::compiler::Compiler;
-use crate::inst::{InstPtr, Program, ProgFork, StepByte, LayoutStep, AcceptState};
+use crate::compiler::{Compiler, UnionSemantics};
+use crate::error::Error;
+use crate::inst::{Alternate, Inst, InstPtr, InstSpan, Program, ProgCursor, ProgFork, StepByte, LayoutStep, AcceptState};
 use crate::ty::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use core::fmt::Write;
+use core::ops::Range;
 
 
 struct ExecFork {
     dst: ProgFork,
     src: ProgFork,
+    /// Last product state explored before the fork.
+    last: Option<ProductState>,
+    /// Length of `Execution::accepted` at the fork.
+    accepted: usize,
+}
+
+/// A state of the product of the dst and src automata: the instructions
+/// both programs are at and the byte position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ProductState {
+    dst: InstPtr,
+    src: Option<InstPtr>,
+    pos: usize,
+}
+
+/// A step `Execution::check` took between two product states, and the
+/// fork it was taken in.
+struct ProductEdge {
+    from: Option<ProductState>,
+    to: ProductState,
+    accepts: AcceptState,
+    variant: usize,
+    /// Bytes the step covers, more than one when it went through runs.
+    len: u32,
 }
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Reject {
     src: Option<InstPtr>,
     dst: InstPtr,
     reason: AcceptState,
 }
 
-struct Execution {
+/// Src bytes that run into `reject`, one per position up to and including
+/// the rejected one. `None` stands for an uninit byte.
+#[derive(Debug, Clone)]
+struct Counterexample {
+    reject: Reject,
+    bytes: Vec<Option<u8>>,
+}
+
+/// Outcome of checking whether `src` can be transmuted into `dst`.
+#[derive(Debug)]
+struct Verdict {
+    src: String,
+    dst: String,
+    rejects: Vec<Reject>,
+}
+
+impl Verdict {
+    fn accepted(&self) -> bool {
+        self.rejects.is_empty()
+    }
+}
+
+impl core::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let outcome = if self.accepted() { "accepted" } else { "rejected" };
+        writeln!(f, "{} -> {}: {}", self.src, self.dst, outcome)?;
+        for reject in self.rejects.iter() {
+            let src = reject.src.map_or("-".to_string(), |ip| ip.to_string());
+            writeln!(f, "  src ip {}, dst ip {}: {}", src, reject.dst, reject.reason)?;
+        }
+        Ok(())
+    }
+}
+
+struct Execution<'p> {
     forks: Vec<ExecFork>,
     accept: Vec<AcceptState>,
-    reject: Vec<Reject>,
-    dst: Program,
-    src: Program,
+    /// Where the src bytes that stand for more than one position, or
+    /// copies of such, were accepted, as `((origin, alternate), start)
+    /// -> end`.
+    accepted_at: BTreeMap<((InstPtr, Alternate), usize), usize>,
+    /// The rejects and the position of each.
+    reject: Vec<(Reject, usize)>,
+    dst: ProgCursor<'p>,
+    src: ProgCursor<'p>,
+    trace: Vec<ProductEdge>,
+    last: Option<ProductState>,
+    variant: usize,
+    variants: usize,
+    /// Src bytes accepted on the path being explored. They are only marked
+    /// as always accepted once the path reaches the end of dst, a later
+    /// byte of the path may still be rejected.
+    accepted: Vec<(InstPtr, Range<usize>)>,
+    /// For each src type, the dst types of the same size it is already
+    /// known to be accepted as, e.g. by an earlier check, whose spans are
+    /// accepted as a whole.
+    known: HashMap<Ty, HashSet<Ty>>,
+    /// Whether `src` and `dst` are behind shared references, where they
+    /// must also agree on which bytes are in an `UnsafeCell`.
+    shared_ref: bool,
 }
 
-impl Execution {
-    fn new(dst: Program, src: Program) -> Self {
+impl<'p> Execution<'p> {
+    fn new(dst: &'p Program, src: &'p Program) -> Self {
         Self {
             forks: Vec::new(),
             accept: src.accept_state(0).collect(),
+            accepted_at: BTreeMap::new(),
             reject: Vec::new(),
-            dst,
-            src,
+            dst: dst.cursor(),
+            src: src.cursor(),
+            trace: Vec::new(),
+            last: None,
+            variant: 0,
+            variants: 1,
+            accepted: Vec::new(),
+            known: HashMap::new(),
+            shared_ref: false,
         }
     }
+    /// Checks whether `&src` can be transmuted into `&dst`.
+    fn behind_shared_ref(dst: &'p Program, src: &'p Program) -> Self {
+        Self { shared_ref: true, ..Self::new(dst, src) }
+    }
+    fn push_fork(&mut self, dst: ProgFork, src: ProgFork) {
+        let accepted = self.accepted.len();
+        self.forks.push(ExecFork { dst, src, last: self.last, accepted });
+    }
     fn pop_fork(&mut self) -> bool {
         if let Some(fork) = self.forks.pop() {
             self.src.restore_fork(fork.src);
             self.dst.restore_fork(fork.dst);
+            self.last = fork.last;
+            self.accepted.truncate(fork.accepted);
+            self.variant = self.variants;
+            self.variants += 1;
             true
         } else {
             false
         }
     }
-    fn check(&mut self) {
+    /// A dst span and a src span, with where each starts, at the current
+    /// position that can be accepted as a whole: of the same type and
+    /// equally private, or public and one of the `known` pairs.
+    fn accepted_spans(&self) -> Option<(InstPtr, InstSpan, InstPtr, InstSpan)> {
+        let src_spans = self.src.spans();
+        self.dst.spans().into_iter().find_map(|(dst_start, dst_span)| {
+            let dst_ty = self.dst.span_ty(&dst_span);
+            src_spans.iter().find(|(start, span)| {
+                let src_ty = self.src.span_ty(span);
+                // behind shared references the bytes must agree on being in
+                // a cell too, which a union around the span can make them;
+                // the known pairs were only checked by value
+                if self.shared_ref {
+                    return span.private == dst_span.private && src_ty == dst_ty
+                        && self.src.in_cell(*start) == self.dst.in_cell(dst_start);
+                }
+                if span.private || dst_span.private {
+                    return span.private == dst_span.private && src_ty == dst_ty;
+                }
+                src_ty == dst_ty || span.size == dst_span.size && self.known.get(src_ty)
+                    .is_some_and(|dsts| dsts.contains(dst_ty))
+            }).map(|(start, src_span)| (dst_start, dst_span.clone(), *start, src_span.clone()))
+        })
+    }
+    /// Marks the src bytes of the path explored so far as always accepted.
+    fn accept_path(&mut self) {
+        for (ip, range) in core::mem::take(&mut self.accepted) {
+            if self.by_position(ip) {
+                self.accept_at((self.src.origin(ip), self.src.alternate(ip)), range);
+            } else {
+                self.accept[ip as usize] = AcceptState::Always;
+            }
+        }
+    }
+    /// Marks the src byte at `ip` as always accepted at the positions in
+    /// `range`, merged with those it already was.
+    fn accept_at(&mut self, ip: (InstPtr, Alternate), range: Range<usize>) {
+        let (mut start, mut end) = (range.start, range.end);
+        if let Some((&(_, prev), &prev_end)) = self.accepted_at.range((ip, 0)..=(ip, start)).next_back() {
+            if prev_end >= start {
+                start = prev;
+                end = end.max(prev_end);
+            }
+        }
+        let merged = self.accepted_at.range((ip, start)..=(ip, end))
+            .map(|(&key, &end)| (key, end))
+            .collect::<Vec<_>>();
+        for (key, merged_end) in merged {
+            self.accepted_at.remove(&key);
+            end = end.max(merged_end);
+        }
+        self.accepted_at.insert((ip, start), end);
+    }
+    /// Whether the src byte at `ip` is remembered by position: one in a
+    /// `Repeat` body, or a copy, which reads the same as its origin at
+    /// that position.
+    fn by_position(&self, ip: InstPtr) -> bool {
+        self.src.is_repeated(ip) || self.src.origin(ip) != ip
+    }
+    /// Whether the src byte at `ip` was accepted at `pos` on a path that
+    /// reached the end of dst.
+    fn accepted(&self, ip: InstPtr, pos: usize) -> bool {
+        if !self.by_position(ip) {
+            return self.accept[ip as usize].always();
+        }
+        let origin = (self.src.origin(ip), self.src.alternate(ip));
+        self.accepted_at.range((origin, 0)..=(origin, pos)).next_back()
+            .is_some_and(|(_, &end)| pos < end)
+    }
+    fn check(&mut self) -> Result<(), Error> {
+        for cursor in [&self.dst, &self.src].iter() {
+            if cursor.program().insts().iter().any(|inst| matches!(inst, Inst::Ref(_))) {
+                return Err(Error::Unsupported("reference"));
+            }
+        }
+        // every value of an uninhabited src fits, there are none; and no
+        // value fits an uninhabited dst
+        if self.src.is_uninhabited() {
+            return Ok(());
+        }
+        if self.dst.is_uninhabited() {
+            let reject = Reject { src: None, dst: 0, reason: AcceptState::NeverUninhabited };
+            self.reject.push((reject, 0));
+            return Ok(());
+        }
         loop {
+            // identical types at the same position are accepted as a whole,
+            // and so are pairs of types already known to be
+            if let Some((dst_start, dst_span, start, src_span)) = self.accepted_spans() {
+                let pos = self.dst.position();
+                self.accepted.extend((start..src_span.end).map(|ip| (ip, pos..pos + src_span.size)));
+                self.dst.skip_span(dst_start, &dst_span);
+                self.src.skip_span(start, &src_span);
+                continue;
+            }
             let src_fork = self.src.save_fork();
             let dst_fork = self.dst.save_fork();
             if let Some(next_src) = self.src.next_fork() {
                 // println!("fork src");
                 self.src.next();
-                self.forks.push(ExecFork {
-                    dst: self.dst.save_fork(),
-                    src: next_src,
-                });
+                let dst_fork = self.dst.save_fork();
+                self.push_fork(dst_fork, next_src);
                 continue;
             }
             if let Some(next_dst) = self.dst.next_fork() {
                 // println!("fork dst");
                 self.dst.next();
-                self.forks.push(ExecFork {
-                    dst: next_dst,
-                    src: src_fork,
-                });
+                self.push_fork(next_dst, src_fork);
                 continue;
             }
 
-            let (s_ip, byte_src) = match self.src.next() {
-                None => (None, StepByte::Uninit),
-                Some(LayoutStep::Byte { ip, byte, .. }) => {
-                    (Some(ip), byte)
+            // an ended src reads as uninit bytes, as many as needed
+            let (s_ip, byte_src, s_run) = match self.src.next() {
+                None => (None, StepByte::Uninit, u32::MAX),
+                Some(LayoutStep::Byte { ip, byte, run, .. }) => {
+                    (Some(ip), byte, run)
                 }
-                _ => unreachable!("peek and next must match")
+                _ => return Err(Error::MalformedProgram(
+                    format!("{} forks after next_fork", self.src.name()))),
             };
-            let (d_ip, byte_dst) = match self.dst.next() {
+            let (d_ip, pos, byte_dst, d_run) = match self.dst.next() {
                 None => {
+                    self.accept_path();
                     if self.pop_fork() { continue }
                     else { break }
                 }
-                Some(LayoutStep::Byte { ip, byte, .. }) => (ip, byte),
-                _ => unreachable!("peek and next must match")
+                Some(LayoutStep::Byte { ip, pos, byte, run }) => (ip, pos, byte, run),
+                _ => return Err(Error::MalformedProgram(
+                    format!("{} forks after next_fork", self.dst.name()))),
             };
-            println!("dst={}, src={:?}", d_ip, s_ip);
-            if s_ip.map_or(false, |ip| self.accept[ip as usize].always()) {
+            // the rest of src was accepted on an earlier path
+            if s_ip.is_some_and(|ip| self.accepted(ip, pos)) {
+                self.accept_path();
                 if self.pop_fork() { continue }
                 else { break }
             }
-            let accepts = byte_dst.accepts(&byte_src);
-            let (accepts, fork) = self.src.synthetic_fork(s_ip, accepts, &mut self.accept);
-            if let Some(src_fork) = fork {
-                self.forks.push(ExecFork {
-                    dst: dst_fork,
-                    src: src_fork,
+            let src_in_cell = matches!(s_ip, Some(ip) if self.src.in_cell(ip));
+            let accepts = if self.shared_ref && self.dst.in_cell(d_ip) != src_in_cell {
+                AcceptState::NeverCellMismatch
+            } else {
+                byte_dst.accepts(&byte_src)
+            };
+            // runs that agree or that narrowing makes agree are stepped
+            // through together
+            let narrows = matches!(accepts, AcceptState::MaybeCheckRange(..));
+            let len = if accepts.always() || narrows { d_run.min(s_run) } else { 1 };
+            self.dst.skip_run(len - 1);
+            self.src.skip_run(len - 1);
+            // narrowing a src byte that stands for more than one position
+            // would narrow all of them, so it gets an instruction of its own
+            let s_ip = match s_ip {
+                Some(ip) if narrows => Some(self.src.peel(ip, len, &mut self.accept)),
+                _ => s_ip,
+            };
+            // a run is narrowed as a whole, its values outside of dst split
+            // off in one alternate dst rejects
+            let split_off = match (s_ip, &accepts) {
+                (Some(ip), &AcceptState::MaybeCheckRange(dst, src)) if len > 1 => {
+                    self.src.narrow_run(ip, len, dst, &mut self.accept).map(|split_off| {
+                        let outside = if src.start < dst.start {
+                            (src.start..=dst.start - 1).into()
+                        } else {
+                            (dst.end + 1..=src.end).into()
+                        };
+                        (split_off, AcceptState::NeverOutOfRange(dst, outside))
+                    })
+                }
+                _ => None,
+            };
+            let (accepts, fork) = if let Some((split_off, reason)) = split_off {
+                let state = ProductState { dst: d_ip, src: Some(split_off), pos };
+                self.trace.push(ProductEdge {
+                    from: self.last,
+                    to: state,
+                    accepts: reason.clone(),
+                    variant: self.variant,
+                    len: 1,
                 });
+                let reject = Reject { src: Some(split_off), dst: d_ip, reason };
+                self.reject.push((reject, pos));
+                (AcceptState::Always, None)
+            } else {
+                self.src.synthetic_fork(s_ip, accepts, &mut self.accept)
+            };
+            if let Some(src_fork) = fork {
+                self.push_fork(dst_fork, src_fork);
             }
-            if let Some(ip) = s_ip {
-                println!("accepts={:?}", accepts);
-                self.accept[ip as usize] = accepts.clone();
+            let state = ProductState { dst: d_ip, src: s_ip, pos };
+            self.trace.push(ProductEdge {
+                from: self.last.replace(state),
+                to: state,
+                accepts: accepts.clone(),
+                variant: self.variant,
+                len,
+            });
+            match s_ip {
+                Some(ip) if accepts.always() => self.accepted.push((ip, pos..pos + len as usize)),
+                Some(ip) if !self.by_position(ip) => self.accept[ip as usize] = accepts.clone(),
+                _ => {}
             }
             if !accepts.always() {
-                self.reject.push(Reject {
+                let reject = Reject {
                     src: s_ip,
                     dst: d_ip,
                     reason: accepts,
-                });
+                };
+                self.reject.push((reject, pos));
                 if self.pop_fork() { continue }
                 else { break }
             }
         }
-        println!("dst: {:?}\nsrc: {:?}", self.dst, self.src);
-        let mut dot = "digraph q {\n".to_string();
-        self.dst.print_dot(&mut dot).unwrap();
-        self.src.print_dot(&mut dot).unwrap();
-        dot.push_str("}\n");
-        println!("{}", dot);
-        println!("accept: {:?}", self.accept);
-        self.reject.retain(|rej| {
-            let src = match rej.src {
-                Some(src) => src,
-                None => { return false; },
+        // a src byte rejected on one path may be accepted on a later one;
+        // a src that ran out before dst stays rejected
+        let rejects = core::mem::take(&mut self.reject);
+        self.reject = rejects.into_iter().filter(|(rej, pos)| match rej.src {
+            Some(src) => !self.accepted(src, *pos),
+            None => true,
+        }).collect();
+        Ok(())
+    }
+    /// Writes the explored product states as a Graphviz digraph, with one
+    /// cluster per fork and edges colored by the `AcceptState` of the step.
+    fn print_product_dot<W: Write>(&self, dst: &mut W) -> core::fmt::Result {
+        let node = |state: &ProductState| match state.src {
+            Some(src) => format!("p{}_d{}_s{}", state.pos, state.dst, src),
+            None => format!("p{}_d{}_end", state.pos, state.dst),
+        };
+        writeln!(dst, "digraph product {{")?;
+        writeln!(dst, "  start [shape=point];")?;
+        // a state reached again by a later fork stays in the first cluster
+        let mut declared = Vec::new();
+        for variant in 0..self.variants {
+            let mut nodes = String::new();
+            for edge in self.trace.iter().filter(|edge| edge.variant == variant) {
+                if declared.contains(&edge.to) {
+                    continue;
+                }
+                declared.push(edge.to);
+                let src = edge.to.src.map_or("end".to_string(), |ip| format!("ip{}", ip));
+                writeln!(nodes, "    {} [shape=ellipse, label=\"pos={}\\ndst ip{}\\nsrc {}\"];",
+                    node(&edge.to), edge.to.pos, edge.to.dst, src)?;
+            }
+            if !nodes.is_empty() {
+                writeln!(dst, "  subgraph cluster_{} {{", variant)?;
+                writeln!(dst, "    label=\"variant {}\";", variant)?;
+                write!(dst, "{}", nodes)?;
+                writeln!(dst, "  }}")?;
+            }
+        }
+        for edge in self.trace.iter() {
+            let color = match edge.accepts {
+                AcceptState::Always => "darkgreen",
+                AcceptState::MaybeCheckRange(..) => "orange",
+                _ => "red",
             };
-            !self.accept[src as usize].always()
-        });
-        println!("reject: {:?}", self.reject);
+            let from = edge.from.as_ref().map_or("start".to_string(), node);
+            let times = if edge.len > 1 { format!(" x{}", edge.len) } else { String::new() };
+            writeln!(dst, "  {} -> {} [color={}, label=\"{}{}\"];",
+                from, node(&edge.to), color, edge.accepts, times)?;
+        }
+        writeln!(dst, "}}")
+    }
+    /// Builds a counterexample for every reject by following the explored
+    /// product states from the start to the rejected one.
+    fn counterexamples(&self) -> Vec<Counterexample> {
+        self.reject.iter().filter_map(|(rej, pos)| {
+            let mut edge = self.trace.iter().find(|edge| {
+                edge.to.dst == rej.dst && edge.to.src == rej.src && edge.to.pos == *pos
+                    && !edge.accepts.always()
+            })?;
+            let mut chain = vec![edge];
+            while let Some(from) = edge.from {
+                edge = self.trace.iter().find(|edge| edge.to == from)?;
+                chain.push(edge);
+            }
+            let bytes = chain.iter().rev()
+                .flat_map(|edge| (0..edge.len).map(move |_| self.src_byte(edge)))
+                .collect();
+            Some(Counterexample { reject: rej.clone(), bytes })
+        }).collect()
+    }
+    /// Picks a src byte for a step, outside of the dst range if the step
+    /// was rejected for it.
+    fn src_byte(&self, edge: &ProductEdge) -> Option<u8> {
+        match self.src.inst(edge.to.src?) {
+            Inst::Byte(byte) => Some(byte.byte),
+            Inst::Pointer(ptr) => Some(ptr.range.start),
+            Inst::ByteRangeRun(run) => Some(run.range.start),
+            Inst::ByteRange(range) => Some(match edge.accepts {
+                AcceptState::NeverOutOfRange(dst, src) | AcceptState::MaybeCheckRange(dst, src) => {
+                    if src.start < dst.start { src.start } else { src.start.max(dst.end + 1) }
+                }
+                _ => range.range.start,
+            }),
+            _ => None,
+        }
+    }
+    /// Whether every reject only rejects some of the values of a src byte:
+    /// a range check dst needs, or the out of range values a synthetic fork
+    /// split off a src byte whose other values are accepted.
+    fn rejects_only_values(&self) -> bool {
+        self.reject.iter().all(|(rej, _)| match rej.reason {
+            AcceptState::MaybeCheckRange(..) => true,
+            AcceptState::NeverOutOfRange(..) =>
+                rej.src.is_some_and(|ip| self.src.is_split_off(ip)),
+            _ => false,
+        })
+    }
+    fn verdict(&self) -> Verdict {
+        Verdict {
+            src: self.src.name().to_string(),
+            dst: self.dst.name().to_string(),
+            rejects: self.reject.iter().map(|(rej, _)| rej.clone()).collect(),
+        }
+    }
+}
+
+/// Loads the types declared in a Rust source, C header, ELF file with
+/// debug info, or JSON `types` document.
+fn load_types(path: &str) -> Result<BTreeMap<String, Ty>, String> {
+    let data = std::fs::read(path)
+        .map_err(|err| format!("{}: {}", path, err))?;
+    let types = if data.starts_with(b"\x7fELF") {
+        dwarf::import_elf(&data).map(|imported| imported.types).map_err(|err| err.to_string())
+    } else {
+        let text = String::from_utf8_lossy(&data);
+        if path.ends_with(".h") || path.ends_with(".c") {
+            parse::c::parse_c(&text).map_err(|err| err.to_string())
+        } else if path.ends_with(".json") {
+            json::load("types", &text).map_err(|err| err.to_string())
+        } else {
+            parse::rust::parse_rust(&text).map_err(|err| err.to_string())
+        }
+    };
+    types.map_err(|err| format!("{}: {}", path, err))
+}
+
+/// The error for a type `load_types` didn't find in `path`, with why it was
+/// skipped if the debug info of an ELF file describes it.
+fn no_type_named(path: &str, name: &str) -> String {
+    let skipped = std::fs::read(path).ok()
+        .filter(|data| data.starts_with(b"\x7fELF"))
+        .and_then(|data| dwarf::import_elf(&data).ok())
+        .and_then(|mut imported| imported.skipped.remove(name));
+    match skipped {
+        Some(err) => format!("{}: type `{}` was skipped: {}", path, name, err),
+        None => format!("{}: no type named `{}`", path, name),
+    }
+}
+
+/// Checks whether `src` can be transmuted into `dst`, both declared in the
+/// file at `path`, or `&src` into `&dst` if `shared_ref` is set, and
+/// optionally prints the verdict as a JSON document.
+fn check_file(
+    path: &str, src: &str, dst: &str, as_json: bool, shared_ref: bool,
+) -> Result<(), String> {
+    let types = load_types(path)?;
+    let (prog_src, prog_dst) = compile_pair(path, &types, src, dst)?;
+    let mut execution = if shared_ref {
+        Execution::behind_shared_ref(&prog_dst, &prog_src)
+    } else {
+        Execution::new(&prog_dst, &prog_src)
+    };
+    execution.check().map_err(|err| format!("{}: {}", path, err))?;
+    if as_json {
+        println!("{}", json::save("verdict", &execution.verdict()));
+    } else {
+        print!("{}", execution.verdict());
+    }
+    Ok(())
+}
+
+/// Prints the product states explored checking whether `src` can be
+/// transmuted into `dst` as a Graphviz digraph.
+fn print_product(path: &str, src: &str, dst: &str) -> Result<(), String> {
+    let types = load_types(path)?;
+    let (prog_src, prog_dst) = compile_pair(path, &types, src, dst)?;
+    let mut execution = Execution::new(&prog_dst, &prog_src);
+    execution.check().map_err(|err| format!("{}: {}", path, err))?;
+    let mut dot = String::new();
+    execution.print_product_dot(&mut dot).map_err(|err| err.to_string())?;
+    print!("{}", dot);
+    Ok(())
+}
+
+/// Compiles `src` and `dst` from the `types` declared in the file at `path`.
+/// Compiled programs are reused from the directory named by
+/// `TRANSMUTE_VM_CACHE` when it is set.
+fn compile_pair(
+    path: &str, types: &BTreeMap<String, Ty>, src: &str, dst: &str,
+) -> Result<(Program, Program), String> {
+    let cache = cache::ProgramCache::from_env().transpose()
+        .map_err(|err| format!("{}: {}", cache::CACHE_DIR_ENV, err))?;
+    let endian = Endian::Little;
+    let unions = UnionSemantics::from_env()?;
+    let compile = |name: &str| {
+        let ty = types.get(name)
+            .ok_or_else(|| no_type_named(path, name))?;
+        let compiled = match &cache {
+            Some(cache) => cache.compile(ty, endian, unions, name, path),
+            None => Compiler::compile_with(ty, endian, unions, name)
+                .map_err(cache::CacheError::Compile),
+        };
+        compiled.map_err(|err| match err {
+            cache::CacheError::Io(err) => format!("{}: {}", cache::CACHE_DIR_ENV, err),
+            cache::CacheError::Compile(err) => format!("{}: compiling `{}`: {}", path, name, err),
+        })
+    };
+    Ok((compile(src)?, compile(dst)?))
+}
+
+/// Prints the bytes of `src` and `dst` side by side for every pair of
+/// variant paths.
+fn compare_file(path: &str, src: &str, dst: &str) -> Result<(), String> {
+    let types = load_types(path)?;
+    let (prog_src, prog_dst) = compile_pair(path, &types, src, dst)?;
+    let table = print::print_comparison(&prog_dst, &prog_src)
+        .map_err(|err| err.to_string())?;
+    print!("{}", table);
+    Ok(())
+}
+
+/// Runs the check of one manifest entry for the HTML report.
+fn check_entry(entry: &report::ManifestEntry) -> Result<report::Checked, String> {
+    let types = load_types(&entry.path)?;
+    let (prog_src, prog_dst) = compile_pair(&entry.path, &types, &entry.src, &entry.dst)?;
+    let layout = |name: &str| Printer::new().print_layout(&types[name], Endian::Little)
+        .map_err(|err| err.to_string());
+    let src_layout = layout(&entry.src)?;
+    let dst_layout = layout(&entry.dst)?;
+    let mut execution = Execution::new(&prog_dst, &prog_src);
+    execution.check().map_err(|err| err.to_string())?;
+    Ok(report::Checked {
+        verdict: execution.verdict(),
+        counterexamples: execution.counterexamples(),
+        src_layout,
+        dst_layout,
+    })
+}
+
+/// Checks every pair listed in the manifest at `path` and writes a
+/// self-contained HTML report to `out`.
+fn report_manifest(path: &str, out: &str) -> Result<(), String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("{}: {}", path, err))?;
+    let base = std::path::Path::new(path).parent().unwrap_or(std::path::Path::new(""));
+    let entries = report::parse_manifest(&text, base)
+        .map_err(|err| format!("{}: {}", path, err))?;
+    let results = entries.into_iter().map(|entry| {
+        let outcome = check_entry(&entry);
+        report::ReportEntry { entry, outcome }
+    }).collect::<Vec<_>>();
+    let html = report::html_report(&results).map_err(|err| err.to_string())?;
+    std::fs::write(out, html).map_err(|err| format!("{}: {}", out, err))
+}
+
+/// Prints the transmutability matrix of the types `names` declared in
+/// `path`, or of all of them if none are named.
+fn print_matrix(path: &str, names: &[String]) -> Result<(), String> {
+    let types = load_types(path)?;
+    let selected = if names.is_empty() {
+        types.iter().map(|(name, ty)| (name.as_str(), ty)).collect::<Vec<_>>()
+    } else {
+        names.iter().map(|name| types.get(name)
+            .map(|ty| (name.as_str(), ty))
+            .ok_or_else(|| no_type_named(path, name)))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    let matrix = matrix::check_matrix(&selected, Endian::Little, UnionSemantics::from_env()?);
+    print!("{}", matrix);
+    Ok(())
+}
+
+/// Prints the byte layout diagram of the type `name` declared in `path`.
+fn print_layout(path: &str, name: &str) -> Result<(), String> {
+    let types = load_types(path)?;
+    let ty = types.get(name)
+        .ok_or_else(|| no_type_named(path, name))?;
+    let layout = Printer::new().print_layout(ty, Endian::Little)
+        .map_err(|err| err.to_string())?;
+    print!("{}", layout);
+    Ok(())
+}
+
+/// Lists the programs in the cache named by `TRANSMUTE_VM_CACHE`, with the
+/// file each type was declared in.
+fn list_cache() -> Result<(), String> {
+    let cache = cache::ProgramCache::from_env()
+        .ok_or_else(|| format!("{} is not set", cache::CACHE_DIR_ENV))?
+        .map_err(|err| format!("{}: {}", cache::CACHE_DIR_ENV, err))?;
+    let entries = cache.entries()
+        .map_err(|err| format!("{}: {}", cache::CACHE_DIR_ENV, err))?;
+    for cached in entries {
+        println!("{:032x} {}: {} ({} instructions)",
+            cached.key, cached.source, cached.program.name(), cached.program.insts().len());
     }
+    Ok(())
 }
 
 #[allow(dead_code)]
 fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() > 1 {
+        let result = match &args[1..] {
+            [flag, path] if flag == "--dump-json" => load_types(path)
+                .map(|types| println!("{}", json::save("types", &types))),
+            [flag, path, name] if flag == "--layout" => print_layout(path, name),
+            [flag] if flag == "--list-cache" => list_cache(),
+            [flag, manifest, out] if flag == "--report" => report_manifest(manifest, out),
+            [flag, path, names @ ..] if flag == "--matrix" => print_matrix(path, names),
+            [flag, path, src, dst] if flag == "--json" => check_file(path, src, dst, true, false),
+            [flag, path, src, dst] if flag == "--shared-ref" => {
+                check_file(path, src, dst, false, true)
+            }
+            [flag, path, src, dst] if flag == "--compare" => compare_file(path, src, dst),
+            [flag, path, src, dst] if flag == "--dot" => print_product(path, src, dst),
+            [path, src, dst] => check_file(path, src, dst, false, false),
+            _ => Err(format!(
+                "usage: {0} [--json|--compare|--dot|--shared-ref] <types.rs|types.h|types.json|binary> <Src> <Dst>\n\
+                 \x20      {0} --dump-json <types.rs|types.h|types.json|binary>\n\
+                 \x20      {0} --layout <types.rs|types.h|types.json|binary> <Type>\n\
+                 \x20      {0} --report <manifest> <report.html>\n\
+                 \x20      {0} --matrix <types.rs|types.h|types.json|binary> [<Type>...]\n\
+                 \x20      {0} --list-cache\n\
+                 set {1} to a directory to reuse compiled programs\n\
+                 set {2} to variants (the default), bytes or provenance to pick what unions hold",
+                args[0], cache::CACHE_DIR_ENV, compiler::UNIONS_ENV)),
+        };
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
     // #[allow(dead_code)]
     // {
     //     use core::alloc::Layout;
@@ -157,6 +714,8 @@ fn main() {
                 a: bool,
                 b: u32,
                 c: [u8; 4],
+                #[valid(0..=1, 7)]
+                status: u8,
             }
         );
         derive_ty!(#[repr(C)] union Uni {
@@ -173,13 +732,12 @@ fn main() {
         println!("{}", printer.print_rust(&ty_bar).unwrap());
         println!("{}", printer.print_c(&ty_bar).unwrap());
         let mut compiler = Compiler::new(Endian::Little);
-        compiler.extend_from_ty(&ty_bar);
+        compiler.extend_from_ty(&ty_bar).unwrap();
         println!("comp layout: {:?}", compiler.layout);
-        let prog_bar = Program::new(compiler.insts, "Bar");
+        let prog_bar = Program::new(compiler.insts, compiler.types, "Bar");
         println!("program for bar: {:?}", prog_bar);
         // std::process::exit(0);
     }
-    println!("{:?}", core::mem::size_of::<inst::Inst>());
     // derive_ty!(#[repr(C)] struct Foo {
     //     fiedl0: u8,
     //     fiedl1: u32,
@@ -236,10 +794,10 @@ fn main() {
     // }
 
     let endian = if true { Endian::Little } else { Endian::Big };
-    let prog_dst = Compiler::compile(&StructDst::ty_of(), endian, "StructDst");
-    let prog_src = Compiler::compile(&StructSrc::ty_of(), endian, "StructSrc");
-    let mut execution = Execution::new(prog_dst, prog_src);
-    execution.check();
+    let prog_dst = Compiler::compile(&StructDst::ty_of(), endian, "StructDst").unwrap();
+    let prog_src = Compiler::compile(&StructSrc::ty_of(), endian, "StructSrc").unwrap();
+    let mut execution = Execution::new(&prog_dst, &prog_src);
+    execution.check().unwrap();
     // trait CanTransmuteInto<&Bar> for &Foo {}
     // trait CanTransmuteInto<Bar> for Foo {}
     // 1) implement Rust complier in OCaml
@@ -252,3 +810,557 @@ fn main() {
 
     */
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inst::InstOpaque;
+
+    /// Checks whether the type `a` declared in the Rust snippet `src` can be
+    /// transmuted into the type `b` declared there.
+    fn check(src: &str, a: &str, b: &str) -> Verdict {
+        check_with(src, a, b, Endian::Little, UnionSemantics::Variants, false)
+    }
+
+    /// `check` in another byte order, union semantics, or behind shared
+    /// references.
+    fn check_with(
+        src: &str, a: &str, b: &str, endian: Endian, unions: UnionSemantics, shared_ref: bool,
+    ) -> Verdict {
+        let types = parse::rust::parse_rust(src).unwrap();
+        verdict_with(&types[a], &types[b], endian, unions, shared_ref)
+    }
+
+    fn verdict(src: &Ty, dst: &Ty) -> Verdict {
+        verdict_with(src, dst, Endian::Little, UnionSemantics::Variants, false)
+    }
+
+    fn verdict_with(
+        src: &Ty, dst: &Ty, endian: Endian, unions: UnionSemantics, shared_ref: bool,
+    ) -> Verdict {
+        let src = Compiler::compile_with(src, endian, unions, "src").unwrap();
+        let dst = Compiler::compile_with(dst, endian, unions, "dst").unwrap();
+        let mut execution = if shared_ref {
+            Execution::behind_shared_ref(&dst, &src)
+        } else {
+            Execution::new(&dst, &src)
+        };
+        execution.check().unwrap();
+        execution.verdict()
+    }
+
+    #[test]
+    fn src_shorter_than_dst() {
+        assert!(!verdict(&u8::ty_of(), &u16::ty_of()).accepted());
+        assert!(verdict(&u16::ty_of(), &u16::ty_of()).accepted());
+    }
+
+    #[test]
+    fn uninhabited() {
+        let types = parse::rust::parse_rust("
+            enum Void {}
+            #[repr(C)] struct Never { a: u8, b: ! }
+        ").unwrap();
+        let empty = Ty::Union(Union::new("Empty"));
+        for uninhabited in [&types["Void"], &types["Never"], &empty] {
+            assert!(verdict(uninhabited, &u8::ty_of()).accepted());
+            let rejects = verdict(&u8::ty_of(), uninhabited).rejects;
+            assert!(matches!(rejects[..], [Reject { src: None, reason: AcceptState::NeverUninhabited, .. }]));
+        }
+        assert!(verdict(&types["Void"], &empty).accepted());
+    }
+
+    #[test]
+    #[allow(dead_code)]
+    fn primitive_repr_enum() {
+        derive_ty!(#[repr(u8)] enum E { A(u32), B { x: u16 } });
+        // RFC 2195: a union of `repr(C)` structs that start with the tag
+        derive_ty!(#[repr(C)] struct EA { tag: u8, a: u32 });
+        derive_ty!(#[repr(C)] struct EB { tag: u8, x: u16 });
+        derive_ty!(#[repr(C)] union Tagged { a: EA, b: EB });
+        let ty = E::ty_of();
+        assert_eq!(layout_of(&ty).unwrap(), core::alloc::Layout::new::<E>());
+        let Ty::Enum(ref en) = ty else { panic!("E is an enum") };
+        let offsets = en.variants.iter().map(|variant| match variant.payload {
+            Ty::Struct(ref st) => st.fields.iter().map(|field| field.offset).collect::<Vec<_>>(),
+            _ => panic!("variants are structs"),
+        }).collect::<Vec<_>>();
+        assert_eq!(offsets, [[4], [2]]);
+
+        assert!(verdict(&ty, &ty).accepted());
+        assert!(verdict(&ty, &Tagged::ty_of()).accepted());
+        assert!(!verdict(&ty, &EA::ty_of()).accepted());
+        assert!(!verdict(&Tagged::ty_of(), &ty).accepted());
+    }
+
+    #[test]
+    #[allow(dead_code)]
+    fn tag_mismatch() {
+        derive_ty!(#[repr(u8)] enum Src { A(u32), C(u32) });
+        derive_ty!(#[repr(u8)] enum Dst { A(u32) });
+        assert!(verdict(&Dst::ty_of(), &Src::ty_of()).accepted());
+        let rejects = verdict(&Src::ty_of(), &Dst::ty_of()).rejects;
+        assert!(matches!(rejects[..], [Reject { reason: AcceptState::NeverTagMismatch, .. }]));
+    }
+
+    #[test]
+    fn opaque() {
+        let opaque = |name: &str, size| {
+            let layout = core::alloc::Layout::from_size_align(size, 8).unwrap();
+            Ty::Opaque(Opaque::new(name, layout))
+        };
+        let program = Compiler::compile(&opaque("O", 24), Endian::Little, "O").unwrap();
+        assert!(matches!(program.insts()[..], [Inst::Span(_), Inst::Opaque(InstOpaque { size: 24, .. }), Inst::Accept]));
+
+        assert!(verdict(&opaque("O", 24), &opaque("O", 24)).accepted());
+        assert!(!verdict(&opaque("O", 24), &opaque("P", 24)).accepted());
+        assert!(!verdict(&opaque("O", 24), &opaque("O", 16)).accepted());
+        assert!(!verdict(&opaque("O", 8), &u64::ty_of()).accepted());
+        assert!(!verdict(&u64::ty_of(), &opaque("O", 8)).accepted());
+    }
+
+    #[test]
+    fn wrappers() {
+        use core::mem::{ManuallyDrop, MaybeUninit};
+        use core::num::Wrapping;
+        assert!(ManuallyDrop::<u32>::ty_of() == u32::ty_of());
+        assert!(Wrapping::<u16>::ty_of() == u16::ty_of());
+        assert!(core::marker::PhantomData::<u64>::ty_of() == Ty::Void);
+        // any bytes fit a MaybeUninit, which may itself be uninit
+        assert!(verdict(&u32::ty_of(), &MaybeUninit::<u32>::ty_of()).accepted());
+        assert!(verdict(&<[u8; 4]>::ty_of(), &MaybeUninit::<u32>::ty_of()).accepted());
+        let rejects = verdict(&MaybeUninit::<u32>::ty_of(), &u32::ty_of()).rejects;
+        assert!(rejects.iter().any(|rej| matches!(rej.reason, AcceptState::NeverReadUninit)));
+    }
+
+    #[test]
+    fn pointers() {
+        use core::ptr::NonNull;
+        let (ptr, non_null) = (<*mut u8>::ty_of(), NonNull::<u8>::ty_of());
+        assert!(verdict(&non_null, &ptr).accepted());
+        assert!(verdict(&non_null, &non_null).accepted());
+        assert!(verdict(&<*const u32>::ty_of(), &ptr).accepted());
+        // only the null pointer is rejected
+        let src = Compiler::compile(&ptr, Endian::Little, "src").unwrap();
+        let dst = Compiler::compile(&non_null, Endian::Little, "dst").unwrap();
+        let mut nullable = Execution::new(&dst, &src);
+        nullable.check().unwrap();
+        assert!(!nullable.verdict().accepted());
+        assert!(nullable.rejects_only_values());
+        // integers make pointers without provenance, but can't hold one
+        let addr = Ty::Int(core::mem::size_of::<usize>() as u32);
+        assert!(verdict(&addr, &ptr).accepted());
+        let rejects = verdict(&ptr, &addr).rejects;
+        assert!(!rejects.is_empty());
+        assert!(rejects.iter().all(|rej| matches!(rej.reason, AcceptState::NeverStripsProvenance)));
+    }
+
+    #[test]
+    fn scalar_ranges() {
+        use core::num::NonZeroU16;
+        let src = "
+            type Byte = u8;
+            type Int = u16;
+            type NonZero = NonZeroU16;
+            type NonZeroByte = NonZeroU8;
+            #[repr(transparent)]
+            #[rustc_layout_scalar_valid_range_start(1)]
+            #[rustc_layout_scalar_valid_range_end(12)]
+            struct Month(u8);
+            // 0xffff, 0x0000 and 0x0001
+            #[repr(transparent)]
+            #[rustc_layout_scalar_valid_range_start(0xffff)]
+            #[rustc_layout_scalar_valid_range_end(1)]
+            struct Wrap(u16);
+            #[repr(transparent)]
+            #[rustc_layout_scalar_valid_range_end(1)]
+            struct Small(u16);
+            #[repr(transparent)]
+            #[rustc_layout_scalar_valid_range_start(0x0001_00ff)]
+            #[rustc_layout_scalar_valid_range_end(0x00ff_0100)]
+            struct Range(u32);
+            #[repr(transparent)]
+            #[rustc_layout_scalar_valid_range_start(0x0001_0100)]
+            #[rustc_layout_scalar_valid_range_end(0x00ff_0100)]
+            struct Above(u32);
+            #[repr(transparent)]
+            #[rustc_layout_scalar_valid_range_start(0x0001_00ff)]
+            #[rustc_layout_scalar_valid_range_end(0x00ff_00ff)]
+            struct Below(u32);
+            #[repr(transparent)]
+            #[rustc_layout_scalar_valid_range_start(0x0001_0000)]
+            #[rustc_layout_scalar_valid_range_end(0)]
+            struct Wide(u32);
+        ";
+        assert!(parse::rust::parse_rust(src).unwrap()["NonZero"] == NonZeroU16::ty_of());
+        for endian in [Endian::Little, Endian::Big] {
+            let accepted = |a: &str, b: &str| {
+                check_with(src, a, b, endian, UnionSemantics::Variants, false).accepted()
+            };
+            assert!(accepted("NonZero", "Int"));
+            assert!(!accepted("Int", "NonZero"));
+            assert!(accepted("NonZero", "NonZero"));
+            assert!(accepted("Month", "NonZeroByte"));
+            assert!(!accepted("NonZeroByte", "Month"));
+            assert!(!accepted("Byte", "Month"));
+            assert!(accepted("Small", "Wrap"));
+            assert!(!accepted("Wrap", "Small"));
+            assert!(!accepted("Wrap", "NonZero"));
+            assert!(accepted("Range", "Range"));
+            assert!(!accepted("Range", "Above"));
+            assert!(!accepted("Range", "Below"));
+            assert!(accepted("Range", "Wide"));
+        }
+    }
+
+    #[test]
+    fn valid_annotations() {
+        derive_ty!(#[repr(C)] struct Status {
+            #[valid(0..=1, 7)]
+            status: u8,
+        });
+        derive_ty!(#[repr(C)] struct Flags {
+            #[valid(0)]
+            a: bool,
+            #[valid(1..=0x100, 0xffff)]
+            b: [u16; 2],
+        });
+        let src = "
+            type Byte = u8;
+            type Bool = bool;
+            #[repr(C)] struct Status {
+                #[valid(0..=1, 7)]
+                status: u8,
+            }
+            #[repr(C)] struct Flags {
+                #[valid(0)]
+                a: bool,
+                #[valid(1..=0x100, 0xffff)]
+                b: [u16; 2],
+            }
+            #[repr(C)] struct Halves { a: bool, b: [u16; 2] }
+            #[repr(C)] struct Narrow {
+                #[valid(0)]
+                a: bool,
+                #[valid(1..=0x100)]
+                b: [u16; 2],
+            }
+            #[repr(transparent)] struct Low(#[valid(0..=1)] u8);
+            #[repr(transparent)] struct Picked(#[valid(7, 1)] u8);
+            #[repr(transparent)] struct Middle(#[valid(1..=2)] u8);
+            // narrowing a bool past its values leaves nothing valid
+            #[repr(transparent)] struct Never(#[valid(2..=3)] bool);
+        ";
+        let types = parse::rust::parse_rust(src).unwrap();
+        assert!(types["Status"] == Status::ty_of());
+        assert!(types["Flags"] == Flags::ty_of());
+        let accepted = |a: &str, b: &str| check(src, a, b).accepted();
+        assert!(accepted("Status", "Byte"));
+        assert!(!accepted("Byte", "Status"));
+        assert!(accepted("Bool", "Status"));
+        assert!(accepted("Low", "Status"));
+        assert!(accepted("Picked", "Status"));
+        assert!(!accepted("Middle", "Status"));
+        assert!(accepted("Status", "Status"));
+
+        assert!(accepted("Flags", "Flags"));
+        assert!(accepted("Flags", "Halves"));
+        assert!(!accepted("Halves", "Flags"));
+        assert!(accepted("Narrow", "Flags"));
+
+        assert!(types["Never"].is_uninhabited());
+        assert!(!accepted("Byte", "Never"));
+        assert!(accepted("Never", "Status"));
+    }
+
+    #[test]
+    fn union_semantics() {
+        use UnionSemantics::*;
+        let src = "
+            type Int = u16;
+            #[repr(C)] union U { x: u16, y: bool }
+            #[repr(C, align(2))] struct Half { a: u8 }
+        ";
+        let accepted = |a: &str, b: &str| {
+            [Variants, Bytes, BytesWithProvenance]
+                .map(|unions| check_with(src, a, b, Endian::Little, unions, false).accepted())
+        };
+        assert_eq!(accepted("Int", "U"), [true; 3]);
+        // the second byte is padding, which only fits a union of bytes
+        assert_eq!(accepted("Half", "U"), [false, true, true]);
+        // the union may be a `bool` and padding, or any bytes, uninit included
+        assert_eq!(accepted("U", "Int"), [false; 3]);
+        assert_eq!(accepted("U", "U"), [true; 3]);
+
+        // private fields and pointers are out of reach of the parser
+        let accepted = |src: &Ty, dst: &Ty| {
+            [Variants, Bytes, BytesWithProvenance]
+                .map(|unions| verdict_with(src, dst, Endian::Little, unions, false).accepted())
+        };
+        let types = parse::rust::parse_rust(src).unwrap();
+        // private bytes stay unwritable
+        let mut private = Struct::new("Private");
+        private.add_field("u", true, types["U"].clone()).unwrap();
+        assert_eq!(accepted(&u16::ty_of(), &Ty::Struct(private)), [false; 3]);
+
+        let mut pointers = Union::new("P");
+        pointers.add_variant("p", false, <*const u8>::ty_of()).unwrap();
+        pointers.add_variant("a", false, Ty::Int(core::mem::size_of::<usize>() as u32)).unwrap();
+        let pointers = Ty::Union(pointers);
+        assert_eq!(accepted(&<*const u8>::ty_of(), &pointers), [true, false, true]);
+        let rejects = verdict_with(&<*const u8>::ty_of(), &pointers, Endian::Little, Bytes, false).rejects;
+        assert!(rejects.iter().all(|rej| matches!(rej.reason, AcceptState::NeverStripsProvenance)));
+    }
+
+    #[test]
+    fn shared_refs() {
+        use core::cell::Cell;
+        let src = "
+            type Int = u32;
+            type Bytes = [u8; 4];
+            type CellInt = Cell<u32>;
+            type CellBytes = UnsafeCell<[u8; 4]>;
+            type Atomic = AtomicU32;
+            #[repr(C)] struct A { a: UnsafeCell<u32> }
+            #[repr(C)] struct B { b: u32 }
+            #[repr(C)] union V { a: A, b: B }
+            #[repr(C)] union W { a: UnsafeCell<u16>, b: [u8; 4] }
+        ";
+        assert!(parse::rust::parse_rust(src).unwrap()["CellInt"] == Cell::<u32>::ty_of());
+        let shared = |a: &str, b: &str| {
+            check_with(src, a, b, Endian::Little, UnionSemantics::Variants, true)
+        };
+        // by value, the bytes line up
+        assert!(check(src, "CellInt", "Int").accepted());
+        assert!(shared("Int", "Int").accepted());
+        assert!(shared("CellInt", "Atomic").accepted());
+        assert!(shared("CellBytes", "CellInt").accepted());
+        for (a, b) in [("CellInt", "Int"), ("Int", "Atomic")] {
+            let rejects = shared(a, b).rejects;
+            assert!(!rejects.is_empty());
+            assert!(rejects.iter().all(|rej| matches!(rej.reason, AcceptState::NeverCellMismatch)));
+        }
+
+        // any field of a union can be written through `&V`, so all of its
+        // bytes are mutable once one field is a cell
+        assert!(!shared("B", "V").accepted());
+        assert!(!shared("V", "B").accepted());
+        assert!(shared("A", "V").accepted());
+        assert!(shared("V", "V").accepted());
+        // bytes past the cell field are mutable through another one
+        assert!(shared("CellBytes", "W").accepted());
+        assert!(check(src, "Bytes", "W").accepted());
+        assert!(!shared("Bytes", "W").accepted());
+    }
+
+    #[test]
+    fn spans_are_accepted_as_a_whole() {
+        let types = parse::rust::parse_rust("
+            #[repr(C)] union U { x: u8, y: bool }
+            #[repr(C)] struct A { a: [U; 4], b: u16 }
+            #[repr(C)] struct B { a: [U; 4], b: u16 }
+            #[repr(C)] struct S { a: A }
+            #[repr(C)] struct T { b: B }
+        ").unwrap();
+        let trace = |src: &str, dst: &str, known: &[(&str, &str)]| {
+            let src = Compiler::compile(&types[src], Endian::Little, src).unwrap();
+            let dst = Compiler::compile(&types[dst], Endian::Little, dst).unwrap();
+            let mut execution = Execution::new(&dst, &src);
+            for (src, dst) in known {
+                execution.known.entry(types[*src].clone()).or_default().insert(types[*dst].clone());
+            }
+            execution.check().unwrap();
+            assert!(execution.verdict().accepted());
+            execution.trace.len()
+        };
+        // A and B have the same members, only A is identical to A
+        assert_eq!(trace("A", "A", &[]), 0);
+        assert!(trace("B", "A", &[]) > 0);
+        // T only differs from S by its field being a B
+        assert!(trace("T", "S", &[]) > 0);
+        assert_eq!(trace("T", "S", &[("B", "A")]), 0);
+    }
+
+    #[test]
+    #[allow(dead_code)]
+    fn large_arrays() {
+        let compiled = |ty: &Ty| Compiler::compile(ty, Endian::Little, "a").unwrap().insts().to_vec();
+        let pairs = |first: Ty, count| {
+            let mut st = Struct::new("P");
+            st.add_field("a", false, first).unwrap();
+            st.add_field("b", false, u16::ty_of()).unwrap();
+            Ty::Array(Box::new(Array { element: Ty::Struct(st), count }))
+        };
+        // runs and repeats keep programs small however long the array
+        let (bytes, bools) = (<[u8; 65536]>::ty_of(), <[bool; 65536]>::ty_of());
+        assert!(matches!(compiled(&bytes)[..], [Inst::ByteRangeRun(_), Inst::Accept]));
+        let insts = compiled(&pairs(bool::ty_of(), 1 << 20));
+        assert!(insts.len() < 8);
+        assert!(insts.iter().any(|inst| matches!(inst, Inst::Repeat(_))));
+
+        // and are checked the way the unrolled bytes would be
+        assert!(!verdict(&bytes, &bools).accepted());
+        assert!(verdict(&bools, &bytes).accepted());
+        // a run is narrowed as a whole, with one alternate and one reject
+        let dst = Compiler::compile(&<[bool; 4194304]>::ty_of(), Endian::Little, "dst").unwrap();
+        let src = Compiler::compile(&<[u8; 4194304]>::ty_of(), Endian::Little, "src").unwrap();
+        let mut execution = Execution::new(&dst, &src);
+        execution.check().unwrap();
+        assert_eq!(execution.src.inst_count(), 8);
+        assert_eq!(execution.verdict().rejects.len(), 1);
+        // a counterexample has a byte for each one a run stepped through
+        derive_ty!(#[repr(C)] struct Tail { a: [bool; 4096], b: u8 });
+        derive_ty!(#[repr(C)] struct BoolTail { a: [u8; 4096], b: bool });
+        let dst = Compiler::compile(&BoolTail::ty_of(), Endian::Little, "BoolTail").unwrap();
+        let src = Compiler::compile(&Tail::ty_of(), Endian::Little, "Tail").unwrap();
+        let mut execution = Execution::new(&dst, &src);
+        execution.check().unwrap();
+        let counterexamples = execution.counterexamples();
+        assert!(matches!(counterexamples[..], [Counterexample { ref bytes, .. }]
+            if bytes.len() == 4097 && bytes[4096] == Some(2)));
+        assert!(!verdict(&pairs(u8::ty_of(), 1024), &pairs(bool::ty_of(), 1024)).accepted());
+        assert!(verdict(&pairs(bool::ty_of(), 1024), &pairs(u8::ty_of(), 1024)).accepted());
+        assert!(!verdict(&pairs(bool::ty_of(), 1024), &pairs(bool::ty_of(), 1025)).accepted());
+
+        // a byte narrowed in a body is narrowed at its position only
+        let halves = |ranges| Ty::Array(Box::new(Array {
+            element: u16::ty_of().narrowed(ranges),
+            count: 1024,
+        }));
+        let (low, wide) = (halves(vec![(1, 0x100)]), halves(vec![(1, 0x100), (0xffff, 0xffff)]));
+        assert!(verdict(&low, &wide).accepted());
+        assert!(!verdict(&wide, &low).accepted());
+    }
+
+    #[test]
+    fn narrowed_runs() {
+        let types = parse::rust::parse_rust("
+            #[repr(C)] struct S { a: [u8; 8] }
+            #[repr(C)] struct Y { a: [u8; 3], b: V }
+            #[repr(C)] union V { p: [bool; 5], q: [u8; 5] }
+            #[repr(C)] union U { x: [bool; 8], y: Y }
+            #[repr(C)] struct Y2 { a: [u8; 3], b: V2 }
+            #[repr(C)] union V2 { p: [bool; 5], q: [bool; 5] }
+            #[repr(C)] union U2 { x: [bool; 8], y: Y2 }
+            #[repr(C)] union U3 { y: Y, x: [bool; 8] }
+            #[repr(C)] struct Y4 { a: [bool; 3], b: V }
+            #[repr(C)] union U5 { x: [bool; 8], y: Y4 }
+            #[repr(C)] struct T { a: [bool; 2], b: [u8; 2] }
+            #[repr(C)] union W { a: [bool; 2], b: [u8; 3] }
+            #[repr(C)] union A { f0: [W; 1], f1: T }
+            #[repr(C)] union B { f0: T, f1: [u8; 4], f2: bool }
+        ").unwrap();
+        let verdict = |src: &str, dst: &str| verdict(&types[src], &types[dst]).accepted();
+        // the values of a run a variant rejects are checked by the others,
+        // whichever variant narrowed the run first
+        assert!(verdict("S", "U"));
+        assert!(verdict("S", "U3"));
+        assert!(!verdict("S", "U2"));
+        assert!(!verdict("S", "U5"));
+        // and so are the values of a run's alternate another variant narrows
+        assert!(!verdict("A", "B"));
+    }
+
+    #[test]
+    fn program_positions() {
+        let types = parse::rust::parse_rust("
+            #[repr(C)] struct P { a: u8, b: u32 }
+            #[repr(C)] struct A { x: [P; 3], y: u16 }
+            #[repr(C)] union U { a: u8, b: u16 }
+            #[repr(C)] struct E { x: [[P; 2]; 3], y: u8 }
+        ").unwrap();
+        let compile = |name: &str| Compiler::compile(&types[name], Endian::Little, name).unwrap();
+        let positions = |name: &str| {
+            let program = compile(name);
+            program.insts().iter().cloned().zip(program.positions()).collect::<Vec<_>>()
+        };
+        // a repeat body is at its first iteration, what follows it after the last
+        assert!(matches!(positions("A")[..], [
+            (Inst::Span(_), 0), (Inst::Repeat(_), 0), (Inst::Span(_), 0),
+            (Inst::ByteRange(_), 0), (Inst::UninitRun(3), 1), (Inst::ByteRangeRun(_), 4),
+            (Inst::ByteRangeRun(_), 24), (Inst::UninitRun(2), 26), (Inst::Accept, 28),
+        ]));
+        // every variant of a union starts where the union does
+        assert!(matches!(positions("U")[..], [
+            (Inst::Span(_), 0), (Inst::Split(_), 0), (Inst::ByteRange(_), 0),
+            (Inst::Uninit, 1), (Inst::JoinGoto(_), 2), (Inst::ByteRangeRun(_), 0), (Inst::Accept, 2),
+        ]));
+        assert!(matches!(positions("E")[..], [.., (Inst::UninitRun(3), 49), (Inst::Accept, 52)]));
+
+        let mut dot = String::new();
+        compile("A").print_dot(&mut dot).unwrap();
+        assert!(dot.contains("\"A_ip_6\" [shape=ellipse, label=\"pos=24, ip6\"];"));
+        assert!(dot.contains("\"A_ip_1\" -> \"A_ip_2\" [label=\"repeat x3\"];"));
+        assert!(dot.contains("\"A_ip_8\" -> \"A_accepting\";"));
+    }
+
+    #[test]
+    fn shared_programs() {
+        fn sync<T: Sync>(_: &T) {}
+        let types = parse::rust::parse_rust("
+            #[repr(C)] union U { x: u8, y: bool }
+            #[repr(C)] struct A { a: [U; 64] }
+            #[repr(C)] struct B { a: [bool; 64] }
+            #[repr(C)] struct C { a: [u8; 64] }
+            #[repr(C)] struct D { a: [bool; 63], b: u8 }
+        ").unwrap();
+        let compile = |name: &str| Compiler::compile(&types[name], Endian::Little, name).unwrap();
+        let src = compile("A");
+        sync(&src);
+        let compiled = format!("{:?}", src);
+        // the checks fork and narrow src, each in a cursor of its own
+        let dsts = ["B", "C", "D", "A"].map(compile);
+        let check = |dst| {
+            let mut execution = Execution::new(dst, &src);
+            execution.check().unwrap();
+            execution.verdict().accepted()
+        };
+        let verdicts = std::thread::scope(|scope| {
+            let checks = dsts.iter().map(|dst| scope.spawn(move || check(dst))).collect::<Vec<_>>();
+            checks.into_iter().map(|check| check.join().unwrap()).collect::<Vec<_>>()
+        });
+        assert_eq!(verdicts, [false, true, false, true]);
+        assert_eq!(format!("{:?}", src), compiled);
+        assert_eq!(dsts.iter().map(check).collect::<Vec<_>>(), verdicts);
+    }
+
+    #[test]
+    fn product_dot() {
+        let types = parse::rust::parse_rust("
+            #[repr(C)] union U { x: u8, y: bool }
+            #[repr(C)] struct A { a: U, b: u8 }
+            #[repr(C)] struct B { a: bool, b: u16 }
+        ").unwrap();
+        let src = Compiler::compile(&types["A"], Endian::Little, "A").unwrap();
+        let dst = Compiler::compile(&types["B"], Endian::Little, "B").unwrap();
+        let mut execution = Execution::new(&dst, &src);
+        execution.check().unwrap();
+        let mut dot = String::new();
+        execution.print_product_dot(&mut dot).unwrap();
+        assert_eq!(dot, r#"digraph product {
+  start [shape=point];
+  subgraph cluster_0 {
+    label="variant 0";
+    p0_d1_s3 [shape=ellipse, label="pos=0\ndst ip1\nsrc ip3"];
+    p1_d2_s6 [shape=ellipse, label="pos=1\ndst ip2\nsrc ip6"];
+    p2_d3_end [shape=ellipse, label="pos=2\ndst ip3\nsrc end"];
+  }
+  subgraph cluster_1 {
+    label="variant 1";
+    p0_d1_s8 [shape=ellipse, label="pos=0\ndst ip1\nsrc ip8"];
+  }
+  subgraph cluster_2 {
+    label="variant 2";
+    p0_d1_s5 [shape=ellipse, label="pos=0\ndst ip1\nsrc ip5"];
+  }
+  start -> p0_d1_s3 [color=darkgreen, label="always"];
+  p0_d1_s3 -> p1_d2_s6 [color=darkgreen, label="always"];
+  p1_d2_s6 -> p2_d3_end [color=red, label="never: reads uninit"];
+  start -> p0_d1_s8 [color=red, label="never: 0x02-0xff outside of 0x00-0x01"];
+  start -> p0_d1_s5 [color=darkgreen, label="always"];
+  p0_d1_s5 -> p1_d2_s6 [color=darkgreen, label="always"];
+  p1_d2_s6 -> p2_d3_end [color=red, label="never: reads uninit"];
+}
+"#);
+    }
+}
@@ -0,0 +1,202 @@
+use core::fmt::{self, Write};
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+
+use crate::compiler::{Compiler, UnionSemantics};
+use crate::error::Error;
+use crate::inst::Program;
+use crate::ty::{layout_of, Endian, Ty};
+use crate::Execution;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transmutability {
+    Yes,
+    No,
+    /// Only some values are rejected, e.g. a range check is needed.
+    Maybe,
+    /// The pair couldn't be checked, see `Matrix::errors`.
+    Error,
+}
+
+impl Transmutability {
+    fn of(execution: &Execution) -> Self {
+        if execution.verdict().accepted() {
+            Transmutability::Yes
+        } else if execution.rejects_only_values() {
+            Transmutability::Maybe
+        } else {
+            Transmutability::No
+        }
+    }
+}
+
+impl fmt::Display for Transmutability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Transmutability::Yes => "yes",
+            Transmutability::No => "no",
+            Transmutability::Maybe => "maybe",
+            Transmutability::Error => "error",
+        })
+    }
+}
+
+/// Transmutability of every pair among a list of types.
+pub struct Matrix {
+    pub names: Vec<String>,
+    /// `cells[src][dst]`
+    pub cells: Vec<Vec<Transmutability>>,
+    /// Why each `Error` cell couldn't be checked, as `(src, dst, error)`.
+    pub errors: Vec<(usize, usize, Error)>,
+}
+
+/// Checks every `(src, dst)` pair among `types`. Each type is compiled
+/// once, and a pair is checked once for all pairs of types structurally
+/// equal to it. Accepted pairs are shared with the later checks, which
+/// accept members of those types at the same position as a whole.
+/// Cells are by-value transmutes, so a src larger than dst is `No` even
+/// though the checker accepts dst reading a prefix of it. A pair that
+/// fails to check, e.g. because of a pointer, is marked as such, the
+/// others are still checked.
+pub fn check_matrix(types: &[(&str, &Ty)], endian: Endian, unions: UnionSemantics) -> Matrix {
+    // map every type to the first structurally equal one
+    let repr = types.iter().enumerate()
+        .map(|(idx, (_, ty))| types.iter().position(|(_, other)| *other == *ty).unwrap_or(idx))
+        .collect::<Vec<_>>();
+    let mut compiled = HashMap::<usize, Result<Program, Error>>::new();
+    for &idx in repr.iter() {
+        if let Entry::Vacant(entry) = compiled.entry(idx) {
+            let (name, ty) = types[idx];
+            entry.insert(Compiler::compile_with(ty, endian, unions, name));
+        }
+    }
+    let program = |idx: usize| compiled[&repr[idx]].as_ref().map_err(Error::clone);
+    type Known = HashMap<Ty, HashSet<Ty>>;
+    let check = |src: usize, dst: usize, known: &mut Known| -> Result<Transmutability, Error> {
+        if layout_of(types[src].1)?.size() > layout_of(types[dst].1)?.size() {
+            return Ok(Transmutability::No);
+        }
+        let mut execution = Execution::new(program(dst)?, program(src)?);
+        execution.known = std::mem::take(known);
+        let checked = execution.check();
+        *known = std::mem::take(&mut execution.known);
+        checked?;
+        let cell = Transmutability::of(&execution);
+        if cell == Transmutability::Yes {
+            known.entry(types[src].1.clone()).or_default().insert(types[dst].1.clone());
+        }
+        Ok(cell)
+    };
+
+    let mut known = HashMap::new();
+    let mut memo = HashMap::<(usize, usize), Result<Transmutability, Error>>::new();
+    let mut cells = Vec::with_capacity(types.len());
+    let mut errors = Vec::new();
+    for src in 0..types.len() {
+        let mut row = Vec::with_capacity(types.len());
+        for dst in 0..types.len() {
+            let result = memo.entry((repr[src], repr[dst]))
+                .or_insert_with(|| check(src, dst, &mut known));
+            row.push(match result {
+                Ok(cell) => *cell,
+                Err(err) => {
+                    errors.push((src, dst, err.clone()));
+                    Transmutability::Error
+                }
+            });
+        }
+        cells.push(row);
+    }
+    Matrix {
+        names: types.iter().map(|(name, _)| name.to_string()).collect(),
+        cells,
+        errors,
+    }
+}
+
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let corner = "src \\ dst";
+        let first = self.names.iter().map(|name| name.len())
+            .chain(Some(corner.len())).max().unwrap_or(0);
+        let widths = self.names.iter().map(|name| name.len().max("maybe".len()))
+            .collect::<Vec<_>>();
+        let mut line = String::new();
+        write!(line, "{:<width$}", corner, width = first)?;
+        for (name, &width) in self.names.iter().zip(&widths) {
+            write!(line, " | {:<width$}", name, width = width)?;
+        }
+        writeln!(f, "{}", line.trim_end())?;
+        for (name, row) in self.names.iter().zip(&self.cells) {
+            line.clear();
+            write!(line, "{:<width$}", name, width = first)?;
+            for (cell, &width) in row.iter().zip(&widths) {
+                write!(line, " | {:<width$}", cell, width = width)?;
+            }
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        for &(src, dst, ref err) in &self.errors {
+            writeln!(f, "{} -> {}: {}", self.names[src], self.names[dst], err)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::derive::InspectTy;
+
+    #[test]
+    fn cells() {
+        let (u8_ty, bool_ty, u32_ty) = (u8::ty_of(), bool::ty_of(), u32::ty_of());
+        let types = [("u8", &u8_ty), ("bool", &bool_ty), ("u32", &u32_ty)];
+        let matrix = check_matrix(&types, Endian::Little, UnionSemantics::Variants);
+        use Transmutability::*;
+        assert_eq!(matrix.cells, [
+            [Yes, Maybe, No],
+            [Yes, Yes, No],
+            [No, No, Yes],
+        ]);
+        assert_eq!(matrix.to_string(), "\
+src \\ dst | u8    | bool  | u32
+u8        | yes   | maybe | no
+bool      | yes   | yes   | no
+u32       | no    | no    | yes
+");
+    }
+
+    #[test]
+    fn shared_pairs() {
+        let types = crate::parse::rust::parse_rust("
+            #[repr(C)] struct A { a: u8, b: bool }
+            #[repr(C)] struct B { a: u8, b: u8 }
+            #[repr(C)] struct S { a: A }
+            #[repr(C)] struct T { b: B }
+        ").unwrap();
+        let types = ["A", "B", "S", "T"].map(|name| (name, &types[name]));
+        let matrix = check_matrix(&types, Endian::Little, UnionSemantics::Variants);
+        use Transmutability::*;
+        // B -> A is not a known pair, T -> S still needs a range check
+        assert_eq!(matrix.cells, [
+            [Yes, Yes, Yes, Yes],
+            [Maybe, Yes, Maybe, Yes],
+            [Yes, Yes, Yes, Yes],
+            [Maybe, Yes, Maybe, Yes],
+        ]);
+    }
+
+    #[test]
+    fn unsupported_pairs_are_errors() {
+        let (u8_ty, r) = (u8::ty_of(), <&u8 as InspectTy>::ty_of());
+        let types = [("u8", &u8_ty), ("ref", &r)];
+        let matrix = check_matrix(&types, Endian::Little, UnionSemantics::Variants);
+        use Transmutability::*;
+        assert_eq!(matrix.cells, [[Yes, Error], [Error, Error]]);
+        assert_eq!(matrix.to_string().lines().skip(3).collect::<Vec<_>>(), [
+            "u8 -> ref: reference is not supported",
+            "ref -> u8: reference is not supported",
+            "ref -> ref: reference is not supported",
+        ]);
+    }
+}
@@ -0,0 +1,705 @@
+use std::collections::BTreeMap;
+
+use crate::error::Error;
+use crate::ty::*;
+use super::{tokenize, Cursor, ParseError, Token};
+
+/// The result of parsing a type specifier: struct and union tags may be
+/// declared before they are defined, and only become usable by value later.
+#[derive(Clone)]
+enum Spec {
+    Ty(Ty),
+    Incomplete(String),
+}
+
+#[derive(Default)]
+struct Attrs {
+    packed: bool,
+    align: Option<usize>,
+}
+
+/// Largest fundamental alignment, used by a bare `__attribute__((aligned))`.
+const MAX_ALIGN: usize = 16;
+
+/// Parses C type declarations: `typedef`s, `struct`, `union` and `enum`
+/// definitions, fixed-size arrays, `stdint.h` integers and `_Bool`, with
+/// `__attribute__((packed, aligned(N)))`, assuming an LP64 target.
+/// Every complete struct, union and enum tag and every typedef is returned
+/// under its own name, typedefs taking precedence over tags. Enums are the
+/// integers they are stored in.
+/// Preprocessor directives are skipped; function declarations and variables
+/// are skipped as well.
+pub fn parse_c(src: &str) -> Result<BTreeMap<String, Ty>, ParseError> {
+    for (idx, line) in src.lines().enumerate() {
+        let directive = line.trim_start().trim_start_matches('#').trim_start();
+        if line.trim_start().starts_with('#') && directive.starts_with("pragma pack") {
+            return Err(ParseError::new(idx + 1, "`#pragma pack` is not supported"));
+        }
+    }
+    let mut parser = Parser {
+        cursor: Cursor::new(tokenize(src, true)?),
+        tags: BTreeMap::new(),
+        typedefs: BTreeMap::new(),
+        constants: BTreeMap::new(),
+    };
+    parser.parse()?;
+
+    let mut types = BTreeMap::new();
+    for (name, tag) in parser.tags.iter() {
+        if let Some(ty) = tag {
+            types.insert(name.clone(), ty.clone());
+        }
+    }
+    for (name, spec) in parser.typedefs.iter() {
+        match spec {
+            Spec::Ty(ty) => { types.insert(name.clone(), ty.clone()); }
+            Spec::Incomplete(tag) => {
+                if let Some(Some(ty)) = parser.tags.get(tag) {
+                    types.insert(name.clone(), ty.clone());
+                }
+            }
+        }
+    }
+    Ok(types)
+}
+
+struct Parser {
+    cursor: Cursor,
+    /// Struct, union and enum tags, `None` until the definition is seen.
+    tags: BTreeMap<String, Option<Ty>>,
+    typedefs: BTreeMap<String, Spec>,
+    /// Enumeration constants, usable in array sizes and other enumerators.
+    constants: BTreeMap<String, i128>,
+}
+
+impl Parser {
+    fn parse(&mut self) -> Result<(), ParseError> {
+        while !self.cursor.is_empty() {
+            // `extern "C" {` blocks from C++-compatible headers
+            if self.cursor.eat_ident("extern") && self.cursor.eat_punct('"') {
+                self.cursor.expect_ident()?;
+                self.cursor.expect_punct('"')?;
+                self.cursor.eat_punct('{');
+                continue;
+            }
+            if self.cursor.eat_punct(';') || self.cursor.eat_punct('}') {
+                continue;
+            }
+            if self.cursor.eat_ident("typedef") {
+                let mut attrs = self.parse_attrs()?;
+                let spec = self.parse_specifier(&mut attrs)?;
+                loop {
+                    let (name, spec) = self.parse_declarator(spec.clone())?;
+                    let mut attrs = self.parse_attrs()?;
+                    let spec = match spec {
+                        Spec::Ty(ty) => Spec::Ty(self.apply_typedef(&name, ty, &mut attrs)?),
+                        incomplete => incomplete,
+                    };
+                    self.typedefs.insert(name, spec);
+                    if !self.cursor.eat_punct(',') {
+                        break;
+                    }
+                }
+                self.cursor.expect_punct(';')?;
+                continue;
+            }
+            let mut attrs = self.parse_attrs()?;
+            self.parse_specifier(&mut attrs)?;
+            if !self.cursor.eat_punct(';') {
+                self.skip_declaration()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips a variable or function declaration, including a function body.
+    fn skip_declaration(&mut self) -> Result<(), ParseError> {
+        loop {
+            match self.cursor.next() {
+                Some(Token::Punct(';')) => return Ok(()),
+                Some(Token::Punct('(')) => self.cursor.skip_group('(', ')')?,
+                Some(Token::Punct('{')) => return self.cursor.skip_group('{', '}'),
+                Some(_) => {}
+                None => return Err(self.cursor.unexpected("`;`")),
+            }
+        }
+    }
+
+    /// Gives anonymous structs and unions the name of their typedef, and
+    /// applies attributes placed after the declarator.
+    fn apply_typedef(&self, name: &str, ty: Ty, attrs: &mut Attrs) -> Result<Ty, ParseError> {
+        if attrs.packed {
+            return Err(self.cursor.error(format!(
+                "`packed` on typedef `{}` must be placed on the struct", name)));
+        }
+        Ok(match ty {
+            Ty::Struct(mut st) => {
+                if st.name.is_empty() { st.name = name.to_string(); }
+                if let Some(align) = attrs.align.take() {
+                    st = self.realign_struct(st, align)?;
+                }
+                Ty::Struct(st)
+            }
+            Ty::Union(mut un) => {
+                if un.name.is_empty() { un.name = name.to_string(); }
+                if let Some(align) = attrs.align.take() {
+                    un = self.realign_union(un, align)?;
+                }
+                Ty::Union(un)
+            }
+            _ if attrs.align.is_some() => {
+                return Err(self.cursor.error(format!(
+                    "`aligned` on typedef `{}` is only supported for structs and unions", name)));
+            }
+            ty => ty,
+        })
+    }
+
+    fn realign_struct(&self, st: Struct, align: usize) -> Result<Struct, ParseError> {
+        let align = st.align.map_or(align, |old| old.max(align));
+        let mut realigned = Struct::with_repr(st.name, st.pack, Some(align))
+            .map_err(|err| self.layout_error(err))?;
+        for field in st.fields {
+            realigned.add_field(field.name, field.private, field.ty)
+                .map_err(|err| self.layout_error(err))?;
+        }
+        Ok(realigned)
+    }
+
+    fn realign_union(&self, un: Union, align: usize) -> Result<Union, ParseError> {
+        let align = un.align.map_or(align, |old| old.max(align));
+        let mut realigned = Union::with_repr(un.name, un.pack, Some(align))
+            .map_err(|err| self.layout_error(err))?;
+        for variant in un.variants {
+            realigned.add_variant(variant.name, variant.private, variant.ty)
+                .map_err(|err| self.layout_error(err))?;
+        }
+        Ok(realigned)
+    }
+
+    fn layout_error(&self, err: Error) -> ParseError {
+        self.cursor.error(err.to_string())
+    }
+
+    /// Parses any number of `__attribute__((...))` lists.
+    fn parse_attrs(&mut self) -> Result<Attrs, ParseError> {
+        let mut attrs = Attrs::default();
+        self.parse_more_attrs(&mut attrs)?;
+        Ok(attrs)
+    }
+
+    fn parse_more_attrs(&mut self, attrs: &mut Attrs) -> Result<(), ParseError> {
+        while self.cursor.eat_ident("__attribute__") || self.cursor.eat_ident("__attribute") {
+            self.cursor.expect_punct('(')?;
+            self.cursor.expect_punct('(')?;
+            while !self.cursor.eat_punct(')') {
+                let name = self.cursor.expect_ident()?;
+                match name.trim_matches('_') {
+                    "packed" => attrs.packed = true,
+                    "aligned" => {
+                        let align = if self.cursor.eat_punct('(') {
+                            let align = self.parse_const()?;
+                            self.cursor.expect_punct(')')?;
+                            match usize::try_from(align) {
+                                Ok(align) if align.is_power_of_two() => align,
+                                _ => {
+                                    return Err(self.cursor.error(format!(
+                                        "`{}` is not a power of two", align)));
+                                }
+                            }
+                        } else {
+                            MAX_ALIGN
+                        };
+                        attrs.align = Some(attrs.align.map_or(align, |old| old.max(align)));
+                    }
+                    _ => {
+                        if self.cursor.eat_punct('(') {
+                            self.cursor.skip_group('(', ')')?;
+                        }
+                    }
+                }
+                if !self.cursor.eat_punct(',') {
+                    self.cursor.expect_punct(')')?;
+                    break;
+                }
+            }
+            self.cursor.expect_punct(')')?;
+        }
+        Ok(())
+    }
+
+    fn parse_specifier(&mut self, attrs: &mut Attrs) -> Result<Spec, ParseError> {
+        const QUALIFIERS: &[&str] = &[
+            "const", "volatile", "restrict", "static", "extern", "inline",
+            "register", "__inline", "__inline__", "__extension__",
+        ];
+        while QUALIFIERS.iter().any(|q| self.cursor.eat_ident(q)) {}
+        self.parse_more_attrs(attrs)?;
+
+        if self.cursor.eat_ident("struct") {
+            return self.parse_record(attrs, false);
+        }
+        if self.cursor.eat_ident("union") {
+            return self.parse_record(attrs, true);
+        }
+        if self.cursor.eat_ident("enum") {
+            return self.parse_enum(attrs);
+        }
+
+        let mut words = Vec::new();
+        while let Some(Token::Ident(word)) = self.cursor.peek() {
+            let keyword = matches!(word.as_str(),
+                "signed" | "unsigned" | "char" | "short" | "int" | "long"
+                | "_Bool" | "float" | "double" | "void");
+            if QUALIFIERS.contains(&word.as_str()) {
+                self.cursor.next();
+            } else if keyword {
+                words.push(word.clone());
+                self.cursor.next();
+            } else {
+                break;
+            }
+        }
+        if !words.is_empty() {
+            return self.primitive(&words).map(Spec::Ty);
+        }
+
+        let name = self.cursor.expect_ident()?;
+        match self.typedefs.get(&name) {
+            Some(Spec::Incomplete(tag)) => {
+                // the tag may have been defined after the typedef
+                return Ok(match self.tags.get(tag) {
+                    Some(Some(ty)) => Spec::Ty(ty.clone()),
+                    _ => Spec::Incomplete(tag.clone()),
+                });
+            }
+            Some(spec) => return Ok(spec.clone()),
+            None => {}
+        }
+        let ty = match name.as_str() {
+            "uint8_t" | "int8_t" | "u8" | "i8" => Ty::Int(1),
+            "uint16_t" | "int16_t" | "u16" | "i16" => Ty::Int(2),
+            "uint32_t" | "int32_t" | "u32" | "i32" => Ty::Int(4),
+            "uint64_t" | "int64_t" | "u64" | "i64" => Ty::Int(8),
+            "__uint128_t" | "__int128_t" | "u128" | "i128" => Ty::Int(16),
+            "size_t" | "ssize_t" | "ptrdiff_t" | "intptr_t" | "uintptr_t" => Ty::Int(8),
+            "bool" => Ty::Bool,
+            // the zero-sized type `Printer::print_c` names
+            "Zst" => Ty::Void,
+            _ => return Err(self.cursor.error(format!("unknown type `{}`", name))),
+        };
+        Ok(Spec::Ty(ty))
+    }
+
+    fn primitive(&self, words: &[String]) -> Result<Ty, ParseError> {
+        let has = |word: &str| words.iter().any(|w| w == word);
+        let longs = words.iter().filter(|w| *w == "long").count();
+        let ty = if has("void") {
+            Ty::Void
+        } else if has("_Bool") {
+            Ty::Bool
+        } else if has("char") {
+            Ty::Int(1)
+        } else if has("short") {
+            Ty::Int(2)
+        } else if has("float") {
+            Ty::Int(4)
+        } else if has("double") {
+            if longs > 0 {
+                return Err(self.cursor.error("`long double` is not supported"));
+            }
+            Ty::Int(8)
+        } else if longs > 0 {
+            Ty::Int(8)
+        } else {
+            Ty::Int(4)
+        };
+        Ok(ty)
+    }
+
+    fn parse_record(&mut self, attrs: &mut Attrs, is_union: bool) -> Result<Spec, ParseError> {
+        self.parse_more_attrs(attrs)?;
+        let tag = match self.cursor.peek() {
+            Some(Token::Ident(_)) => Some(self.cursor.expect_ident()?),
+            _ => None,
+        };
+        self.parse_more_attrs(attrs)?;
+        if !self.cursor.eat_punct('{') {
+            let tag = tag.ok_or_else(|| self.cursor.unexpected("`{`"))?;
+            return Ok(match self.tags.get(&tag) {
+                Some(Some(ty)) => Spec::Ty(ty.clone()),
+                Some(None) => Spec::Incomplete(tag),
+                None => {
+                    self.tags.insert(tag.clone(), None);
+                    Spec::Incomplete(tag)
+                }
+            });
+        }
+
+        let mut members = Vec::new();
+        while !self.cursor.eat_punct('}') {
+            let mut member_attrs = self.parse_attrs()?;
+            let spec = self.parse_specifier(&mut member_attrs)?;
+            if self.cursor.eat_punct(';') {
+                // C11 anonymous struct or union member
+                match spec {
+                    Spec::Ty(ty @ Ty::Struct(_)) | Spec::Ty(ty @ Ty::Union(_)) => {
+                        members.push((String::new(), ty));
+                        continue;
+                    }
+                    _ => return Err(self.cursor.error("declaration declares nothing")),
+                }
+            }
+            loop {
+                let (name, spec) = self.parse_declarator(spec.clone())?;
+                self.parse_more_attrs(&mut member_attrs)?;
+                if member_attrs.packed || member_attrs.align.is_some() {
+                    return Err(self.cursor.error(format!(
+                        "attributes on member `{}` are not supported", name)));
+                }
+                match spec {
+                    Spec::Ty(ty) => members.push((name, ty)),
+                    Spec::Incomplete(tag) => {
+                        return Err(self.cursor.error(format!(
+                            "member `{}` has incomplete type `{}`", name, tag)));
+                    }
+                }
+                if !self.cursor.eat_punct(',') {
+                    break;
+                }
+            }
+            self.cursor.expect_punct(';')?;
+        }
+        self.parse_more_attrs(attrs)?;
+
+        let name = tag.clone().unwrap_or_default();
+        let pack = if attrs.packed { Some(1) } else { None };
+        let align = attrs.align.take();
+        let ty = if is_union {
+            // an empty union (a GNU extension) has no members to hold a value
+            let mut un = Union::with_repr(name, pack, align)
+                .map_err(|err| self.layout_error(err))?;
+            for (member, ty) in members {
+                un.add_variant(member, false, ty).map_err(|err| self.layout_error(err))?;
+            }
+            Ty::Union(un)
+        } else {
+            let mut st = Struct::with_repr(name, pack, align)
+                .map_err(|err| self.layout_error(err))?;
+            for (member, ty) in members {
+                st.add_field(member, false, ty).map_err(|err| self.layout_error(err))?;
+            }
+            Ty::Struct(st)
+        };
+        if let Some(tag) = tag {
+            self.tags.insert(tag, Some(ty.clone()));
+        }
+        Ok(Spec::Ty(ty))
+    }
+
+    fn parse_enum(&mut self, attrs: &mut Attrs) -> Result<Spec, ParseError> {
+        self.parse_more_attrs(attrs)?;
+        let tag = match self.cursor.peek() {
+            Some(Token::Ident(_)) => Some(self.cursor.expect_ident()?),
+            _ => None,
+        };
+        self.parse_more_attrs(attrs)?;
+        if !self.cursor.eat_punct('{') {
+            let tag = tag.ok_or_else(|| self.cursor.unexpected("`{`"))?;
+            return match self.tags.get(&tag) {
+                Some(Some(ty)) => Ok(Spec::Ty(ty.clone())),
+                _ => Err(self.cursor.error(format!("unknown enum `{}`", tag))),
+            };
+        }
+
+        let mut values = Vec::new();
+        let mut next = 0_i128;
+        while !self.cursor.eat_punct('}') {
+            let name = self.cursor.expect_ident()?;
+            self.parse_attrs()?;
+            if self.cursor.eat_punct('=') {
+                next = self.parse_const()?;
+            }
+            self.constants.insert(name, next);
+            values.push(next);
+            next += 1;
+            if !self.cursor.eat_punct(',') {
+                self.cursor.expect_punct('}')?;
+                break;
+            }
+        }
+        self.parse_more_attrs(attrs)?;
+        if values.is_empty() {
+            return Err(self.cursor.error("enum has no enumerators"));
+        }
+
+        // `packed` enums use the smallest integer that fits all values
+        let fits = |size: u32| {
+            let bits = size * 8;
+            values.iter().all(|&v| {
+                v >= -(1_i128 << (bits - 1)) && v < (1_i128 << bits)
+            })
+        };
+        let size = if attrs.packed {
+            [1, 2, 4, 8].into_iter().find(|&size| fits(size)).unwrap_or(16)
+        } else if fits(4) {
+            4
+        } else {
+            8
+        };
+        // any value of the underlying integer can be stored in a C enum,
+        // not only its enumerators
+        let ty = Ty::Int(size);
+        if let Some(tag) = tag {
+            self.tags.insert(tag, Some(ty.clone()));
+        }
+        Ok(Spec::Ty(ty))
+    }
+
+    fn parse_declarator(&mut self, spec: Spec) -> Result<(String, Spec), ParseError> {
+        if self.cursor.is_punct('*') || self.cursor.is_punct('(') {
+            return Err(self.cursor.error("pointers are not supported"));
+        }
+        let name = self.cursor.expect_ident()?;
+        let mut dims = Vec::new();
+        while self.cursor.eat_punct('[') {
+            if self.cursor.eat_punct(']') {
+                // flexible array member, takes no space
+                dims.push(0);
+                continue;
+            }
+            let count = self.parse_const()?;
+            self.cursor.expect_punct(']')?;
+            let count = usize::try_from(count).map_err(|_| {
+                self.cursor.error(format!("invalid array size for `{}`", name))
+            })?;
+            dims.push(count);
+        }
+        if self.cursor.is_punct(':') {
+            return Err(self.cursor.error(format!("bit-field `{}` is not supported", name)));
+        }
+        if dims.is_empty() {
+            return Ok((name, spec));
+        }
+        let mut ty = match spec {
+            Spec::Ty(ty) => ty,
+            Spec::Incomplete(tag) => {
+                return Err(self.cursor.error(format!(
+                    "array `{}` has incomplete element type `{}`", name, tag)));
+            }
+        };
+        for count in dims.into_iter().rev() {
+            ty = Ty::Array(Box::new(Array { element: ty, count }));
+        }
+        Ok((name, Spec::Ty(ty)))
+    }
+
+    /// Parses an integer constant expression made of literals, enumeration
+    /// constants, parentheses, unary `-`/`~` and binary `* + - << >> & ^ |`.
+    fn parse_const(&mut self) -> Result<i128, ParseError> {
+        self.parse_binary(0)
+    }
+
+    fn parse_binary(&mut self, min_prec: u8) -> Result<i128, ParseError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let (op, prec, width) = match (self.cursor.peek(), self.cursor.peek_nth(1)) {
+                (Some(Token::Punct('*')), _) => ('*', 5, 1),
+                (Some(Token::Punct('+')), _) => ('+', 4, 1),
+                (Some(Token::Punct('-')), _) => ('-', 4, 1),
+                (Some(Token::Punct('<')), Some(Token::Punct('<'))) => ('<', 3, 2),
+                (Some(Token::Punct('>')), Some(Token::Punct('>'))) => ('>', 3, 2),
+                (Some(Token::Punct('&')), _) => ('&', 2, 1),
+                (Some(Token::Punct('^')), _) => ('^', 1, 1),
+                (Some(Token::Punct('|')), _) => ('|', 0, 1),
+                _ => return Ok(lhs),
+            };
+            if prec < min_prec {
+                return Ok(lhs);
+            }
+            for _ in 0..width {
+                self.cursor.next();
+            }
+            let rhs = self.parse_binary(prec + 1)?;
+            let value = match op {
+                '*' => lhs.checked_mul(rhs),
+                '+' => lhs.checked_add(rhs),
+                '-' => lhs.checked_sub(rhs),
+                '<' => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shl(rhs)),
+                '>' => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shr(rhs)),
+                '&' => Some(lhs & rhs),
+                '^' => Some(lhs ^ rhs),
+                _ => Some(lhs | rhs),
+            };
+            lhs = value.ok_or_else(|| self.cursor.error("constant expression overflows"))?;
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<i128, ParseError> {
+        if self.cursor.eat_punct('-') {
+            return Ok(-self.parse_unary()?);
+        }
+        if self.cursor.eat_punct('~') {
+            return Ok(!self.parse_unary()?);
+        }
+        if self.cursor.eat_punct('(') {
+            let value = self.parse_const()?;
+            self.cursor.expect_punct(')')?;
+            return Ok(value);
+        }
+        match self.cursor.peek() {
+            Some(Token::Ident(_)) => {
+                let name = self.cursor.expect_ident()?;
+                self.constants.get(&name).copied().ok_or_else(|| {
+                    self.cursor.error(format!("unknown constant `{}`", name))
+                })
+            }
+            _ => {
+                let value = self.cursor.expect_int()?;
+                i128::try_from(value).map_err(|_| self.cursor.error("constant is too large"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enums_are_integers() {
+        let types = parse_c("
+            enum Small { A, B = 0x7fffffff };
+            enum Large { C = 0x100000000ULL };
+            typedef enum __attribute__((packed)) { D = 255 } Packed;
+        ").unwrap();
+        assert!(matches!(types["Small"], Ty::Int(4)));
+        assert!(matches!(types["Large"], Ty::Int(8)));
+        assert!(matches!(types["Packed"], Ty::Int(1)));
+    }
+
+    #[test]
+    fn leading_zero_is_octal() {
+        let types = parse_c("
+            enum { N = 010, M = 010u };
+            struct S { uint8_t a[N]; uint8_t b[0x10]; uint8_t c[M]; uint8_t d[0u]; };
+        ").unwrap();
+        assert_eq!(layout_of(&types["S"]).unwrap().size(), 8 + 16 + 8);
+        assert!(parse_c("struct S { uint8_t a[09]; };").is_err());
+        assert!(parse_c("struct S { uint8_t a[09u]; };").is_err());
+    }
+
+    fn size_align(ty: &Ty) -> (usize, usize) {
+        let layout = layout_of(ty).unwrap();
+        (layout.size(), layout.align())
+    }
+
+    fn offsets(ty: &Ty) -> Vec<usize> {
+        match ty {
+            Ty::Struct(st) => st.fields.iter().map(|field| field.offset).collect(),
+            _ => panic!("not a struct"),
+        }
+    }
+
+    #[test]
+    fn packed_and_aligned() {
+        let types = parse_c("
+            struct __attribute__((packed)) P { uint8_t a; uint32_t b; uint16_t c; };
+            struct Q { uint8_t a; uint32_t b; } __attribute__((packed, aligned(2)));
+            struct __attribute__((aligned(16))) R { uint8_t a; };
+            typedef struct { uint8_t a; uint64_t b; } __attribute__((aligned(32))) T;
+            struct PA { uint8_t a; struct P p; };
+        ").unwrap();
+        // as gcc lays them out on x86_64
+        assert_eq!(size_align(&types["P"]), (7, 1));
+        assert_eq!(offsets(&types["P"]), [0, 1, 5]);
+        assert_eq!(size_align(&types["Q"]), (6, 2));
+        assert_eq!(size_align(&types["R"]), (16, 16));
+        assert_eq!(size_align(&types["T"]), (32, 32));
+        assert_eq!(offsets(&types["T"]), [0, 8]);
+        assert_eq!(size_align(&types["PA"]), (8, 1));
+        assert!(parse_c("struct S { uint8_t a[2]; } __attribute__((aligned(3)));").is_err());
+        assert!(parse_c("struct S { uint32_t a __attribute__((packed)); };").is_err());
+        assert!(parse_c("typedef uint32_t A __attribute__((aligned(8)));").is_err());
+    }
+
+    #[test]
+    fn anonymous_members() {
+        let types = parse_c("
+            struct S {
+                uint8_t tag;
+                union { uint32_t x; uint8_t y[6]; };
+                struct { uint16_t lo, hi; };
+            };
+        ").unwrap();
+        assert_eq!(size_align(&types["S"]), (16, 4));
+        assert_eq!(offsets(&types["S"]), [0, 4, 12]);
+        let Ty::Struct(ref st) = types["S"] else { panic!("not a struct") };
+        assert!(st.fields[1].name.is_empty() && matches!(st.fields[1].ty, Ty::Union(_)));
+        assert_eq!(offsets(&st.fields[2].ty), [0, 2]);
+        assert!(parse_c("struct S { uint8_t; };").is_err());
+    }
+
+    #[test]
+    fn typedef_chains() {
+        let types = parse_c("
+            typedef uint16_t half;
+            typedef half word[3];
+            typedef word words[2];
+            struct C { uint8_t a; words w; };
+            typedef struct C C_t;
+            typedef C_t Cs[2];
+            typedef struct Later Later_t;
+            struct Later { uint64_t x; };
+        ").unwrap();
+        assert!(types["half"] == Ty::Int(2));
+        assert_eq!(size_align(&types["words"]), (12, 2));
+        assert_eq!(size_align(&types["C"]), (14, 2));
+        assert_eq!(offsets(&types["C"]), [0, 2]);
+        assert!(types["C_t"] == types["C"]);
+        assert_eq!(size_align(&types["Cs"]), (28, 2));
+        // a typedef of a tag defined after it
+        assert_eq!(size_align(&types["Later_t"]), (8, 8));
+        assert!(parse_c("typedef struct Never N; struct S { N n; };").is_err());
+    }
+
+    #[test]
+    fn constant_expression_lengths() {
+        let types = parse_c("
+            enum { N = 4, M = N * 2 };
+            struct S { uint8_t a[(N << 2) - 1]; uint8_t b[M + 1 | 0x10]; uint8_t c[~-3 & 0xf]; };
+        ").unwrap();
+        assert_eq!(offsets(&types["S"]), [0, 15, 40]);
+        assert_eq!(size_align(&types["S"]), (42, 1));
+        assert!(parse_c("struct S { uint8_t a[K]; };").is_err());
+        assert!(parse_c("struct S { uint8_t a[1 - 2]; };").is_err());
+        assert!(parse_c("struct S { uint8_t a[1 << 200]; };").is_err());
+    }
+
+    #[test]
+    fn long_is_lp64() {
+        let types = parse_c("
+            struct S { char c; long l; unsigned long long ll; short s; };
+            typedef long int li;
+            typedef unsigned long ul;
+        ").unwrap();
+        assert_eq!(size_align(&types["S"]), (32, 8));
+        assert_eq!(offsets(&types["S"]), [0, 8, 16, 24]);
+        assert!(types["li"] == Ty::Int(8) && types["ul"] == Ty::Int(8));
+        assert!(parse_c("struct S { long double d; };").is_err());
+    }
+
+    #[test]
+    fn bit_fields_and_pack_pragmas_are_rejected() {
+        let err = parse_c("struct S { uint32_t a : 3; uint32_t b : 5; };").err().unwrap();
+        assert!(err.to_string().contains("bit-field `a`"));
+        let err = parse_c("
+            #pragma pack(push, 1)
+            struct S { uint8_t a; uint32_t b; };
+            #pragma pack(pop)
+        ").err().unwrap();
+        assert!(err.to_string().contains("#pragma pack"));
+        // other directives are skipped
+        assert!(parse_c("#pragma once\n#include <stdint.h>\nstruct S { uint8_t a; };").is_ok());
+    }
+}
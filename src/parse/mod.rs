@@ -0,0 +1,257 @@
+use core::fmt;
+
+pub mod c;
+pub mod rust;
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        Self { line, message: message.into() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Ident(String),
+    Int(u128),
+    Punct(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "`{}`", ident),
+            Token::Int(value) => write!(f, "`{}`", value),
+            Token::Punct(punct) => write!(f, "`{}`", punct),
+        }
+    }
+}
+
+/// Splits source text into identifiers, integer literals and single-char
+/// punctuation, dropping whitespace, comments and lifetimes.
+/// For C source, set `c`: lines starting with `#` followed by a letter are
+/// preprocessor directives, which are skipped as well, and integers with
+/// a leading zero are octal.
+pub fn tokenize(src: &str, c: bool) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+    let mut line = 1;
+    let mut line_start = true;
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\n' => {
+                line += 1;
+                line_start = true;
+                continue;
+            }
+            _ if ch.is_whitespace() => continue,
+            '#' if c && line_start => {
+                // skip to the end of the line, honoring line continuations
+                let mut prev = '#';
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' && prev != '\\' { break; }
+                    if next == '\n' { line += 1; }
+                    prev = next;
+                    chars.next();
+                }
+                continue;
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&next| next != '\n') {
+                    chars.next();
+                }
+                continue;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(next) => {
+                            if next == '\n' { line += 1; }
+                            prev = next;
+                        }
+                        None => {
+                            return Err(ParseError::new(line, "unterminated comment"));
+                        }
+                    }
+                }
+                continue;
+            }
+            '\'' => {
+                // lifetimes carry no layout information
+                while chars.peek().is_some_and(|&next| is_ident_char(next)) {
+                    chars.next();
+                }
+            }
+            _ if ch.is_ascii_digit() => {
+                let mut text = ch.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !is_ident_char(next) { break; }
+                    text.push(next);
+                    chars.next();
+                }
+                let value = parse_int(&text, c)
+                    .ok_or_else(|| ParseError::new(line,
+                        format!("invalid integer literal `{}`", text)))?;
+                tokens.push((line, Token::Int(value)));
+            }
+            _ if is_ident_char(ch) => {
+                let mut ident = ch.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !is_ident_char(next) { break; }
+                    ident.push(next);
+                    chars.next();
+                }
+                tokens.push((line, Token::Ident(ident)));
+            }
+            _ => tokens.push((line, Token::Punct(ch))),
+        }
+        line_start = false;
+    }
+    Ok(tokens)
+}
+
+fn is_ident_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+/// Parses a Rust or C integer literal, ignoring `_` separators and
+/// type suffixes like `u8`, `usize`, `U` or `ULL`. A leading zero makes
+/// a C literal octal, Rust has `0o` for that.
+fn parse_int(text: &str, c: bool) -> Option<u128> {
+    let text = text.replace('_', "");
+    let lower = text.to_ascii_lowercase();
+    let (radix, digits) = if let Some(hex) = lower.strip_prefix("0x") {
+        (16, hex)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (2, bin)
+    } else if let Some(oct) = lower.strip_prefix("0o") {
+        (8, oct)
+    } else if c && lower.starts_with('0')
+        && lower.as_bytes().get(1).is_some_and(u8::is_ascii_digit) {
+        // the type suffix doesn't change the radix, `010u` is octal too
+        (8, &lower[1..])
+    } else {
+        (10, lower.as_str())
+    };
+    let end = digits.find(|ch: char| !ch.is_digit(radix)).unwrap_or(digits.len());
+    let (digits, suffix) = digits.split_at(end);
+    let valid_suffix = matches!(suffix,
+        "" | "u" | "l" | "ul" | "lu" | "ll" | "ull" | "llu"
+        | "u8" | "u16" | "u32" | "u64" | "u128" | "usize"
+        | "i8" | "i16" | "i32" | "i64" | "i128" | "isize");
+    if digits.is_empty() || !valid_suffix {
+        return None;
+    }
+    u128::from_str_radix(digits, radix).ok()
+}
+
+/// A position in a token stream with the usual single-token lookahead helpers.
+pub struct Cursor {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Cursor {
+    pub fn new(tokens: Vec<(usize, Token)>) -> Self {
+        Self { tokens, pos: 0 }
+    }
+    pub fn line(&self) -> usize {
+        self.tokens.get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |(line, _)| *line)
+    }
+    pub fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError::new(self.line(), message)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+    pub fn peek(&self) -> Option<&Token> {
+        self.peek_nth(0)
+    }
+    pub fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + n).map(|(_, token)| token)
+    }
+    pub fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+    pub fn is_punct(&self, punct: char) -> bool {
+        self.peek() == Some(&Token::Punct(punct))
+    }
+    pub fn is_ident(&self, ident: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(id)) if id == ident)
+    }
+    pub fn eat_punct(&mut self, punct: char) -> bool {
+        let found = self.is_punct(punct);
+        if found { self.pos += 1; }
+        found
+    }
+    pub fn eat_ident(&mut self, ident: &str) -> bool {
+        let found = self.is_ident(ident);
+        if found { self.pos += 1; }
+        found
+    }
+    pub fn expect_punct(&mut self, punct: char) -> Result<(), ParseError> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", punct)))
+        }
+    }
+    pub fn expect_ident(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Token::Ident(ident)) => {
+                let ident = ident.clone();
+                self.pos += 1;
+                Ok(ident)
+            }
+            _ => Err(self.unexpected("identifier")),
+        }
+    }
+    pub fn expect_int(&mut self) -> Result<u128, ParseError> {
+        match self.peek() {
+            Some(&Token::Int(value)) => {
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => Err(self.unexpected("integer")),
+        }
+    }
+    pub fn unexpected(&self, expected: &str) -> ParseError {
+        match self.peek() {
+            Some(token) => self.error(format!("expected {}, found {}", expected, token)),
+            None => self.error(format!("expected {}, found end of input", expected)),
+        }
+    }
+    /// Skips a balanced group, assuming the opening bracket was already consumed.
+    pub fn skip_group(&mut self, open: char, close: char) -> Result<(), ParseError> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some(Token::Punct(ch)) if ch == open => depth += 1,
+                Some(Token::Punct(ch)) if ch == close => depth -= 1,
+                Some(_) => {}
+                None => return Err(self.error(format!("unclosed `{}`", open))),
+            }
+        }
+        Ok(())
+    }
+}
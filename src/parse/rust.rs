@@ -0,0 +1,729 @@
+use std::collections::BTreeMap;
+
+use crate::error::Error;
+use crate::ty::*;
+use super::{tokenize, Cursor, ParseError, Token};
+
+/// Type expression as written in the source, before names are resolved.
+enum TyExpr {
+    Named(usize, String),
+    Array(Box<TyExpr>, usize),
+    /// `UnsafeCell<T>` or `Cell<T>`.
+    Cell(Box<TyExpr>),
+    /// A field type under `#[valid(...)]`, see `Ty::narrowed`.
+    Valid(Box<TyExpr>, Vec<(u128, u128)>),
+    Unit,
+    Never,
+}
+
+enum Payload {
+    Unit,
+    Tuple(Vec<TyExpr>),
+    Named(Vec<(String, TyExpr)>),
+}
+
+struct Variant {
+    name: String,
+    disc: Option<i128>,
+    payload: Payload,
+}
+
+enum DeclKind {
+    Struct(Payload, Repr),
+    /// A struct of one integer with a valid range, see `Ty::Scalar`.
+    Scalar(Scalar),
+    Union(Vec<(String, TyExpr)>, Repr),
+    /// `signed` if the tag is an `iN`, which bounds the discriminants.
+    Enum { tag: u32, signed: bool, repr: EnumRepr, variants: Vec<Variant> },
+    Alias(TyExpr),
+}
+
+struct Decl {
+    line: usize,
+    kind: DeclKind,
+}
+
+#[derive(Default)]
+struct Repr {
+    c: bool,
+    transparent: bool,
+    int: Option<u32>,
+    /// Whether `int` is an `iN` rather than a `uN`.
+    signed: bool,
+    pack: Option<usize>,
+    align: Option<usize>,
+    /// `rustc_layout_scalar_valid_range_start/end`
+    valid_start: Option<u128>,
+    valid_end: Option<u128>,
+}
+
+#[derive(Default)]
+struct Attributes {
+    repr: Repr,
+    /// `#[valid(0..=1, 7)]` on a field, as `derive_ty!` takes it.
+    valid: Option<Vec<(u128, u128)>>,
+}
+
+/// Parses `#[repr(C)]` struct, union and enum declarations and `type` aliases
+/// from Rust source, in the shape that `Printer::print_rust` emits.
+/// Declarations may refer to each other in any order; every one of them is
+/// returned under its own name.
+pub fn parse_rust(src: &str) -> Result<BTreeMap<String, Ty>, ParseError> {
+    let mut cursor = Cursor::new(tokenize(src, false)?);
+    let mut decls = BTreeMap::new();
+    while !cursor.is_empty() {
+        let line = cursor.line();
+        if let Some((name, kind)) = parse_item(&mut cursor)? {
+            if decls.insert(name.clone(), Decl { line, kind }).is_some() {
+                return Err(ParseError::new(line,
+                    format!("type `{}` is defined more than once", name)));
+            }
+        }
+    }
+
+    let mut resolver = Resolver {
+        decls: &decls,
+        done: BTreeMap::new(),
+        visiting: Vec::new(),
+    };
+    for name in decls.keys() {
+        resolver.resolve_decl(name)?;
+    }
+    Ok(resolver.done)
+}
+
+fn parse_item(cursor: &mut Cursor) -> Result<Option<(String, DeclKind)>, ParseError> {
+    let repr = parse_attributes(cursor)?.repr;
+    skip_visibility(cursor)?;
+    if cursor.eat_ident("use") {
+        while !cursor.eat_punct(';') {
+            if cursor.next().is_none() {
+                return Err(cursor.unexpected("`;`"));
+            }
+        }
+        return Ok(None);
+    }
+    if cursor.eat_ident("type") {
+        let name = cursor.expect_ident()?;
+        cursor.expect_punct('=')?;
+        let ty = parse_ty(cursor)?;
+        cursor.expect_punct(';')?;
+        return Ok(Some((name, DeclKind::Alias(ty))));
+    }
+    let keyword = cursor.expect_ident()?;
+    let name = cursor.expect_ident()?;
+    if cursor.is_punct('<') {
+        return Err(cursor.error(format!("generic type `{}` is not supported", name)));
+    }
+    let kind = match keyword.as_str() {
+        "struct" => {
+            require_repr_c(cursor, &name, &repr)?;
+            let payload = parse_payload(cursor)?;
+            if !matches!(payload, Payload::Named(_)) {
+                cursor.expect_punct(';')?;
+            }
+            if repr.valid_start.is_none() && repr.valid_end.is_none() {
+                DeclKind::Struct(payload, repr)
+            } else {
+                let size = match payload {
+                    Payload::Tuple(ref fields) => match fields[..] {
+                        [TyExpr::Named(_, ref int)] => int_size(int),
+                        _ => None,
+                    },
+                    _ => None,
+                }.ok_or_else(|| cursor.error(format!(
+                    "valid range of `{}` needs a single integer field", name)))?;
+                let start = repr.valid_start.unwrap_or(0);
+                let end = repr.valid_end.unwrap_or_else(|| int_max(size));
+                DeclKind::Scalar(Scalar::new(size, start, end))
+            }
+        }
+        "union" => {
+            require_repr_c(cursor, &name, &repr)?;
+            cursor.expect_punct('{')?;
+            DeclKind::Union(parse_named_fields(cursor)?, repr)
+        }
+        "enum" => {
+            if repr.pack.is_some() || repr.align.is_some() {
+                return Err(cursor.error(format!(
+                    "packed or aligned enum `{}` is not supported", name)));
+            }
+            cursor.expect_punct('{')?;
+            let mut variants = Vec::new();
+            while !cursor.eat_punct('}') {
+                parse_attributes(cursor)?;
+                let name = cursor.expect_ident()?;
+                let payload = parse_payload(cursor)?;
+                let disc = if cursor.eat_punct('=') {
+                    let negative = cursor.eat_punct('-');
+                    let value = i128::try_from(cursor.expect_int()?)
+                        .map_err(|_| cursor.error("discriminant out of range"))?;
+                    Some(if negative { -value } else { value })
+                } else {
+                    None
+                };
+                variants.push(Variant { name, disc, payload });
+                if !cursor.eat_punct(',') {
+                    cursor.expect_punct('}')?;
+                    break;
+                }
+            }
+            // a C `int` is signed
+            let signed = repr.signed || repr.int.is_none();
+            let (tag, enum_repr) = match repr {
+                // `enum Void {}` is `!` under another name, rustc rejects
+                // any repr on it
+                _ if variants.is_empty() => {
+                    if repr.c || repr.int.is_some() {
+                        return Err(cursor.error(format!(
+                            "zero-variant enum `{}` can't have a repr", name)));
+                    }
+                    (0, EnumRepr::C)
+                }
+                Repr { c: true, int: Some(size), .. } => (size, EnumRepr::C),
+                // a C `int`, which is what every target we support uses
+                Repr { c: true, int: None, .. } => (4, EnumRepr::C),
+                // both layouts are the same for fieldless enums
+                Repr { c: false, int: Some(size), .. } => {
+                    let fieldless = variants.iter()
+                        .all(|v| matches!(v.payload, Payload::Unit));
+                    (size, if fieldless { EnumRepr::C } else { EnumRepr::Primitive })
+                }
+                Repr { c: false, int: None, .. } => {
+                    return Err(cursor.error(format!(
+                        "enum `{}` must be #[repr(C)] or have a primitive repr", name)));
+                }
+            };
+            DeclKind::Enum { tag, signed, repr: enum_repr, variants }
+        }
+        _ => {
+            return Err(cursor.error(format!(
+                "expected `struct`, `enum`, `union` or `type`, found `{}`", keyword)));
+        }
+    };
+    Ok(Some((name, kind)))
+}
+
+fn require_repr_c(cursor: &Cursor, name: &str, repr: &Repr) -> Result<(), ParseError> {
+    // a transparent type is laid out like its only non-zero-sized field,
+    // which is what `repr(C)` does with it as well
+    if repr.c || repr.transparent {
+        Ok(())
+    } else {
+        Err(cursor.error(format!("type `{}` must be #[repr(C)]", name)))
+    }
+}
+
+fn parse_attributes(cursor: &mut Cursor) -> Result<Attributes, ParseError> {
+    let mut attrs = Attributes::default();
+    let repr = &mut attrs.repr;
+    while cursor.eat_punct('#') {
+        cursor.expect_punct('[')?;
+        if cursor.eat_ident("valid") {
+            attrs.valid = Some(parse_valid_ranges(cursor)?);
+            cursor.expect_punct(']')?;
+            continue;
+        }
+        let bound = if cursor.eat_ident("rustc_layout_scalar_valid_range_start") {
+            Some(&mut repr.valid_start)
+        } else if cursor.eat_ident("rustc_layout_scalar_valid_range_end") {
+            Some(&mut repr.valid_end)
+        } else {
+            None
+        };
+        if let Some(bound) = bound {
+            cursor.expect_punct('(')?;
+            *bound = Some(cursor.expect_int()?);
+            cursor.expect_punct(')')?;
+            cursor.expect_punct(']')?;
+            continue;
+        }
+        if !cursor.eat_ident("repr") {
+            cursor.skip_group('[', ']')?;
+            continue;
+        }
+        cursor.expect_punct('(')?;
+        loop {
+            let hint = cursor.expect_ident()?;
+            match hint.as_str() {
+                "C" => repr.c = true,
+                "transparent" => repr.transparent = true,
+                "packed" => {
+                    let pack = if cursor.eat_punct('(') {
+                        let pack = parse_power_of_two(cursor)?;
+                        cursor.expect_punct(')')?;
+                        pack
+                    } else {
+                        1
+                    };
+                    repr.pack = Some(pack);
+                }
+                "align" => {
+                    cursor.expect_punct('(')?;
+                    repr.align = Some(parse_power_of_two(cursor)?);
+                    cursor.expect_punct(')')?;
+                }
+                _ => {
+                    repr.int = Some(int_size(&hint).ok_or_else(|| {
+                        cursor.error(format!("unsupported repr `{}`", hint))
+                    })?);
+                    repr.signed = hint.starts_with('i');
+                }
+            }
+            if !cursor.eat_punct(',') {
+                break;
+            }
+        }
+        cursor.expect_punct(')')?;
+        cursor.expect_punct(']')?;
+    }
+    Ok(attrs)
+}
+
+/// Parses `(0..=1, 7)`: values and inclusive ranges of them.
+fn parse_valid_ranges(cursor: &mut Cursor) -> Result<Vec<(u128, u128)>, ParseError> {
+    cursor.expect_punct('(')?;
+    let mut ranges = Vec::new();
+    while !cursor.eat_punct(')') {
+        let start = cursor.expect_int()?;
+        let end = if cursor.eat_punct('.') {
+            cursor.expect_punct('.')?;
+            cursor.expect_punct('=')?;
+            cursor.expect_int()?
+        } else {
+            start
+        };
+        ranges.push((start, end));
+        if !cursor.eat_punct(',') {
+            cursor.expect_punct(')')?;
+            break;
+        }
+    }
+    Ok(ranges)
+}
+
+/// Wraps `ty` in the `#[valid(...)]` of its field, if any.
+fn with_valid(ty: TyExpr, attrs: Attributes) -> TyExpr {
+    match attrs.valid {
+        Some(ranges) => TyExpr::Valid(Box::new(ty), ranges),
+        None => ty,
+    }
+}
+
+fn parse_power_of_two(cursor: &mut Cursor) -> Result<usize, ParseError> {
+    let value = cursor.expect_int()?;
+    match usize::try_from(value) {
+        Ok(value) if value.is_power_of_two() => Ok(value),
+        _ => Err(cursor.error(format!("`{}` is not a power of two", value))),
+    }
+}
+
+fn skip_visibility(cursor: &mut Cursor) -> Result<(), ParseError> {
+    if cursor.eat_ident("pub") && cursor.eat_punct('(') {
+        cursor.skip_group('(', ')')?;
+    }
+    Ok(())
+}
+
+fn parse_payload(cursor: &mut Cursor) -> Result<Payload, ParseError> {
+    if cursor.eat_punct('{') {
+        Ok(Payload::Named(parse_named_fields(cursor)?))
+    } else if cursor.eat_punct('(') {
+        let mut fields = Vec::new();
+        while !cursor.eat_punct(')') {
+            let attrs = parse_attributes(cursor)?;
+            skip_visibility(cursor)?;
+            fields.push(with_valid(parse_ty(cursor)?, attrs));
+            if !cursor.eat_punct(',') {
+                cursor.expect_punct(')')?;
+                break;
+            }
+        }
+        Ok(Payload::Tuple(fields))
+    } else {
+        Ok(Payload::Unit)
+    }
+}
+
+/// Parses `name: Type,` pairs up to and including the closing brace.
+fn parse_named_fields(cursor: &mut Cursor) -> Result<Vec<(String, TyExpr)>, ParseError> {
+    let mut fields = Vec::new();
+    while !cursor.eat_punct('}') {
+        let attrs = parse_attributes(cursor)?;
+        skip_visibility(cursor)?;
+        let name = cursor.expect_ident()?;
+        cursor.expect_punct(':')?;
+        fields.push((name, with_valid(parse_ty(cursor)?, attrs)));
+        if !cursor.eat_punct(',') {
+            cursor.expect_punct('}')?;
+            break;
+        }
+    }
+    Ok(fields)
+}
+
+fn parse_ty(cursor: &mut Cursor) -> Result<TyExpr, ParseError> {
+    match cursor.peek() {
+        Some(Token::Punct('[')) => {
+            cursor.next();
+            let element = parse_ty(cursor)?;
+            cursor.expect_punct(';')?;
+            let count = cursor.expect_int()?;
+            cursor.expect_punct(']')?;
+            let count = usize::try_from(count)
+                .map_err(|_| cursor.error("array length is too large"))?;
+            Ok(TyExpr::Array(Box::new(element), count))
+        }
+        Some(Token::Punct('(')) => {
+            cursor.next();
+            if cursor.eat_punct(')') {
+                Ok(TyExpr::Unit)
+            } else {
+                Err(cursor.error("tuples are not supported"))
+            }
+        }
+        Some(Token::Punct('!')) => {
+            cursor.next();
+            Ok(TyExpr::Never)
+        }
+        Some(Token::Punct('&')) | Some(Token::Punct('*')) => {
+            Err(cursor.error("pointers and references are not supported"))
+        }
+        Some(Token::Ident(_)) => {
+            let line = cursor.line();
+            let first = cursor.expect_ident()?;
+            let mut name = first.clone();
+            while cursor.is_punct(':') && cursor.peek_nth(1) == Some(&Token::Punct(':')) {
+                // the standard library's types are known by their last segment
+                if first != "core" && first != "std" {
+                    return Err(cursor.error(format!("paths like `{}::..` are not supported", first)));
+                }
+                cursor.next();
+                cursor.next();
+                name = cursor.expect_ident()?;
+            }
+            if cursor.is_punct('<') {
+                if name != "UnsafeCell" && name != "Cell" {
+                    return Err(cursor.error(format!("generic type `{}` is not supported", name)));
+                }
+                cursor.next();
+                let inner = parse_ty(cursor)?;
+                cursor.expect_punct('>')?;
+                return Ok(TyExpr::Cell(Box::new(inner)));
+            }
+            Ok(TyExpr::Named(line, name))
+        }
+        _ => Err(cursor.unexpected("type")),
+    }
+}
+
+fn int_size(name: &str) -> Option<u32> {
+    match name {
+        "u8" | "i8" => Some(1),
+        "u16" | "i16" => Some(2),
+        "u32" | "i32" => Some(4),
+        "u64" | "i64" => Some(8),
+        "u128" | "i128" => Some(16),
+        "usize" | "isize" => Some(core::mem::size_of::<usize>() as u32),
+        _ => None,
+    }
+}
+
+struct Resolver<'d> {
+    decls: &'d BTreeMap<String, Decl>,
+    done: BTreeMap<String, Ty>,
+    visiting: Vec<String>,
+}
+
+impl<'d> Resolver<'d> {
+    fn resolve_decl(&mut self, name: &str) -> Result<Ty, ParseError> {
+        if let Some(ty) = self.done.get(name) {
+            return Ok(ty.clone());
+        }
+        let decl = &self.decls[name];
+        if self.visiting.iter().any(|n| n == name) {
+            return Err(ParseError::new(decl.line,
+                format!("type `{}` contains itself", name)));
+        }
+        self.visiting.push(name.to_string());
+        let line = decl.line;
+        let layout_error = |err: Error| ParseError::new(line, err.to_string());
+        let ty = match decl.kind {
+            DeclKind::Struct(ref payload, ref repr) => {
+                let st = Struct::with_repr(name, repr.pack, repr.align).map_err(layout_error)?;
+                self.payload(line, st, payload)?
+            }
+            DeclKind::Union(ref fields, ref repr) => {
+                if fields.is_empty() {
+                    return Err(ParseError::new(decl.line,
+                        format!("union `{}` has no fields", name)));
+                }
+                let mut un = Union::with_repr(name, repr.pack, repr.align).map_err(layout_error)?;
+                for (field_name, field) in fields {
+                    un.add_variant(field_name.clone(), false, self.resolve(field)?)
+                        .map_err(layout_error)?;
+                }
+                Ty::Union(un)
+            }
+            DeclKind::Enum { ref variants, .. } if variants.is_empty() => Ty::Never,
+            DeclKind::Enum { tag, signed, repr, ref variants } => {
+                let mask = u128::MAX >> (128 - tag * 8);
+                let (min, max) = match (signed, tag) {
+                    (true, _) => (-1 << (tag * 8 - 1), (mask >> 1) as i128),
+                    // discriminants are parsed as `i128`, larger ones don't get here
+                    (false, 16) => (0, i128::MAX),
+                    (false, _) => (0, mask as i128),
+                };
+                let mut en = Enum::with_repr(name, tag, repr).map_err(layout_error)?;
+                let mut next = 0_i128;
+                for variant in variants {
+                    let value = variant.disc.unwrap_or(next);
+                    if !(min..=max).contains(&value) {
+                        return Err(ParseError::new(decl.line, format!(
+                            "discriminant {} of `{}::{}` doesn't fit in a {}-byte tag",
+                            value, name, variant.name, tag)));
+                    }
+                    let disc = value as u128 & mask;
+                    if repr == EnumRepr::Primitive {
+                        let fields = match variant.payload {
+                            Payload::Unit => Vec::new(),
+                            Payload::Tuple(ref fields) => fields.iter().enumerate()
+                                .map(|(idx, field)| Ok((idx.to_string(), false, self.resolve(field)?)))
+                                .collect::<Result<_, ParseError>>()?,
+                            Payload::Named(ref fields) => fields.iter()
+                                .map(|(field_name, field)| Ok((field_name.clone(), false, self.resolve(field)?)))
+                                .collect::<Result<_, ParseError>>()?,
+                        };
+                        en.add_variant_fields(variant.name.clone(), disc, fields)
+                            .map_err(layout_error)?;
+                    } else {
+                        let payload = match variant.payload {
+                            Payload::Unit => Ty::Void,
+                            // `Var(T)` has the same layout as `T`, keep it unwrapped like `derive_ty!`
+                            Payload::Tuple(ref fields) if fields.len() == 1 => self.resolve(&fields[0])?,
+                            ref payload => {
+                                let st = Struct::new(format!("{}_{}", name, variant.name));
+                                self.payload(line, st, payload)?
+                            }
+                        };
+                        en.add_variant(variant.name.clone(), disc, payload).map_err(layout_error)?;
+                    }
+                    next = value.saturating_add(1);
+                }
+                Ty::Enum(en)
+            }
+            DeclKind::Scalar(ref scalar) => Ty::Scalar(scalar.clone()),
+            DeclKind::Alias(ref ty) => self.resolve(ty)?,
+        };
+        self.visiting.pop();
+        self.done.insert(name.to_string(), ty.clone());
+        Ok(ty)
+    }
+    fn payload(&mut self, line: usize, mut st: Struct, payload: &Payload) -> Result<Ty, ParseError> {
+        let layout_error = |err: Error| ParseError::new(line, err.to_string());
+        match payload {
+            Payload::Unit => {}
+            Payload::Tuple(fields) => {
+                // tuple fields are named by their index, like `.0`
+                for (idx, field) in fields.iter().enumerate() {
+                    st.add_field(idx.to_string(), false, self.resolve(field)?)
+                        .map_err(layout_error)?;
+                }
+            }
+            Payload::Named(fields) => {
+                for (field_name, field) in fields {
+                    st.add_field(field_name.clone(), false, self.resolve(field)?)
+                        .map_err(layout_error)?;
+                }
+            }
+        }
+        Ok(Ty::Struct(st))
+    }
+    fn resolve(&mut self, expr: &TyExpr) -> Result<Ty, ParseError> {
+        match expr {
+            TyExpr::Unit => Ok(Ty::Void),
+            TyExpr::Never => Ok(Ty::Never),
+            TyExpr::Array(element, count) => {
+                Ok(Ty::Array(Box::new(Array {
+                    element: self.resolve(element)?,
+                    count: *count,
+                })))
+            }
+            TyExpr::Cell(inner) => Ok(Ty::UnsafeCell(Box::new(self.resolve(inner)?))),
+            TyExpr::Valid(ty, ranges) => Ok(self.resolve(ty)?.narrowed(ranges.clone())),
+            TyExpr::Named(line, name) => {
+                if self.decls.contains_key(name) {
+                    return self.resolve_decl(name);
+                }
+                match name.as_str() {
+                    "bool" => Ok(Ty::Bool),
+                    // floats have no invalid bit patterns
+                    "f32" => Ok(Ty::Int(4)),
+                    "f64" => Ok(Ty::Int(8)),
+                    // atomics are their integer in an `UnsafeCell`
+                    "AtomicBool" => Ok(Ty::UnsafeCell(Box::new(Ty::Bool))),
+                    _ if name.starts_with("Atomic") => {
+                        int_size(&name["Atomic".len()..].to_ascii_lowercase())
+                            .map(|size| Ty::UnsafeCell(Box::new(Ty::Int(size))))
+                            .ok_or_else(|| {
+                                ParseError::new(*line, format!("unknown type `{}`", name))
+                            })
+                    }
+                    _ => match name.strip_prefix("NonZero") {
+                        // `NonZeroU16` and the like, zero is their only invalid value
+                        Some(int) => int_size(&int.to_ascii_lowercase())
+                            .map(|size| Ty::Scalar(Scalar::non_zero(size))),
+                        None => int_size(name).map(Ty::Int),
+                    }.ok_or_else(|| {
+                        ParseError::new(*line, format!("unknown type `{}`", name))
+                    }),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_one(src: &str, name: &str) -> Ty {
+        parse_rust(src).unwrap().remove(name).unwrap()
+    }
+
+    #[test]
+    fn leading_zero_is_decimal() {
+        let ty = parse_one("#[repr(C)] struct S { a: [u8; 010] }", "S");
+        assert_eq!(layout_of(&ty).unwrap().size(), 10);
+        let ty = parse_one("#[repr(C)] struct S { a: [u8; 0o10] }", "S");
+        assert_eq!(layout_of(&ty).unwrap().size(), 8);
+    }
+
+    #[test]
+    fn tuple_and_unit_structs_are_structs() {
+        let Ty::Struct(st) = parse_one("#[repr(C)] struct N(u16);", "N") else {
+            panic!("not a struct");
+        };
+        assert_eq!(st.fields.len(), 1);
+        let Ty::Struct(st) = parse_one("#[repr(C)] struct U;", "U") else {
+            panic!("not a struct");
+        };
+        assert!(st.fields.is_empty());
+    }
+
+    #[test]
+    fn tuple_structs_keep_their_repr() {
+        let ty = parse_one("#[repr(C, align(8))] struct W(u8);", "W");
+        assert_eq!(layout_of(&ty).unwrap().size(), 8);
+        assert_eq!(layout_of(&ty).unwrap().align(), 8);
+        let ty = parse_one("#[repr(C, packed)] struct P(u8, u32);", "P");
+        assert_eq!(layout_of(&ty).unwrap().size(), 5);
+        let ty = parse_one("#[repr(C, align(4))] struct U;", "U");
+        assert_eq!(layout_of(&ty).unwrap().align(), 4);
+    }
+
+    #[test]
+    fn scalar_valid_ranges() {
+        let scalar = |src: &str| match parse_one(src, "S") {
+            Ty::Scalar(scalar) => (scalar.size, scalar.start, scalar.end),
+            _ => panic!("not a scalar"),
+        };
+        assert_eq!(scalar("type S = NonZeroU16;"), (2, 1, 0xffff));
+        assert_eq!(scalar("
+            #[repr(transparent)]
+            #[rustc_layout_scalar_valid_range_start(1)]
+            #[rustc_layout_scalar_valid_range_end(12)]
+            struct S(u8);
+        "), (1, 1, 12));
+        assert_eq!(scalar("
+            #[rustc_layout_scalar_valid_range_start(0xfff0)]
+            #[repr(C)] struct S(u16);
+        "), (2, 0xfff0, 0xffff));
+        assert!(parse_rust("
+            #[rustc_layout_scalar_valid_range_end(1)] #[repr(C)] struct S(bool);
+        ").is_err());
+        assert!(parse_rust("
+            #[rustc_layout_scalar_valid_range_end(1)] struct S(u8);
+        ").is_err());
+    }
+
+    #[test]
+    fn valid_field_ranges() {
+        let Ty::Struct(st) = parse_one("
+            #[repr(C)] struct S {
+                #[valid(0..=1, 7)] pub a: u8,
+                #[valid(1)] b: [bool; 2],
+                c: u8,
+            }
+        ", "S") else {
+            panic!("not a struct");
+        };
+        assert!(st.fields[0].ty == Ty::Int(1).narrowed(vec![(0, 1), (7, 7)]));
+        assert!(st.fields[1].ty == Ty::Array(Box::new(Array {
+            element: Ty::Bool.narrowed(vec![(1, 1)]),
+            count: 2,
+        })));
+        assert!(st.fields[2].ty == Ty::Int(1));
+        let Ty::Struct(st) = parse_one("#[repr(C)] struct S(#[valid(2..=3,)] u16);", "S") else {
+            panic!("not a struct");
+        };
+        assert!(st.fields[0].ty == Ty::Int(2).narrowed(vec![(2, 3)]));
+        assert!(parse_rust("#[repr(C)] struct S { #[valid(1..2)] a: u8 }").is_err());
+    }
+
+    #[test]
+    fn cells() {
+        let cell = |ty| Ty::UnsafeCell(Box::new(ty));
+        let ty = parse_one("#[repr(C)] struct S { a: UnsafeCell<u32> }", "S");
+        let Ty::Struct(st) = ty else { panic!("not a struct") };
+        assert!(st.fields[0].ty == cell(Ty::Int(4)));
+        for (src, inner) in [
+            ("Cell<[bool; 2]>", Ty::Array(Box::new(Array { element: Ty::Bool, count: 2 }))),
+            ("core::cell::UnsafeCell<u16>", Ty::Int(2)),
+            ("std::sync::atomic::AtomicU64", Ty::Int(8)),
+            ("AtomicBool", Ty::Bool),
+        ] {
+            let ty = parse_one(&format!("#[repr(C)] struct S {{ a: {} }}", src), "S");
+            let Ty::Struct(st) = ty else { panic!("not a struct") };
+            assert!(st.fields[0].ty == cell(inner), "{}", src);
+        }
+        assert!(parse_rust("#[repr(C)] struct S { a: Vec<u8> }").is_err());
+        assert!(parse_rust("#[repr(C)] struct S { a: AtomicU7 }").is_err());
+        assert!(parse_rust("#[repr(C)] struct S { a: my::Cell<u8> }").is_err());
+    }
+
+    #[test]
+    fn discriminants() {
+        let Ty::Enum(en) = parse_one("#[repr(u8)] enum E { A = 255, B = 7, C }", "E") else {
+            panic!("not an enum");
+        };
+        let discs = en.variants.iter().map(|v| v.disc).collect::<Vec<_>>();
+        assert_eq!(discs, [255, 7, 8]);
+        let Ty::Enum(en) = parse_one("#[repr(i8)] enum E { A = -1 }", "E") else {
+            panic!("not an enum");
+        };
+        assert_eq!(en.variants[0].disc, 0xff);
+        let Ty::Enum(en) = parse_one("#[repr(i16)] enum E { A = 32767, B = -32768 }", "E") else {
+            panic!("not an enum");
+        };
+        let discs = en.variants.iter().map(|v| v.disc).collect::<Vec<_>>();
+        assert_eq!(discs, [0x7fff, 0x8000]);
+    }
+
+    #[test]
+    fn discriminant_out_of_range() {
+        for src in [
+            "#[repr(u8)] enum E { A = 256 }",
+            "#[repr(u8)] enum E { A = 255, B }",
+            "#[repr(i8)] enum E { A = -129 }",
+            "#[repr(i8)] enum E { A = 128 }",
+            "#[repr(i8)] enum E { A = 200 }",
+            "#[repr(u8)] enum E { A = -1 }",
+            "#[repr(u16)] enum E { A = -1 }",
+            "#[repr(C)] enum E { A = 2147483648 }",
+            "#[repr(u8)] enum E { A = 340282366920938463463374607431768211455 }",
+        ] {
+            assert!(parse_rust(src).is_err(), "{}", src);
+        }
+    }
+}
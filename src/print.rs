@@ -1,189 +1,906 @@
 use core::fmt::{self, Write};
+use crate::error::Error;
+use crate::inst::{Inst, InstByte, ProgPath, Program, StepByte};
 use crate::ty::*;
 
+/// Prints a type and everything it contains as C or Rust declarations.
+/// Structurally equal types are printed once, and every type is printed
+/// after the types it contains.
 pub struct Printer<'t> {
     pub pos: usize,
-    queue: Vec<(&'t Ty, String)>,
+    /// Named types printed so far, with the name they were printed under.
+    defined: Vec<(&'t Ty, String)>,
 }
 impl<'t> Printer<'t> {
     pub fn new() -> Self {
         Self {
             pos: 0,
-            queue: vec![],
+            defined: vec![],
         }
     }
-    pub fn c_name_for(&mut self, ty: &'t Ty) -> String {
-        match ty {
-            Ty::Void => "Zst".into(),
-            Ty::Bool => "bool".into(),
-            Ty::Int(sz) => format!("u{}", sz * 8),
-            Ty::Ptr(_) => unimplemented!("pointer"),
-            Ty::Ref(_) => unimplemented!("reference"),
-            Ty::Array(_) => {
-                panic!("c doesn't have a good type name for arrays");
-            }
-            Ty::Struct(_) => {
-                let id = self.pos;
-                self.pos += 1;
-                format!("Struct{}", id)
+    /// Looks up the name `ty` was printed under, or picks a fresh one.
+    /// Returns whether the type still needs to be defined.
+    fn intern(&mut self, ty: &'t Ty, prefix: &str) -> (String, bool) {
+        if let Some((_, name)) = self.defined.iter().find(|(other, _)| *other == ty) {
+            return (name.clone(), false);
+        }
+        let declared = match ty {
+            Ty::Struct(ref st) => &st.name,
+            Ty::Union(ref un) => &un.name,
+            Ty::Enum(ref en) => &en.name,
+            Ty::Opaque(ref op) => &op.name,
+            Ty::Scalar(_) => "",
+            _ => unreachable!("only named types are interned"),
+        };
+        let base = ident(declared).unwrap_or_else(|| {
+            let id = self.pos;
+            self.pos += 1;
+            format!("{}{}", prefix, id)
+        });
+        // different types may share a declared name, e.g. after merging files
+        let mut name = base.clone();
+        let mut suffix = 1;
+        while self.defined.iter().any(|(_, other)| *other == name) {
+            name = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        self.defined.push((ty, name.clone()));
+        (name, true)
+    }
+    /// Returns the C name of `ty`, writing its definition and those of the
+    /// types it contains to `dst` first if they weren't printed yet.
+    pub fn c_name_for(&mut self, ty: &'t Ty, dst: &mut String) -> Result<String, Error> {
+        let prefix = match ty {
+            Ty::Void => return Ok("Zst".into()),
+            Ty::Never => return Err(Error::Unsupported("`!` in C")),
+            Ty::Bool => return Ok("bool".into()),
+            Ty::Int(sz) => return Ok(format!("u{}", sz * 8)),
+            // C has no valid ranges, the bytes are the same
+            Ty::Scalar(ref scalar) => return Ok(format!("u{}", scalar.size * 8)),
+            Ty::Ptr(_) => return Err(Error::Unsupported("pointer")),
+            Ty::Ref(_) => return Err(Error::Unsupported("reference")),
+            // C arrays are declarators, see `c_declarator`
+            Ty::Array(_) => return Err(Error::Unsupported("array as a C type name")),
+            // C has no interior mutability, the bytes are the same
+            Ty::UnsafeCell(ref inner) => return self.c_name_for(inner, dst),
+            Ty::Valid(ref valid) => return self.c_name_for(&valid.ty, dst),
+            Ty::Struct(_) => "Struct",
+            Ty::Union(_) => "Union",
+            Ty::Enum(_) => "TaggedUnion",
+            Ty::Opaque(_) => "Opaque",
+        };
+        let (id, fresh) = self.intern(ty, prefix);
+        if fresh {
+            self.define_c(ty, &id, dst)?;
+        }
+        Ok(id)
+    }
+    /// Returns the Rust name of `ty`, writing its definition and those of
+    /// the types it contains to `dst` first if they weren't printed yet.
+    pub fn rust_name_for(&mut self, ty: &'t Ty, dst: &mut String) -> Result<String, Error> {
+        let prefix = match ty {
+            Ty::Void => return Ok("()".into()),
+            Ty::Never => return Ok("!".into()),
+            Ty::Bool => return Ok("bool".into()),
+            Ty::Int(sz) => return Ok(format!("u{}", sz * 8)),
+            Ty::Scalar(_) => "Scalar",
+            Ty::Ptr(_) => return Err(Error::Unsupported("pointer")),
+            Ty::Ref(_) => return Err(Error::Unsupported("reference")),
+            Ty::Array(ref arr) => {
+                let ty = self.rust_name_for(&arr.element, dst)?;
+                return Ok(format!("[{}; {}]", ty, arr.count));
             }
-            Ty::Union(_) => {
-                let id = self.pos;
-                self.pos += 1;
-                format!("Union{}", id)
+            Ty::UnsafeCell(ref inner) => {
+                let ty = self.rust_name_for(inner, dst)?;
+                return Ok(format!("core::cell::UnsafeCell<{}>", ty));
             }
-            Ty::Enum(_) => {
-                let id = self.pos;
-                self.pos += 1;
-                format!("TaggedUnion{}", id)
+            // the ranges go on the field, see `valid_attr`
+            Ty::Valid(ref valid) => return self.rust_name_for(&valid.ty, dst),
+            Ty::Struct(_) => "Struct",
+            Ty::Union(_) => "Union",
+            Ty::Enum(_) => "Enum",
+            Ty::Opaque(_) => "Opaque",
+        };
+        let (id, fresh) = self.intern(ty, prefix);
+        if fresh {
+            self.define_rust(ty, &id, dst)?;
+        }
+        Ok(id)
+    }
+    /// Returns the C name of the innermost element of `ty` and the array
+    /// dimensions to declare it with, e.g. `("u8", "[2][3]")`.
+    fn c_declarator(&mut self, ty: &'t Ty, dst: &mut String) -> Result<(String, String), Error> {
+        let mut ty = ty;
+        let mut dims = String::new();
+        loop {
+            match ty {
+                Ty::Array(ref arr) => {
+                    write!(dims, "[{}]", arr.count)?;
+                    ty = &arr.element;
+                }
+                Ty::UnsafeCell(ref inner) => ty = inner,
+                _ => break,
             }
         }
+        Ok((self.c_name_for(ty, dst)?, dims))
     }
-    pub fn rust_name_for(&mut self, ty: &'t Ty) -> String {
+    fn write_c_fields(
+        &mut self, def: &mut String, dst: &mut String, prefix: &str,
+        fields: impl Iterator<Item=(&'t str, &'t Ty)>
+    ) -> Result<(), Error> {
+        for (idx, (field, ty)) in fields.enumerate() {
+            let field = member_name(field, prefix, idx);
+            let (name, dims) = self.c_declarator(ty, dst)?;
+            writeln!(def, "  {} {}{};", name, field, dims)?;
+        }
+        Ok(())
+    }
+    fn define_c(&mut self, ty: &'t Ty, id: &str, dst: &mut String) -> Result<(), Error> {
+        // the definition is assembled separately, since printing the fields
+        // appends the definitions of their types to `dst`
+        let mut def = String::new();
         match ty {
-            Ty::Void => "()".into(),
-            Ty::Bool => "bool".into(),
-            Ty::Int(sz) => format!("u{}", sz * 8),
-            Ty::Ptr(_) => unimplemented!("pointer"),
-            Ty::Ref(_) => unimplemented!("reference"),
-            Ty::Array(ref arr) => {
-                let ty = self.rust_name_for(&arr.element);
-                format!("[{}; {}]", ty, arr.count)
+            Ty::Struct(ref st) => {
+                writeln!(def, "typedef struct S_{} {{", id)?;
+                self.write_c_fields(&mut def, dst, "field",
+                    st.fields.iter().map(|f| (f.name.as_str(), &f.ty)))?;
+                writeln!(def, "}}{} {};", c_attributes(st.pack, st.align)?, id)?;
             }
-            Ty::Struct(_) => {
-                let id = self.pos;
-                self.pos += 1;
-                format!("Struct{}", id)
+            Ty::Union(ref un) => {
+                writeln!(def, "typedef union U_{} {{", id)?;
+                self.write_c_fields(&mut def, dst, "variant",
+                    un.variants.iter().map(|v| (v.name.as_str(), &v.ty)))?;
+                writeln!(def, "}}{} {};", c_attributes(un.pack, un.align)?, id)?;
             }
-            Ty::Union(_) => {
-                let id = self.pos;
-                self.pos += 1;
-                format!("Union{}", id)
+            Ty::Opaque(ref op) => {
+                // only the size and alignment are known
+                let align = Some(op.layout.align()).filter(|&align| align > 1);
+                writeln!(def, "typedef struct S_{} {{", id)?;
+                writeln!(def, "  u8 opaque[{}];", op.layout.size())?;
+                writeln!(def, "}}{} {};", c_attributes(None, align)?, id)?;
             }
-            Ty::Enum(_) => {
-                let id = self.pos;
-                self.pos += 1;
-                format!("Enum{}", id)
+            Ty::Enum(ref en) if en.repr == EnumRepr::Primitive => {
+                // a union of structs that all start with the tag
+                let tag = format!("u{} tag;", en.tag_layout.size() * 8);
+                writeln!(def, "typedef union U_{} {{", id)?;
+                writeln!(def, "  {}", tag)?;
+                for (idx, variant) in en.variants.iter().enumerate() {
+                    let mut fields = String::new();
+                    if let Ty::Struct(ref st) = variant.payload {
+                        self.write_c_fields(&mut fields, dst, "field",
+                            st.fields.iter().map(|f| (f.name.as_str(), &f.ty)))?;
+                    }
+                    writeln!(def, "  struct {{\n    {}", tag)?;
+                    for line in fields.lines() {
+                        writeln!(def, "  {}", line)?;
+                    }
+                    writeln!(def, "  }} {};", member_name(&variant.name, "variant", idx))?;
+                }
+                writeln!(def, "}} {};", id)?;
+            }
+            Ty::Enum(ref en) => {
+                writeln!(def, "typedef union U_{}_Payload {{", id)?;
+                self.write_c_fields(&mut def, dst, "variant",
+                    en.variants.iter().map(|v| (v.name.as_str(), &v.payload)))?;
+                writeln!(def, "}} {}_Payload;", id)?;
+                writeln!(def, "typedef struct S_{} {{", id)?;
+                writeln!(def, "  u{} tag;", en.tag_layout.size() * 8)?;
+                writeln!(def, "  {}_Payload payload;", id)?;
+                writeln!(def, "}} {};", id)?;
             }
+            _ => unreachable!("only named types are defined"),
         }
+        dst.push_str(&def);
+        Ok(())
     }
-    pub fn maybe_push(&mut self, ty: &'t Ty, name: String) {
-        if ty.needs_name() {
-            self.queue.push((ty, name));
+    pub fn print_c(&mut self, ty: &'t Ty) -> Result<String, Error> {
+        let mut dst = String::new();
+        self.pos = 0;
+        self.defined.clear();
+        let (name, dims) = self.c_declarator(ty, &mut dst)?;
+        writeln!(dst, "typedef {} Root{};", name, dims)?;
+        Ok(dst)
+    }
+    fn write_rust_fields(
+        &mut self, def: &mut String, dst: &mut String, prefix: &str,
+        fields: impl Iterator<Item=(&'t str, &'t Ty)>
+    ) -> Result<(), Error> {
+        for (idx, (field, ty)) in fields.enumerate() {
+            let name = self.rust_name_for(ty, dst)?;
+            writeln!(def, "  {}{}: {},", valid_attr(ty), member_name(field, prefix, idx), name)?;
         }
+        Ok(())
     }
-    fn write_c_fields(
-        &mut self, dst: &mut String, prefix: &str,
-        fields: impl Iterator<Item=&'t Ty>
-    ) -> Result<(), fmt::Error> {
-        for (idx, ty) in fields.enumerate() {
-            if let Ty::Array(ref arr) = ty {
-                let name = self.c_name_for(&arr.element);
-                writeln!(dst, "  {} {}{}[{}];",
-                    name, prefix, idx, arr.count)?;
-                self.maybe_push(&arr.element, name);
-            } else {
-                let name = self.c_name_for(ty);
-                writeln!(dst, "  {} {}{};", name, prefix, idx)?;
-                self.maybe_push(ty, name);
+    fn define_rust(&mut self, ty: &'t Ty, id: &str, dst: &mut String) -> Result<(), Error> {
+        let mut def = String::new();
+        match ty {
+            Ty::Struct(ref st) => {
+                writeln!(def, "#[repr({})]\nstruct {} {{",
+                    rust_repr(st.pack, st.align), id)?;
+                self.write_rust_fields(&mut def, dst, "field",
+                    st.fields.iter().map(|f| (f.name.as_str(), &f.ty)))?;
+                writeln!(def, "}}")?;
+            }
+            Ty::Enum(ref en) if en.repr == EnumRepr::Primitive => {
+                writeln!(def, "#[repr(u{})]\nenum {} {{", en.tag_layout.size() * 8, id)?;
+                for (idx, variant) in en.variants.iter().enumerate() {
+                    let fields = match variant.payload {
+                        Ty::Struct(ref st) => &st.fields[..],
+                        _ => &[],
+                    };
+                    let mut names = Vec::with_capacity(fields.len());
+                    for field in fields {
+                        let name = self.rust_name_for(&field.ty, dst)?;
+                        names.push((ident(&field.name), valid_attr(&field.ty), name));
+                    }
+                    let variant = member_name(&variant.name, "Var", idx);
+                    if names.is_empty() {
+                        writeln!(def, "  {},", variant)?;
+                    } else if names.iter().all(|(name, _, _)| name.is_some()) {
+                        let names = names.iter()
+                            .map(|(name, attr, ty)| {
+                                format!("{}{}: {}", attr, name.as_ref().unwrap(), ty)
+                            })
+                            .collect::<Vec<_>>();
+                        writeln!(def, "  {} {{ {} }},", variant, names.join(", "))?;
+                    } else {
+                        let names = names.into_iter()
+                            .map(|(_, attr, ty)| attr + &ty)
+                            .collect::<Vec<_>>();
+                        writeln!(def, "  {}({}),", variant, names.join(", "))?;
+                    }
+                }
+                writeln!(def, "}}")?;
+            }
+            Ty::Enum(ref en) => {
+                writeln!(def, "#[repr(C, u{})]\nenum {} {{",
+                    en.tag_layout.size() * 8, id)?;
+                for (idx, variant) in en.variants.iter().enumerate() {
+                    let name = self.rust_name_for(&variant.payload, dst)?;
+                    writeln!(def, "  {}({}),",
+                        member_name(&variant.name, "Var", idx), name)?;
+                }
+                writeln!(def, "}}")?;
             }
+            Ty::Union(ref un) => {
+                writeln!(def, "#[repr({})]\nunion {} {{",
+                    rust_repr(un.pack, un.align), id)?;
+                self.write_rust_fields(&mut def, dst, "variant",
+                    un.variants.iter().map(|v| (v.name.as_str(), &v.ty)))?;
+                writeln!(def, "}}")?;
+            }
+            Ty::Opaque(ref op) => {
+                let align = Some(op.layout.align()).filter(|&align| align > 1);
+                writeln!(def, "#[repr({})]\nstruct {}([u8; {}]);",
+                    rust_repr(None, align), id, op.layout.size())?;
+            }
+            Ty::Scalar(ref scalar) => {
+                writeln!(def, "#[repr(transparent)]")?;
+                writeln!(def, "#[rustc_layout_scalar_valid_range_start({})]", scalar.start)?;
+                writeln!(def, "#[rustc_layout_scalar_valid_range_end({})]", scalar.end)?;
+                writeln!(def, "struct {}(u{});", id, scalar.size * 8)?;
+            }
+            _ => unreachable!("only named types are defined"),
         }
+        dst.push_str(&def);
         Ok(())
     }
-    pub fn print_c(&mut self, ty: &'t Ty) -> Result<String, fmt::Error> {
-        let mut dst = Vec::new();
+    pub fn print_rust(&mut self, ty: &'t Ty) -> Result<String, Error> {
+        let mut dst = String::new();
         self.pos = 0;
-        let name = self.c_name_for(ty);
-        self.maybe_push(ty, name.clone());
-        while let Some((ty, id)) = self.queue.pop() {
-            match ty {
-                Ty::Struct(ref st) => {
-                    let mut tmp = String::new();
-                    writeln!(tmp, "typedef struct S_{} {{", id)?;
-                    self.write_c_fields(&mut tmp, "field",
-                        st.fields.iter().map(|f| &f.ty))?;
-                    writeln!(tmp, "}} {};", id)?;
-                    dst.push(tmp);
+        self.defined.clear();
+        let name = self.rust_name_for(ty, &mut dst)?;
+        writeln!(&mut dst, "type Root = {};", name)?;
+        Ok(dst)
+    }
+    /// Renders the bytes of `ty` as a table with one row per byte offset
+    /// and one column per variant of each enum and union, with the other
+    /// members in their first variant. Array elements are shown in the
+    /// same variant; listing every combination isn't helpful.
+    pub fn print_layout(&mut self, ty: &'t Ty, endian: Endian) -> Result<String, Error> {
+        let root = match ty {
+            Ty::Struct(ref st) => st.name.as_str(),
+            Ty::Union(ref un) => un.name.as_str(),
+            Ty::Enum(ref en) => en.name.as_str(),
+            Ty::Opaque(ref op) => op.name.as_str(),
+            _ => "",
+        };
+        let columns = layout_columns(ty, endian, &LayoutPath::root(root), false)?;
+        let header = |column: &LayoutColumn| if column.variants.is_empty() {
+            "bytes".to_string()
+        } else {
+            column.variants.join(", ")
+        };
+        let size = layout_of(ty)?.size();
+        let offset_width = size.saturating_sub(1).to_string().len().max("offset".len());
+        let widths = columns.iter().map(|column| {
+            column.cells.iter().map(|cell| cell.chars().count())
+                .chain(Some(header(column).chars().count()))
+                .max().unwrap_or(0)
+        }).collect::<Vec<_>>();
+
+        let mut dst = String::new();
+        let mut row = String::new();
+        write!(row, "{:>width$}", "offset", width = offset_width)?;
+        for (column, &width) in columns.iter().zip(&widths) {
+            write!(row, " | {:<width$}", header(column), width = width)?;
+        }
+        writeln!(dst, "{}", row.trim_end())?;
+        write!(dst, "{}", "-".repeat(offset_width))?;
+        for &width in &widths {
+            write!(dst, "-+-{}", "-".repeat(width))?;
+        }
+        writeln!(dst)?;
+        for offset in 0..size {
+            row.clear();
+            write!(row, "{:>width$}", offset, width = offset_width)?;
+            for (column, &width) in columns.iter().zip(&widths) {
+                write!(row, " | {:<width$}", column.cells[offset], width = width)?;
+            }
+            writeln!(dst, "{}", row.trim_end())?;
+        }
+        Ok(dst)
+    }
+}
+
+/// Most paths of each program `print_comparison` goes through.
+const MAX_COMPARED_PATHS: usize = 16;
+
+/// Prints the bytes of every pair of `dst` and `src` paths side by side,
+/// along with whether each dst byte accepts the src byte at its position.
+/// Bytes past the end of `src` are uninit, like `Execution` reads them.
+/// Only the first `MAX_COMPARED_PATHS` paths of each program are shown.
+pub fn print_comparison(dst: &Program, src: &Program) -> Result<String, fmt::Error> {
+    let label = |program: &Program, path: &ProgPath| if path.alternates.is_empty() {
+        program.name().to_string()
+    } else {
+        let ips = path.alternates.iter().map(|ip| ip.to_string()).collect::<Vec<_>>();
+        format!("{} (alternates at ip {})", program.name(), ips.join(", "))
+    };
+    let mut dst_out = String::new();
+    let paths = |program: &Program| {
+        let mut paths = program.paths(MAX_COMPARED_PATHS + 1);
+        let more = paths.len() > MAX_COMPARED_PATHS;
+        paths.truncate(MAX_COMPARED_PATHS);
+        (paths, more)
+    };
+    let (dst_paths, dst_more) = paths(dst);
+    let (src_paths, src_more) = paths(src);
+    for dst_path in &dst_paths {
+        for src_path in &src_paths {
+            let rows = dst_path.bytes.iter().enumerate().map(|(pos, (d_ip, d_byte))| {
+                let (s_ip, s_byte) = match src_path.bytes.get(pos) {
+                    Some((ip, byte)) => (ip.to_string(), byte.clone()),
+                    None => ("-".to_string(), StepByte::Uninit),
+                };
+                [
+                    pos.to_string(),
+                    format!("ip {}: {}", d_ip, d_byte),
+                    format!("ip {}: {}", s_ip, s_byte),
+                    d_byte.accepts(&s_byte).to_string(),
+                ]
+            }).collect::<Vec<_>>();
+            let header = ["pos".to_string(), label(dst, dst_path),
+                label(src, src_path), "accepts".to_string()];
+            let mut widths = [0; 4];
+            for row in rows.iter().chain(Some(&header)) {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.chars().count());
                 }
-                Ty::Union(ref un) => {
-                    let mut tmp = String::new();
-                    writeln!(tmp, "typedef union U_{} {{", id)?;
-                    self.write_c_fields(&mut tmp, "variant",
-                        un.variants.iter().map(|v| &v.ty))?;
-                    writeln!(tmp, "}} {};", id)?;
-                    dst.push(tmp);
+            }
+            let mut line = String::new();
+            for (idx, row) in Some(&header).into_iter().chain(&rows).enumerate() {
+                line.clear();
+                write!(line, "{:>width$}", row[0], width = widths[0])?;
+                for (cell, &width) in row[1..].iter().zip(&widths[1..]) {
+                    write!(line, " | {:<width$}", cell, width = width)?;
                 }
-                Ty::Enum(ref en) => {
-                    let mut tmp = String::new();
-                    writeln!(tmp, "typedef union U_{}_Payload {{", id)?;
-                    self.write_c_fields(&mut tmp, "variant",
-                        en.variants.iter().map(|v| &v.payload))?;
-                    writeln!(tmp, "}} {}_Payload;", id)?;
-                    writeln!(tmp, "typedef struct S_{} {{", id)?;
-                    writeln!(tmp, "  u{} tag;", en.tag_layout.size() * 8)?;
-                    writeln!(tmp, "  {}_Payload payload;", id)?;
-                    writeln!(tmp, "}} {};", id)?;
-                    dst.push(tmp);
+                writeln!(dst_out, "{}", line.trim_end())?;
+                if idx == 0 {
+                    let rule = widths.iter().map(|&width| "-".repeat(width))
+                        .collect::<Vec<_>>();
+                    writeln!(dst_out, "{}", rule.join("-+-"))?;
                 }
-                _ => unimplemented!(),
             }
+            writeln!(dst_out)?;
         }
-        let mut dst = dst.into_iter().rev().collect::<String>();
-        writeln!(dst, "typedef {} Root;", name)?;
-        Ok(dst)
     }
-    fn write_rust_fields(
-        &mut self, dst: &mut String, prefix: &str,
-        fields: impl Iterator<Item=&'t Ty>
-    ) -> Result<(), fmt::Error> {
-        for (idx, ty) in fields.enumerate() {
-            let name = self.rust_name_for(ty);
-            writeln!(dst, "  {}{}: {},", prefix, idx, name)?;
-            if let Ty::Array(ref arr) = ty {
-                let name = self.rust_name_for(&arr.element);
-                self.maybe_push(&arr.element, name);
-            }
-            self.maybe_push(ty, name);
+    for (side, program, more) in [("dst", dst, dst_more), ("src", src, src_more)] {
+        if more {
+            writeln!(dst_out, "{} {} has more than {} paths, the rest are not shown",
+                side, program.name(), MAX_COMPARED_PATHS)?;
         }
-        Ok(())
     }
-    pub fn print_rust(&mut self, ty: &'t Ty) -> Result<String, fmt::Error> {
-        let mut dst = String::new();
-        self.pos = 0;
-        let name = self.rust_name_for(ty);
-        self.maybe_push(ty, name.clone());
-        while let Some((ty, id)) = self.queue.pop() {
-            match ty {
-                Ty::Struct(ref st) => {
-                    writeln!(dst, "#[repr(C)]\nstruct {} {{", id)?;
-                    self.write_rust_fields(&mut dst, "field",
-                        st.fields.iter().map(|f| &f.ty))?;
-                    writeln!(dst, "}}")?;
+    Ok(dst_out)
+}
+
+/// Where a byte of a layout diagram comes from: `owner` names the field
+/// owning it, and `label` the type or field variants are qualified with.
+struct LayoutPath {
+    owner: String,
+    label: String,
+}
+
+impl LayoutPath {
+    fn root(name: &str) -> Self {
+        Self { owner: String::new(), label: ident(name).unwrap_or_default() }
+    }
+    fn member(&self, name: &str) -> Self {
+        let owner = if self.owner.is_empty() || name.starts_with('[') {
+            format!("{}{}", self.owner, name)
+        } else {
+            format!("{}.{}", self.owner, name)
+        };
+        Self { label: owner.clone(), owner }
+    }
+    fn cell(&self, kind: &str, private: bool) -> String {
+        let private = if private { " private" } else { "" };
+        if self.owner.is_empty() {
+            format!("{}{}", kind, private)
+        } else {
+            format!("{} {}{}", self.owner, kind, private)
+        }
+    }
+}
+
+/// One alternative of a layout diagram: the variants taken to get there
+/// and a description of every byte.
+#[derive(Clone)]
+struct LayoutColumn {
+    variants: Vec<String>,
+    cells: Vec<String>,
+}
+
+fn padding_column(path: &LayoutPath, size: usize) -> LayoutColumn {
+    LayoutColumn {
+        variants: Vec::new(),
+        cells: vec![path.cell("padding", false); size],
+    }
+}
+
+/// Writes `part` at `offset` into `base`.
+fn place_column(base: &LayoutColumn, offset: usize, part: &LayoutColumn) -> LayoutColumn {
+    let mut column = base.clone();
+    column.variants.extend(part.variants.iter().cloned());
+    column.cells.splice(offset..offset + part.cells.len(), part.cells.iter().cloned());
+    column
+}
+
+fn layout_columns(
+    ty: &Ty, endian: Endian, path: &LayoutPath, private: bool,
+) -> Result<Vec<LayoutColumn>, Error> {
+    let scalar = |cells: Vec<String>| vec![LayoutColumn { variants: Vec::new(), cells }];
+    let columns = match ty {
+        Ty::Void => scalar(Vec::new()),
+        // no bytes, but the column says that no value gets here
+        Ty::Never => vec![LayoutColumn {
+            variants: vec![format!("{} uninhabited", path.label).trim_start().to_string()],
+            cells: Vec::new(),
+        }],
+        Ty::Bool => scalar(vec![path.cell("0x00-0x01", private)]),
+        Ty::Int(1) => scalar(vec![path.cell("0x00-0xff", private)]),
+        &Ty::Int(size) => scalar((0..size)
+            .map(|byte| path.cell(&format!("byte {} 0x00-0xff", byte), private))
+            .collect()),
+        Ty::Scalar(ref sc) => {
+            let width = sc.size as usize * 2;
+            let range = format!("0x{:0width$x}-0x{:0width$x}", sc.start, sc.end, width = width);
+            if sc.size == 1 {
+                scalar(vec![path.cell(&range, private)])
+            } else {
+                scalar((0..sc.size)
+                    .map(|byte| path.cell(&format!("byte {} of {}", byte, range), private))
+                    .collect())
+            }
+        }
+        Ty::Ptr(ref ptr) => {
+            let range = if ptr.non_null { "not all 0x00" } else { "0x00-0xff" };
+            scalar((0..layout_of(ty)?.size())
+                .map(|byte| path.cell(&format!("pointer byte {} {}", byte, range), private))
+                .collect())
+        }
+        Ty::Ref(_) => return Err(Error::Unsupported("reference")),
+        Ty::Opaque(ref op) => scalar((0..op.layout.size())
+            .map(|byte| path.cell(&format!("opaque byte {}", byte), true))
+            .collect()),
+        Ty::UnsafeCell(ref inner) => layout_columns(inner, endian, path, private)?,
+        Ty::Valid(ref valid) => {
+            let size = layout_of(&valid.ty)?.size();
+            let width = size * 2;
+            let ranges = valid.ranges.iter()
+                .map(|&(start, end)| if start == end {
+                    format!("0x{:0width$x}", start, width = width)
+                } else {
+                    format!("0x{:0width$x}-0x{:0width$x}", start, end, width = width)
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            if size == 1 {
+                scalar(vec![path.cell(&ranges, private)])
+            } else {
+                scalar((0..size)
+                    .map(|byte| path.cell(&format!("byte {} of {}", byte, ranges), private))
+                    .collect())
+            }
+        }
+        Ty::Array(ref arr) => {
+            let stride = layout_of(&arr.element)?.size();
+            let elements = (0..arr.count)
+                .map(|idx| layout_columns(&arr.element, endian,
+                    &path.member(&format!("[{}]", idx)), private))
+                .collect::<Result<Vec<_>, _>>()?;
+            let alternatives = elements.first().map_or(1, |columns| columns.len());
+            (0..alternatives).map(|alt| {
+                let mut column = LayoutColumn { variants: Vec::new(), cells: Vec::new() };
+                for (idx, element) in elements.iter().enumerate() {
+                    column.cells.resize(idx * stride, path.cell("padding", false));
+                    column.cells.extend(element[alt].cells.iter().cloned());
+                }
+                if let Some(first) = elements.first() {
+                    column.variants = first[alt].variants.clone();
                 }
-                Ty::Enum(ref en) => {
-                    writeln!(dst, "#[repr(C, u{})]\nenum {} {{",
-                        en.tag_layout.size() * 8, id)?;
-                    for (idx, variant) in en.variants.iter().enumerate() {
-                        let name = self.rust_name_for(&variant.payload);
-                        writeln!(dst, "  Var{}({}),", idx, name)?;
-                        if let Ty::Array(ref arr) = variant.payload {
-                            let name = self.rust_name_for(&arr.element);
-                            self.maybe_push(&arr.element, name);
+                column.cells.resize(stride * arr.count, path.cell("padding", false));
+                column
+            }).collect()
+        }
+        Ty::Struct(ref st) => {
+            let fields = st.fields.iter().enumerate().map(|(idx, field)| {
+                let field_path = path.member(&member_name(&field.name, "field", idx));
+                layout_columns(&field.ty, endian, &field_path, private || field.private)
+            }).collect::<Result<Vec<_>, _>>()?;
+            // the first variant of every field, then each other variant of
+            // a field with the rest in their first one
+            let column = |alternate: Option<(usize, usize)>| {
+                st.fields.iter().zip(&fields).enumerate().fold(
+                    padding_column(path, st.layout.size()),
+                    |column, (idx, (field, parts))| {
+                        let alt = match alternate {
+                            Some((which, alt)) if which == idx => alt,
+                            _ => 0,
+                        };
+                        place_column(&column, field.offset, &parts[alt])
+                    })
+            };
+            Some(column(None)).into_iter()
+                .chain(fields.iter().enumerate().flat_map(|(idx, parts)| {
+                    (1..parts.len()).map(move |alt| (idx, alt))
+                }).map(|alternate| column(Some(alternate))))
+                .collect()
+        }
+        Ty::Union(ref un) => {
+            let mut columns = Vec::new();
+            for (idx, variant) in un.variants.iter().enumerate() {
+                let name = member_name(&variant.name, "variant", idx);
+                let variant_path = path.member(&name);
+                let mut base = padding_column(path, un.layout.size());
+                base.variants.push(format!("{}.{}", path.label, name));
+                let parts = layout_columns(&variant.ty, endian, &variant_path,
+                    private || variant.private)?;
+                columns.extend(parts.iter().map(|part| place_column(&base, 0, part)));
+            }
+            columns
+        }
+        Ty::Enum(ref en) => {
+            let payload_offset = en.payload_offset()?;
+            let tag_path = path.member("tag");
+            let mut columns = Vec::new();
+            for (idx, variant) in en.variants.iter().enumerate() {
+                let name = member_name(&variant.name, "Var", idx);
+                let mut base = padding_column(path, en.layout.size());
+                base.variants.push(format!("{}::{}", path.label, name));
+                let parts = layout_columns(&variant.payload, endian, &path.member(&name), private)?;
+                let mut placed = parts.iter()
+                    .map(|part| place_column(&base, payload_offset, part))
+                    .collect::<Vec<_>>();
+                // written last, `repr(uN)` payloads start with padding over the tag
+                for column in placed.iter_mut() {
+                    let tag = InstByte::for_literal(endian, en.tag_layout.size(), variant.disc, private);
+                    for (cell, inst) in column.cells.iter_mut().zip(tag) {
+                        if let Inst::Byte(byte) = inst {
+                            *cell = tag_path.cell(&format!("= 0x{:02x}", byte.byte), private);
                         }
-                        self.maybe_push(&variant.payload, name);
                     }
-                    writeln!(dst, "}}")?;
                 }
-                Ty::Union(ref un) => {
-                    writeln!(dst, "#[repr(C)]\nunion {} {{", id)?;
-                    self.write_rust_fields(&mut dst, "variant",
-                        un.variants.iter().map(|v| &v.ty))?;
-                    writeln!(dst, "}}")?;
-                }
-                _ => unimplemented!(),
+                columns.extend(placed);
             }
+            columns
         }
-        writeln!(&mut dst, "type Root = {};", name)?;
-        Ok(dst)
+    };
+    Ok(columns)
+}
+
+
+/// Turns a declared name into an identifier, or `None` for anonymous
+/// types and members, and tuple fields which are named by their index.
+fn ident(name: &str) -> Option<String> {
+    if name.is_empty() || name.starts_with(|ch: char| ch.is_ascii_digit()) {
+        return None;
+    }
+    // qualified names from debug info, e.g. `core::option::Option<u8>`
+    let name = name.replace("::", "_").chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || ch == '_' { ch } else { '_' })
+        .collect();
+    Some(name)
+}
+
+fn member_name(name: &str, prefix: &str, idx: usize) -> String {
+    ident(name).unwrap_or_else(|| format!("{}{}", prefix, idx))
+}
+
+fn c_attributes(pack: Option<usize>, align: Option<usize>) -> Result<String, Error> {
+    let mut attrs = Vec::new();
+    match pack {
+        Some(1) => attrs.push("packed".to_string()),
+        Some(_) => return Err(Error::Unsupported("packed(N) in C")),
+        None => {}
+    }
+    if let Some(align) = align {
+        attrs.push(format!("aligned({})", align));
+    }
+    if attrs.is_empty() {
+        Ok(String::new())
+    } else {
+        Ok(format!(" __attribute__(({}))", attrs.join(", ")))
+    }
+}
+
+/// `#[valid(...)] ` for a field of `Ty::Valid`, or arrays of it, which
+/// `derive_ty!` and `parse_rust` take back.
+fn valid_attr(ty: &Ty) -> String {
+    match ty {
+        Ty::Array(ref arr) => valid_attr(&arr.element),
+        Ty::Valid(ref valid) => format!("#[valid({})] ", valid_ranges(&valid.ranges)),
+        _ => String::new(),
+    }
+}
+
+/// `0..=1, 7`, as written in `#[valid(...)]`.
+fn valid_ranges(ranges: &[(u128, u128)]) -> String {
+    ranges.iter()
+        .map(|&(start, end)| if start == end {
+            start.to_string()
+        } else {
+            format!("{}..={}", start, end)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn rust_repr(pack: Option<usize>, align: Option<usize>) -> String {
+    let mut repr = "C".to_string();
+    match pack {
+        Some(1) => repr.push_str(", packed"),
+        Some(pack) => write!(repr, ", packed({})", pack).unwrap(),
+        None => {}
+    }
+    if let Some(align) = align {
+        write!(repr, ", align({})", align).unwrap();
+    }
+    repr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::parse::rust::parse_rust;
+
+    fn types() -> std::collections::BTreeMap<String, Ty> {
+        parse_rust("
+            #[repr(C, align(4))] struct Pair(u8, u16);
+            #[repr(C)] union U { x: u16, y: bool }
+            #[repr(C, u8)] enum E { A, B(u32), C { p: Pair, u: [U; 2] } }
+            #[repr(C, packed)] struct S { e: E, tail: [u8; 3] }
+        ").unwrap()
+    }
+
+    #[test]
+    fn rust() {
+        assert_eq!(Printer::new().print_rust(&types()["S"]).unwrap(), "\
+#[repr(C, align(4))]
+struct Pair {
+  field0: u8,
+  field1: u16,
+}
+#[repr(C)]
+union U {
+  x: u16,
+  y: bool,
+}
+#[repr(C)]
+struct E_C {
+  p: Pair,
+  u: [U; 2],
+}
+#[repr(C, u8)]
+enum E {
+  A(()),
+  B(u32),
+  C(E_C),
+}
+#[repr(C, packed)]
+struct S {
+  e: E,
+  tail: [u8; 3],
+}
+type Root = S;
+");
+    }
+
+    #[test]
+    fn c() {
+        assert_eq!(Printer::new().print_c(&types()["S"]).unwrap(), "\
+typedef struct S_Pair {
+  u8 field0;
+  u16 field1;
+} __attribute__((aligned(4))) Pair;
+typedef union U_U {
+  u16 x;
+  bool y;
+} U;
+typedef struct S_E_C {
+  Pair p;
+  U u[2];
+} E_C;
+typedef union U_E_Payload {
+  Zst A;
+  u32 B;
+  E_C C;
+} E_Payload;
+typedef struct S_E {
+  u8 tag;
+  E_Payload payload;
+} E;
+typedef struct S_S {
+  E e;
+  u8 tail[3];
+} __attribute__((packed)) S;
+typedef S Root;
+");
+    }
+
+    #[test]
+    fn scalars_round_trip() {
+        let mut st = Struct::new("S");
+        st.add_field("month", false, Ty::Scalar(Scalar::new(1, 1, 12))).unwrap();
+        st.add_field("wrap", false, Ty::Scalar(Scalar::new(2, 0xffff, 1))).unwrap();
+        let st = Ty::Struct(st);
+        let printed = Printer::new().print_rust(&st).unwrap();
+        assert!(parse_rust(&printed).unwrap()["Root"] == st, "{}", printed);
+        assert!(Printer::new().print_c(&st).unwrap().contains("  u16 wrap;"));
+    }
+
+    #[test]
+    fn valid_ranges_round_trip() {
+        let mut st = Struct::new("S");
+        st.add_field("status", false, Ty::Int(1).narrowed(vec![(0, 1), (7, 7)])).unwrap();
+        let flags = Ty::Array(Box::new(Array { element: Ty::Bool, count: 2 }));
+        st.add_field("flags", false, flags.narrowed(vec![(1, 1)])).unwrap();
+        let st = Ty::Struct(st);
+        let printed = Printer::new().print_rust(&st).unwrap();
+        assert!(printed.contains("  #[valid(0..=1, 7)] status: u8,"), "{}", printed);
+        assert!(parse_rust(&printed).unwrap()["Root"] == st, "{}", printed);
+        assert!(Printer::new().print_c(&st).unwrap().contains("  bool flags[2];"));
+    }
+
+    #[test]
+    fn same_name_types_get_a_suffix() {
+        let named = |name: &str, ty: Ty| {
+            let mut st = Struct::new(name);
+            st.add_field("v", false, ty).unwrap();
+            Ty::Struct(st)
+        };
+        let mut outer = Struct::new("Outer");
+        outer.add_field("a", false, named("T", Ty::Int(1))).unwrap();
+        outer.add_field("b", false, named("T", Ty::Int(2))).unwrap();
+        outer.add_field("c", false, named("T", Ty::Int(1))).unwrap();
+        assert_eq!(Printer::new().print_rust(&Ty::Struct(outer)).unwrap(), "\
+#[repr(C)]
+struct T {
+  v: u8,
+}
+#[repr(C)]
+struct T_1 {
+  v: u16,
+}
+#[repr(C)]
+struct Outer {
+  a: T,
+  b: T_1,
+  c: T,
+}
+type Root = Outer;
+");
+    }
+
+    #[test]
+    fn layout() {
+        let types = parse_rust("
+            #[repr(C)] union U { x: u16, y: bool }
+            #[repr(C, u8)] enum E { A, B(u16) }
+            #[repr(C)] struct L { a: u8, u: U, e: E, p: [bool; 2] }
+        ").unwrap();
+        assert_eq!(Printer::new().print_layout(&types["L"], Endian::Little).unwrap(), "\
+offset | u.x, e::A            | u.y, e::A      | u.x, e::B
+-------+----------------------+----------------+---------------------
+     0 | a 0x00-0xff          | a 0x00-0xff    | a 0x00-0xff
+     1 | padding              | padding        | padding
+     2 | u.x byte 0 0x00-0xff | u.y 0x00-0x01  | u.x byte 0 0x00-0xff
+     3 | u.x byte 1 0x00-0xff | u padding      | u.x byte 1 0x00-0xff
+     4 | e.tag = 0x00         | e.tag = 0x00   | e.tag = 0x01
+     5 | e padding            | e padding      | e padding
+     6 | e padding            | e padding      | e.B byte 0 0x00-0xff
+     7 | e padding            | e padding      | e.B byte 1 0x00-0xff
+     8 | p[0] 0x00-0x01       | p[0] 0x00-0x01 | p[0] 0x00-0x01
+     9 | p[1] 0x00-0x01       | p[1] 0x00-0x01 | p[1] 0x00-0x01
+");
+    }
+
+    #[test]
+    fn layout_of_uninhabited_fields() {
+        let types = parse_rust("
+            enum Void {}
+            #[repr(C)] struct S { a: u8, b: !, c: u16 }
+            #[repr(C)] union U { a: u8, v: Void }
+        ").unwrap();
+        assert_eq!(Printer::new().print_layout(&types["S"], Endian::Little).unwrap(), "\
+offset | b uninhabited
+-------+-------------------
+     0 | a 0x00-0xff
+     1 | padding
+     2 | c byte 0 0x00-0xff
+     3 | c byte 1 0x00-0xff
+");
+        assert_eq!(Printer::new().print_layout(&types["U"], Endian::Little).unwrap(), "\
+offset | U.a         | U.v, v uninhabited
+-------+-------------+-------------------
+     0 | a 0x00-0xff | padding
+");
+        assert_eq!(Printer::new().print_layout(&types["Void"], Endian::Little).unwrap(), "\
+offset | uninhabited
+-------+------------
+");
+    }
+
+    #[test]
+    fn comparison() {
+        let types = parse_rust("
+            #[repr(C)] union U { x: u8, y: bool }
+            #[repr(C)] struct A { a: U, b: u8 }
+            #[repr(C)] struct B { a: bool, b: u16 }
+        ").unwrap();
+        let src = Compiler::compile(&types["A"], Endian::Little, "A").unwrap();
+        let dst = Compiler::compile(&types["B"], Endian::Little, "B").unwrap();
+        assert_eq!(print_comparison(&dst, &src).unwrap(), "\
+pos | B               | A               | accepts
+----+-----------------+-----------------+---------------------------------------------
+  0 | ip 1: 0x00-0x01 | ip 3: 0x00-0xff | maybe: 0x00-0xff partly outside of 0x00-0x01
+  1 | ip 2: uninit    | ip 6: 0x00-0xff | always
+  2 | ip 3: 0x00-0xff | ip -: uninit    | never: reads uninit
+  3 | ip 3: 0x00-0xff | ip -: uninit    | never: reads uninit
+
+pos | B               | A (alternates at ip 2) | accepts
+----+-----------------+------------------------+--------------------
+  0 | ip 1: 0x00-0x01 | ip 5: 0x00-0x01        | always
+  1 | ip 2: uninit    | ip 6: 0x00-0xff        | always
+  2 | ip 3: 0x00-0xff | ip -: uninit           | never: reads uninit
+  3 | ip 3: 0x00-0xff | ip -: uninit           | never: reads uninit
+
+");
+    }
+
+    #[test]
+    fn comparison_is_bounded() {
+        let types = parse_rust("
+            #[repr(C)] union U { x: u8, y: bool }
+            #[repr(C)] struct A { a: [U; 5] }
+        ").unwrap();
+        let program = Compiler::compile(&types["A"], Endian::Little, "A").unwrap();
+        let table = print_comparison(&program, &program).unwrap();
+        assert_eq!(table.matches("\npos |").count() + 1, MAX_COMPARED_PATHS * MAX_COMPARED_PATHS);
+        assert!(table.ends_with(&format!("\
+dst A has more than {0} paths, the rest are not shown
+src A has more than {0} paths, the rest are not shown
+", MAX_COMPARED_PATHS)));
     }
 }
@@ -0,0 +1,230 @@
+use core::fmt::{self, Write};
+use std::path::Path;
+
+use crate::{Counterexample, Verdict};
+
+/// One `(src, dst)` pair to check, from a manifest line of the form
+/// `<types file> <Src> <Dst>`. Blank lines and lines starting with `#`
+/// are skipped, relative paths are relative to the manifest.
+pub struct ManifestEntry {
+    pub path: String,
+    pub src: String,
+    pub dst: String,
+}
+
+pub fn parse_manifest(text: &str, base: &Path) -> Result<Vec<ManifestEntry>, String> {
+    let mut entries = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [path, src, dst] => entries.push(ManifestEntry {
+                path: base.join(path).to_string_lossy().into_owned(),
+                src: src.to_string(),
+                dst: dst.to_string(),
+            }),
+            _ => {
+                return Err(format!("line {}: expected `<types file> <Src> <Dst>`", idx + 1));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// What checking one manifest entry produced.
+pub struct Checked {
+    pub verdict: Verdict,
+    pub counterexamples: Vec<Counterexample>,
+    pub src_layout: String,
+    pub dst_layout: String,
+}
+
+pub struct ReportEntry {
+    pub entry: ManifestEntry,
+    /// The check, or why it couldn't be run.
+    pub outcome: Result<Checked, String>,
+}
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }
+pre { background: #f6f6f6; padding: 0.5em; overflow-x: auto; }
+.yes { color: #1a7f37; font-weight: bold; }
+.no { color: #cf222e; font-weight: bold; }
+.error { color: #9a6700; font-weight: bold; }
+.layouts { display: flex; gap: 1em; }
+.layouts > div { flex: 1; min-width: 0; }
+.failing { background: #ffd7d5; }
+";
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Renders a single HTML page with a summary table and, for every entry,
+/// its verdict, rejects, counterexamples and the layouts of both types.
+pub fn html_report(entries: &[ReportEntry]) -> Result<String, fmt::Error> {
+    let mut dst = String::new();
+    writeln!(dst, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(dst, "<title>transmute-vm report</title>\n<style>\n{}</style>\n</head>\n<body>", STYLE)?;
+    writeln!(dst, "<h1>transmute-vm report</h1>")?;
+
+    writeln!(dst, "<table>\n<tr><th>#</th><th>file</th><th>src</th><th>dst</th><th>verdict</th></tr>")?;
+    for (idx, entry) in entries.iter().enumerate() {
+        let (class, verdict) = summary(&entry.outcome);
+        writeln!(dst, "<tr><td><a href=\"#check{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td>\
+            <td class=\"{}\">{}</td></tr>",
+            idx, idx, escape(&entry.entry.path), escape(&entry.entry.src),
+            escape(&entry.entry.dst), class, verdict)?;
+    }
+    writeln!(dst, "</table>")?;
+
+    for (idx, entry) in entries.iter().enumerate() {
+        let (class, verdict) = summary(&entry.outcome);
+        writeln!(dst, "<h2 id=\"check{}\">{} &rarr; {} <span class=\"{}\">{}</span></h2>",
+            idx, escape(&entry.entry.src), escape(&entry.entry.dst), class, verdict)?;
+        writeln!(dst, "<p>{}</p>", escape(&entry.entry.path))?;
+        let checked = match &entry.outcome {
+            Ok(checked) => checked,
+            Err(err) => {
+                writeln!(dst, "<pre>{}</pre>", escape(err))?;
+                continue;
+            }
+        };
+        if !checked.verdict.rejects.is_empty() {
+            writeln!(dst, "<h3>Rejects</h3>")?;
+            writeln!(dst, "<table>\n<tr><th>src ip</th><th>dst ip</th><th>reason</th></tr>")?;
+            for reject in checked.verdict.rejects.iter() {
+                let src = reject.src.map_or("-".to_string(), |ip| ip.to_string());
+                writeln!(dst, "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    src, reject.dst, escape(&reject.reason.to_string()))?;
+            }
+            writeln!(dst, "</table>")?;
+        }
+        if !checked.counterexamples.is_empty() {
+            writeln!(dst, "<h3>Counterexamples</h3>")?;
+            writeln!(dst, "<p>Src bytes from offset 0, <code>uu</code> is uninit. \
+                The highlighted byte is rejected.</p>")?;
+            for example in checked.counterexamples.iter() {
+                write!(dst, "<pre>")?;
+                for (pos, byte) in example.bytes.iter().enumerate() {
+                    let text = byte.map_or("uu".to_string(), |byte| format!("{:02x}", byte));
+                    if pos + 1 == example.bytes.len() {
+                        write!(dst, "<span class=\"failing\">{}</span>", text)?;
+                    } else {
+                        write!(dst, "{} ", text)?;
+                    }
+                }
+                writeln!(dst, "  ({})</pre>", escape(&example.reject.reason.to_string()))?;
+            }
+        }
+        writeln!(dst, "<div class=\"layouts\">")?;
+        writeln!(dst, "<div><h3>src {}</h3><pre>{}</pre></div>",
+            escape(&entry.entry.src), escape(&checked.src_layout))?;
+        writeln!(dst, "<div><h3>dst {}</h3><pre>{}</pre></div>",
+            escape(&entry.entry.dst), escape(&checked.dst_layout))?;
+        writeln!(dst, "</div>")?;
+    }
+    writeln!(dst, "</body>\n</html>")?;
+    Ok(dst)
+}
+
+fn summary(outcome: &Result<Checked, String>) -> (&'static str, &'static str) {
+    match outcome {
+        Ok(checked) if checked.verdict.accepted() => ("yes", "accepted"),
+        Ok(_) => ("no", "rejected"),
+        Err(_) => ("error", "error"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Reject;
+    use crate::inst::{AcceptState, RangeInclusive};
+
+    fn entry(path: &str, src: &str, dst: &str) -> ManifestEntry {
+        ManifestEntry { path: path.to_string(), src: src.to_string(), dst: dst.to_string() }
+    }
+
+    #[test]
+    fn manifest() {
+        let entries = parse_manifest("# pairs\n\ntypes.rs A B\n/abs.h C D\n", Path::new("dir")).unwrap();
+        let entries = entries.iter()
+            .map(|entry| (entry.path.as_str(), entry.src.as_str(), entry.dst.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(entries, [("dir/types.rs", "A", "B"), ("/abs.h", "C", "D")]);
+        assert_eq!(parse_manifest("types.rs A\n", Path::new("")).err().unwrap(),
+            "line 1: expected `<types file> <Src> <Dst>`");
+    }
+
+    #[test]
+    fn report() {
+        let reason = AcceptState::NeverOutOfRange(
+            RangeInclusive { start: 0x00, end: 0x01 }, RangeInclusive { start: 0x02, end: 0xff });
+        let reject = Reject { src: Some(3), dst: 0, reason };
+        let entries = [
+            ReportEntry {
+                entry: entry("a&b.rs", "U8", "Bool"),
+                outcome: Ok(Checked {
+                    verdict: Verdict {
+                        src: "U8".to_string(),
+                        dst: "Bool".to_string(),
+                        rejects: vec![reject.clone()],
+                    },
+                    counterexamples: vec![Counterexample { reject, bytes: vec![None, Some(0x02)] }],
+                    src_layout: "<u8>".to_string(),
+                    dst_layout: "\"bool\"".to_string(),
+                }),
+            },
+            ReportEntry {
+                entry: entry("t.rs", "Vec<u8>", "B"),
+                outcome: Err("t.rs: no type named `Vec<u8>`".to_string()),
+            },
+        ];
+        let html = html_report(&entries).unwrap();
+        let (head, body) = html.split_once("</style>\n").unwrap();
+        assert!(head.starts_with("<!DOCTYPE html>\n"));
+        assert_eq!(body, r##"</head>
+<body>
+<h1>transmute-vm report</h1>
+<table>
+<tr><th>#</th><th>file</th><th>src</th><th>dst</th><th>verdict</th></tr>
+<tr><td><a href="#check0">0</a></td><td>a&amp;b.rs</td><td>U8</td><td>Bool</td><td class="no">rejected</td></tr>
+<tr><td><a href="#check1">1</a></td><td>t.rs</td><td>Vec&lt;u8&gt;</td><td>B</td><td class="error">error</td></tr>
+</table>
+<h2 id="check0">U8 &rarr; Bool <span class="no">rejected</span></h2>
+<p>a&amp;b.rs</p>
+<h3>Rejects</h3>
+<table>
+<tr><th>src ip</th><th>dst ip</th><th>reason</th></tr>
+<tr><td>3</td><td>0</td><td>never: 0x02-0xff outside of 0x00-0x01</td></tr>
+</table>
+<h3>Counterexamples</h3>
+<p>Src bytes from offset 0, <code>uu</code> is uninit. The highlighted byte is rejected.</p>
+<pre>uu <span class="failing">02</span>  (never: 0x02-0xff outside of 0x00-0x01)</pre>
+<div class="layouts">
+<div><h3>src U8</h3><pre>&lt;u8&gt;</pre></div>
+<div><h3>dst Bool</h3><pre>&quot;bool&quot;</pre></div>
+</div>
+<h2 id="check1">Vec&lt;u8&gt; &rarr; B <span class="error">error</span></h2>
+<p>t.rs</p>
+<pre>t.rs: no type named `Vec&lt;u8&gt;`</pre>
+</body>
+</html>
+"##);
+    }
+}
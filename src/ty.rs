@@ -1,124 +1,424 @@
 use core::alloc::Layout;
 
+use crate::error::Error;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Ty {
     Void,
+    /// `!`, a zero-sized type without values.
+    Never,
     Bool,
     Int(u32),
+    Scalar(Scalar),
     Ptr(Pointer),
     Ref(Reference),
     Struct(Struct),
     Array(Box<Array>),
     Enum(Enum),
     Union(Union),
+    Opaque(Opaque),
+    /// `UnsafeCell<T>`: `T`, but mutable through shared references.
+    UnsafeCell(Box<Ty>),
+    /// A bool or integer narrowed by `#[valid(...)]` on a field.
+    Valid(Box<Valid>),
 }
-impl Ty {
-    pub fn needs_name(&self) -> bool {
-        matches!(self, Ty::Struct(_) | Ty::Enum(_) | Ty::Union(_))
-    }
-}
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum RefKind {
     Shared,
     Unique,
 }
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Pointer {
     pub kind: RefKind,
     pub align: usize,
+    /// `NonNull<T>`: zero isn't a valid address.
+    pub non_null: bool,
 }
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Reference {
     pub kind: RefKind,
     pub size: usize,
     pub align: usize,
 }
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Struct {
-    pub name: &'static str,
+    pub name: String,
     pub layout: Layout,
+    /// `repr(packed(N))`: fields are aligned to at most `N` bytes.
+    pub pack: Option<usize>,
+    /// `repr(align(N))`: the struct is aligned to at least `N` bytes.
+    pub align: Option<usize>,
     pub fields: Vec<Field>,
 }
 impl Struct {
-    pub fn new(name: &'static str) -> Self {
-        Self {
-            name,
-            layout: Layout::from_size_align(0, 1).unwrap(),
+    pub fn new(name: impl Into<String>) -> Self {
+        Self::with_repr(name, None, None).expect("no repr is a valid repr")
+    }
+    /// Fails unless `pack` and `align` are powers of two.
+    pub fn with_repr(
+        name: impl Into<String>, pack: Option<usize>, align: Option<usize>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            name: name.into(),
+            layout: repr_layout(pack, align)?,
+            pack,
+            align,
             fields: Vec::new(),
+        })
+    }
+    pub fn add_field(&mut self, name: impl Into<String>, private: bool, ty: Ty) -> Result<(), Error> {
+        let ty_layout = packed_layout(layout_of(&ty)?, self.pack)?;
+        let (field_layout, offset) = Layout::from_size_align(self.end()?, 1)
+            .and_then(|end| end.extend(ty_layout))
+            .map_err(|_| Error::LayoutOverflow)?;
+        self.layout = Layout::from_size_align(
+            field_layout.size(),
+            self.layout.align().max(field_layout.align())
+        ).map_err(|_| Error::LayoutOverflow)?.pad_to_align();
+        self.fields.push(Field { name: name.into(), private, offset, ty });
+        Ok(())
+    }
+    /// Adds a field at a known offset, e.g. one read from debug info.
+    /// Fields must be added in order of their offsets and not overlap.
+    pub fn add_field_at(
+        &mut self, name: impl Into<String>, private: bool, offset: usize, ty: Ty,
+    ) -> Result<(), Error> {
+        let end = self.end()?;
+        if offset < end {
+            return Err(Error::OverlappingField { offset, end });
         }
+        let field_layout = packed_layout(layout_of(&ty)?, self.pack)?;
+        let size = offset.checked_add(field_layout.size()).ok_or(Error::LayoutOverflow)?;
+        self.layout = Layout::from_size_align(
+            self.layout.size().max(size),
+            self.layout.align().max(field_layout.align())
+        ).map_err(|_| Error::LayoutOverflow)?.pad_to_align();
+        self.fields.push(Field { name: name.into(), private, offset, ty });
+        Ok(())
     }
-    pub fn add_field(&mut self, private: bool, ty: Ty) {
-        self.layout = self.layout.extend(layout_of(&ty)).unwrap().0;
-        self.fields.push(Field { private, ty });
+    /// End of the last field, not including trailing padding.
+    fn end(&self) -> Result<usize, Error> {
+        match self.fields.last() {
+            Some(field) => Ok(field.offset + layout_of(&field.ty)?.size()),
+            None => Ok(0),
+        }
     }
 }
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Field {
+    /// Empty for unnamed fields, e.g. C11 anonymous members.
+    pub name: String,
     pub private: bool,
+    /// Offset of the field from the start of the struct.
+    pub offset: usize,
     pub ty: Ty,
 }
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Array {
     pub element: Ty,
     pub count: usize,
 }
+/// Where the payloads of an enum are placed relative to its tag.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EnumRepr {
+    /// `repr(C, uN)`: the tag followed by a union of the payloads, which
+    /// all start at the same offset.
+    C,
+    /// `repr(uN)` with data (RFC 2195): a union of `repr(C)` structs that
+    /// each start with the tag. Payloads are these structs without the
+    /// tag field, placed at offset 0 with their fields after the tag.
+    Primitive,
+}
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Enum {
-    pub name: &'static str,
+    pub name: String,
     pub layout: Layout,
+    pub repr: EnumRepr,
     pub tag_layout: Layout,
     pub payload_layout: Layout,
     pub variants: Vec<EnumVariant>,
 }
 impl Enum {
-    pub fn new(name: &'static str, disc_size: u32) -> Self {
-        let tag_layout = layout_of(&Ty::Int(disc_size));
-        Self {
-            name,
+    pub fn new(name: impl Into<String>, disc_size: u32) -> Result<Self, Error> {
+        Self::with_repr(name, disc_size, EnumRepr::C)
+    }
+    pub fn with_repr(name: impl Into<String>, disc_size: u32, repr: EnumRepr) -> Result<Self, Error> {
+        let tag_layout = layout_of(&Ty::Int(disc_size))?;
+        Ok(Self {
+            name: name.into(),
             layout: tag_layout,
+            repr,
             tag_layout,
             payload_layout: Layout::from_size_align(0, 1).unwrap(),
             variants: Vec::new(),
-        }
+        })
     }
-    pub fn add_variant(&mut self, disc: u128, payload: Ty) {
-        let ty_layout = layout_of(&payload);
+    /// Adds a variant with a payload placed according to `repr`, see
+    /// `add_variant_fields` to build `EnumRepr::Primitive` payloads.
+    pub fn add_variant(&mut self, name: impl Into<String>, disc: u128, payload: Ty) -> Result<(), Error> {
+        let ty_layout = layout_of(&payload)?;
         self.payload_layout = Layout::from_size_align(
             self.payload_layout.size().max(ty_layout.size()),
             self.payload_layout.align().max(ty_layout.align())
-        ).unwrap();
-        self.layout = self.tag_layout.extend(self.payload_layout).unwrap().0;
-        self.variants.push(EnumVariant { disc, payload });
+        ).map_err(|_| Error::LayoutOverflow)?.pad_to_align();
+        self.layout = match self.repr {
+            EnumRepr::C => self.tag_layout.extend(self.payload_layout)
+                .map_err(|_| Error::LayoutOverflow)?.0,
+            EnumRepr::Primitive => Layout::from_size_align(
+                self.tag_layout.size().max(self.payload_layout.size()),
+                self.tag_layout.align().max(self.payload_layout.align())
+            ).map_err(|_| Error::LayoutOverflow)?,
+        }.pad_to_align();
+        self.variants.push(EnumVariant { name: name.into(), disc, payload });
+        Ok(())
+    }
+    /// Adds a variant with the given fields, e.g. `A(u8, u32)`, wrapped
+    /// in a struct named `{enum}_{variant}` and laid out as `repr` requires.
+    pub fn add_variant_fields(
+        &mut self, name: impl Into<String>, disc: u128,
+        fields: impl IntoIterator<Item=(String, bool, Ty)>,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        let mut st = Struct::new(format!("{}_{}", self.name, name));
+        match self.repr {
+            EnumRepr::C => {
+                for (field, private, ty) in fields {
+                    st.add_field(field, private, ty)?;
+                }
+            }
+            EnumRepr::Primitive => {
+                // `#[repr(C)] struct { tag: uN, fields.. }` without the tag
+                let mut layout = self.tag_layout;
+                for (field, private, ty) in fields {
+                    let (extended, offset) = layout.extend(layout_of(&ty)?)
+                        .map_err(|_| Error::LayoutOverflow)?;
+                    layout = extended;
+                    st.add_field_at(field, private, offset, ty)?;
+                }
+                st.layout = layout.pad_to_align();
+            }
+        }
+        self.add_variant(name, disc, Ty::Struct(st))
+    }
+    /// Offset of the payloads from the start of the enum.
+    pub fn payload_offset(&self) -> Result<usize, Error> {
+        match self.repr {
+            EnumRepr::C => Ok(self.tag_layout.extend(self.payload_layout)
+                .map_err(|_| Error::LayoutOverflow)?.1),
+            EnumRepr::Primitive => Ok(0),
+        }
     }
 }
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct EnumVariant {
+    pub name: String,
     pub disc: u128,
     pub payload: Ty,
 }
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Union {
-    pub name: &'static str,
+    pub name: String,
     pub layout: Layout,
+    /// `repr(packed(N))`: variants are aligned to at most `N` bytes.
+    pub pack: Option<usize>,
+    /// `repr(align(N))`: the union is aligned to at least `N` bytes.
+    pub align: Option<usize>,
     pub variants: Vec<UnionVariant>,
 }
 impl Union {
-    pub fn new(name: &'static str) -> Self {
-        Self {
-            name,
-            layout: Layout::from_size_align(0, 1).unwrap(),
+    pub fn new(name: impl Into<String>) -> Self {
+        Self::with_repr(name, None, None).expect("no repr is a valid repr")
+    }
+    /// Fails unless `pack` and `align` are powers of two.
+    pub fn with_repr(
+        name: impl Into<String>, pack: Option<usize>, align: Option<usize>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            name: name.into(),
+            layout: repr_layout(pack, align)?,
+            pack,
+            align,
             variants: Vec::new(),
-        }
+        })
     }
-    pub fn add_variant(&mut self, private: bool, variant: Ty) {
-        let ty_layout = layout_of(&variant);
+    pub fn add_variant(&mut self, name: impl Into<String>, private: bool, variant: Ty) -> Result<(), Error> {
+        let ty_layout = packed_layout(layout_of(&variant)?, self.pack)?;
         self.layout = Layout::from_size_align(
             self.layout.size().max(ty_layout.size()),
             self.layout.align().max(ty_layout.align())
-        ).unwrap();
-        self.variants.push(UnionVariant { private, ty: variant });
+        ).map_err(|_| Error::LayoutOverflow)?.pad_to_align();
+        self.variants.push(UnionVariant { name: name.into(), private, ty: variant });
+        Ok(())
     }
 }
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct UnionVariant {
+    /// Empty for unnamed variants, e.g. C11 anonymous members.
+    pub name: String,
     pub private: bool,
     pub ty: Ty,
 }
 
-pub fn layout_of(ty: &Ty) -> Layout {
-    match ty {
-        Ty::Void => Layout::from_size_align(0, 1).unwrap(),
+/// A type known only by its size and alignment, e.g. a `repr(Rust)` type
+/// or one from another crate. Its bytes can be neither read nor written,
+/// but it can be transmuted into itself.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Opaque {
+    pub name: String,
+    pub layout: Layout,
+}
+impl Opaque {
+    pub fn new(name: impl Into<String>, layout: Layout) -> Self {
+        Self { name: name.into(), layout }
+    }
+    /// Tells opaque types apart in compiled programs: the same for types
+    /// with the same name and layout.
+    pub fn id(&self) -> u64 {
+        // FNV-1a, 64 bit
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let size = self.layout.size() as u64;
+        let align = self.layout.align() as u64;
+        let bytes = self.name.bytes().chain(size.to_le_bytes()).chain(align.to_le_bytes());
+        for byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+}
+
+/// An integer only valid in `start..=end`, wrapping around past the
+/// largest value if `start > end`, like the bounds set by
+/// `rustc_layout_scalar_valid_range_start/end`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Scalar {
+    pub size: u32,
+    pub start: u128,
+    pub end: u128,
+}
+impl Scalar {
+    pub fn new(size: u32, start: u128, end: u128) -> Self {
+        Self { size, start, end }
+    }
+    /// `NonZeroU16` and the like, signed ones too.
+    pub fn non_zero(size: u32) -> Self {
+        Self::new(size, 1, int_max(size))
+    }
+    pub fn wraps(&self) -> bool {
+        self.start > self.end
+    }
+}
+
+/// `ty`, a bool or integer, only valid in `ranges`: inclusive ranges of
+/// its unsigned values, e.g. `[(0, 1), (7, 7)]`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Valid {
+    pub ty: Ty,
+    pub ranges: Vec<(u128, u128)>,
+}
+
+/// Largest value of a `size` byte unsigned integer.
+pub fn int_max(size: u32) -> u128 {
+    u128::MAX.checked_shr(128 - size.min(16) * 8).unwrap_or(0)
+}
+
+fn packed_layout(layout: Layout, pack: Option<usize>) -> Result<Layout, Error> {
+    match pack {
+        Some(pack) => Layout::from_size_align(layout.size(), layout.align().min(pack))
+            .map_err(|_| Error::InvalidAlign(pack)),
+        None => Ok(layout),
+    }
+}
+
+/// The layout of a struct or union without members.
+fn repr_layout(pack: Option<usize>, align: Option<usize>) -> Result<Layout, Error> {
+    if let Some(pack) = pack.filter(|pack| !pack.is_power_of_two()) {
+        return Err(Error::InvalidAlign(pack));
+    }
+    let align = align.unwrap_or(1);
+    Layout::from_size_align(0, align).map_err(|_| Error::InvalidAlign(align))
+}
+
+impl Ty {
+    /// Whether the type has no values: `!`, enums and unions without
+    /// inhabited variants, and anything containing an uninhabited field.
+    pub fn is_uninhabited(&self) -> bool {
+        match self {
+            Ty::Never => true,
+            Ty::Void | Ty::Bool | Ty::Int(_) | Ty::Scalar(_) | Ty::Ptr(_) | Ty::Ref(_)
+                | Ty::Opaque(_) => false,
+            Ty::Array(ref arr) => arr.count > 0 && arr.element.is_uninhabited(),
+            Ty::Struct(ref st) => st.fields.iter().any(|field| field.ty.is_uninhabited()),
+            Ty::Enum(ref en) => en.variants.iter().all(|variant| variant.payload.is_uninhabited()),
+            Ty::Union(ref un) => un.variants.iter().all(|variant| variant.ty.is_uninhabited()),
+            Ty::UnsafeCell(ref inner) => inner.is_uninhabited(),
+            Ty::Valid(ref valid) => valid.ty.is_uninhabited()
+                || matches!(self.valid_values(), Some(values) if values.is_empty()),
+        }
+    }
+    /// Whether the type holds an `UnsafeCell` in place, not behind a
+    /// pointer, i.e. isn't `Freeze`. Opaque types are taken not to.
+    pub fn contains_cell(&self) -> bool {
+        match self {
+            Ty::UnsafeCell(_) => true,
+            Ty::Void | Ty::Never | Ty::Bool | Ty::Int(_) | Ty::Scalar(_) | Ty::Ptr(_)
+                | Ty::Ref(_) | Ty::Opaque(_) => false,
+            Ty::Array(ref arr) => arr.count > 0 && arr.element.contains_cell(),
+            Ty::Struct(ref st) => st.fields.iter().any(|field| field.ty.contains_cell()),
+            Ty::Enum(ref en) => en.variants.iter().any(|variant| variant.payload.contains_cell()),
+            Ty::Union(ref un) => un.variants.iter().any(|variant| variant.ty.contains_cell()),
+            Ty::Valid(ref valid) => valid.ty.contains_cell(),
+        }
+    }
+    /// Narrows the type, or the elements of an array, to `ranges`.
+    pub fn narrowed(self, ranges: Vec<(u128, u128)>) -> Ty {
+        match self {
+            Ty::Array(mut arr) => {
+                arr.element = arr.element.narrowed(ranges);
+                Ty::Array(arr)
+            }
+            ty => Ty::Valid(Box::new(Valid { ty, ranges })),
+        }
+    }
+    /// Valid values of a bool or integer, as sorted inclusive ranges that
+    /// neither overlap nor touch. `None` for any other type.
+    pub fn valid_values(&self) -> Option<Vec<(u128, u128)>> {
+        let values = match *self {
+            Ty::Bool => vec![(0, 1)],
+            Ty::Int(size) => vec![(0, int_max(size))],
+            Ty::Scalar(ref sc) if sc.wraps() => vec![(0, sc.end), (sc.start, int_max(sc.size))],
+            Ty::Scalar(ref sc) => vec![(sc.start, sc.end)],
+            Ty::Valid(ref valid) => {
+                let mut values = Vec::new();
+                for (start, end) in valid.ty.valid_values()? {
+                    for &(lo, hi) in &valid.ranges {
+                        let (lo, hi) = (lo.max(start), hi.min(end));
+                        if lo <= hi {
+                            values.push((lo, hi));
+                        }
+                    }
+                }
+                values.sort_unstable();
+                let mut merged: Vec<(u128, u128)> = Vec::new();
+                for (lo, hi) in values {
+                    match merged.last_mut() {
+                        Some(last) if lo <= last.1.saturating_add(1) => last.1 = last.1.max(hi),
+                        _ => merged.push((lo, hi)),
+                    }
+                }
+                merged
+            }
+            _ => return None,
+        };
+        Some(values)
+    }
+}
+
+pub fn layout_of(ty: &Ty) -> Result<Layout, Error> {
+    let layout = match ty {
+        Ty::Void | Ty::Never => Layout::from_size_align(0, 1).unwrap(),
         Ty::Bool => Layout::from_size_align(1, 1).unwrap(),
-        Ty::Int(size) => {
+        Ty::Int(size) | Ty::Scalar(Scalar { size, .. }) => {
             let size = *size as usize;
             let align = match size {
                 1 => 1,  // u8
@@ -126,19 +426,25 @@ pub fn layout_of(ty: &Ty) -> Layout {
                 4 => 4,  // u32
                 8 => 8,  // u64
                 16 => 8, // u128
-                _ => { panic!("invalid int size!"); }
+                _ => { return Err(Error::InvalidIntSize(size as u32)); }
             };
             Layout::from_size_align(size, align).unwrap()
         }
-        Ty::Ref(_) => unimplemented!(),
-        Ty::Ptr(_) => unimplemented!(),
+        Ty::Ref(_) => return Err(Error::Unsupported("reference")),
+        // thin pointers of the host, like the `InspectTy` impls
+        Ty::Ptr(_) => Layout::new::<*const u8>(),
         Ty::Array(ref arr) => {
-            layout_of(&arr.element).repeat(arr.count).expect("array too big").0
+            layout_of(&arr.element)?.repeat(arr.count)
+                .map_err(|_| Error::LayoutOverflow)?.0
         }
         Ty::Struct(ref st) => st.layout,
         Ty::Enum(ref en) => en.layout,
         Ty::Union(ref un) => un.layout,
-    }
+        Ty::Opaque(ref op) => op.layout,
+        Ty::UnsafeCell(ref inner) => layout_of(inner)?,
+        Ty::Valid(ref valid) => layout_of(&valid.ty)?,
+    };
+    Ok(layout)
 }
 
 #[derive(Clone, Copy)]
@@ -146,3 +452,22 @@ pub enum Endian {
     Little,
     Big,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_errors() {
+        assert_eq!(layout_of(&Ty::Int(3)), Err(Error::InvalidIntSize(3)));
+        assert_eq!(Struct::with_repr("S", Some(3), None).err(), Some(Error::InvalidAlign(3)));
+        assert_eq!(Union::with_repr("U", None, Some(6)).err(), Some(Error::InvalidAlign(6)));
+        assert_eq!(Enum::new("E", 3).err(), Some(Error::InvalidIntSize(3)));
+        let mut st = Struct::new("S");
+        st.add_field_at("a", false, 2, Ty::Int(2)).unwrap();
+        assert_eq!(st.add_field_at("b", false, 3, Ty::Int(1)),
+            Err(Error::OverlappingField { offset: 3, end: 4 }));
+        let big = Ty::Array(Box::new(Array { element: Ty::Int(8), count: usize::MAX / 8 }));
+        assert_eq!(layout_of(&big), Err(Error::LayoutOverflow));
+    }
+}
@@ -0,0 +1,333 @@
+use core::fmt;
+
+use crate::ty::*;
+
+/// Something `Ty::validate` found wrong with a type, at `path`:
+/// the root type's name followed by fields (`.a`), enum variants (`::A`)
+/// and array elements (`[]`) leading to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub path: String,
+    pub kind: ProblemKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// `Ty::Int` or an enum tag of a size other than 1, 2, 4, 8 or 16 bytes.
+    InvalidIntSize(usize),
+    /// The size of the type doesn't fit in `isize`.
+    LayoutOverflow,
+    DuplicateDiscriminant(u128),
+    DiscriminantTooLarge { disc: u128, tag_size: usize },
+    /// A bound of a `Ty::Scalar` or `Ty::Valid` range that doesn't fit its size.
+    BoundTooLarge { bound: u128, size: usize },
+    /// A `Ty::Valid` range ending before its start.
+    EmptyRange { start: u128, end: u128 },
+    /// `Ty::Valid` narrowing something other than a bool or integer.
+    NotNarrowable,
+    /// A field starting before the end of the field preceding it.
+    OverlappingField { offset: usize, end: usize },
+    /// A field or variant ending past the size of its parent.
+    OutOfBounds { end: usize, size: usize },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() { "<root>" } else { &self.path };
+        write!(f, "{}: ", path)?;
+        match self.kind {
+            ProblemKind::InvalidIntSize(size) => write!(f, "invalid int size {}", size),
+            ProblemKind::LayoutOverflow => write!(f, "type is too large"),
+            ProblemKind::DuplicateDiscriminant(disc) => {
+                write!(f, "discriminant {} is used more than once", disc)
+            }
+            ProblemKind::DiscriminantTooLarge { disc, tag_size } => {
+                write!(f, "discriminant {} does not fit a {} byte tag", disc, tag_size)
+            }
+            ProblemKind::BoundTooLarge { bound, size } => {
+                write!(f, "valid range bound {} does not fit {} bytes", bound, size)
+            }
+            ProblemKind::EmptyRange { start, end } => {
+                write!(f, "valid range {}..={} is empty", start, end)
+            }
+            ProblemKind::NotNarrowable => write!(f, "only bools and integers have valid ranges"),
+            ProblemKind::OverlappingField { offset, end } => write!(f,
+                "field at offset {} overlaps the previous field ending at {}", offset, end),
+            ProblemKind::OutOfBounds { end, size } => {
+                write!(f, "ends at offset {}, past the size {}", end, size)
+            }
+        }
+    }
+}
+
+impl Ty {
+    /// Checks everything the compiler relies on but the builders can't
+    /// enforce, e.g. for types with hand-written layouts or variants.
+    pub fn validate(&self) -> Vec<Problem> {
+        let root = match self {
+            Ty::Struct(ref st) => st.name.as_str(),
+            Ty::Enum(ref en) => en.name.as_str(),
+            Ty::Union(ref un) => un.name.as_str(),
+            Ty::Opaque(ref op) => op.name.as_str(),
+            _ => "",
+        };
+        let mut problems = Vec::new();
+        validate(self, root, &mut problems);
+        problems
+    }
+}
+
+fn member(path: &str, sep: &str, name: &str, idx: usize) -> String {
+    let name = if name.is_empty() { idx.to_string() } else { name.to_string() };
+    if path.is_empty() && sep == "." {
+        name
+    } else {
+        format!("{}{}{}", path, sep, name)
+    }
+}
+
+fn report(problems: &mut Vec<Problem>, path: &str, kind: ProblemKind) {
+    problems.push(Problem { path: path.to_string(), kind });
+}
+
+fn validate(ty: &Ty, path: &str, problems: &mut Vec<Problem>) {
+    match ty {
+        Ty::Void | Ty::Never | Ty::Bool | Ty::Ptr(_) | Ty::Ref(_) | Ty::Opaque(_) => {}
+        &Ty::Int(size) => {
+            if !matches!(size, 1 | 2 | 4 | 8 | 16) {
+                report(problems, path, ProblemKind::InvalidIntSize(size as usize));
+            }
+        }
+        Ty::Scalar(ref scalar) => {
+            let size = scalar.size as usize;
+            if !matches!(size, 1 | 2 | 4 | 8 | 16) {
+                report(problems, path, ProblemKind::InvalidIntSize(size));
+                return;
+            }
+            for bound in [scalar.start, scalar.end] {
+                if bound > int_max(scalar.size) {
+                    report(problems, path, ProblemKind::BoundTooLarge { bound, size });
+                }
+            }
+        }
+        Ty::Array(ref arr) => {
+            // an invalid element is reported on its own, not as an overflow
+            let element = layout_of(&arr.element);
+            if element.is_ok_and(|layout| layout.repeat(arr.count).is_err()) {
+                report(problems, path, ProblemKind::LayoutOverflow);
+            }
+            validate(&arr.element, &format!("{}[]", path), problems);
+        }
+        Ty::Struct(ref st) => {
+            let size = st.layout.size();
+            let mut end = 0;
+            for (idx, field) in st.fields.iter().enumerate() {
+                let field_path = member(path, ".", &field.name, idx);
+                if field.offset < end {
+                    report(problems, &field_path,
+                        ProblemKind::OverlappingField { offset: field.offset, end });
+                }
+                end = field.offset.saturating_add(size_of(&field.ty));
+                if end > size {
+                    report(problems, &field_path, ProblemKind::OutOfBounds { end, size });
+                }
+                validate(&field.ty, &field_path, problems);
+            }
+        }
+        Ty::Enum(ref en) => {
+            let tag_size = en.tag_layout.size();
+            if !matches!(tag_size, 1 | 2 | 4 | 8 | 16) {
+                report(problems, path, ProblemKind::InvalidIntSize(tag_size));
+            }
+            let size = en.layout.size();
+            let payload_offset = en.payload_offset().unwrap_or(usize::MAX);
+            for (idx, variant) in en.variants.iter().enumerate() {
+                let variant_path = member(path, "::", &variant.name, idx);
+                if en.repr == EnumRepr::Primitive {
+                    // the payload shares its start with the tag, see `EnumRepr`
+                    let start = match variant.payload {
+                        Ty::Struct(ref st) => st.fields.first().map(|field| field.offset),
+                        ref payload => Some(0).filter(|_| size_of(payload) > 0),
+                    };
+                    if let Some(offset) = start.filter(|&offset| offset < tag_size) {
+                        report(problems, &variant_path,
+                            ProblemKind::OverlappingField { offset, end: tag_size });
+                    }
+                }
+                if en.variants[..idx].iter().any(|other| other.disc == variant.disc) {
+                    report(problems, &variant_path,
+                        ProblemKind::DuplicateDiscriminant(variant.disc));
+                }
+                if tag_size < 16 && variant.disc >> (tag_size * 8) != 0 {
+                    report(problems, &variant_path,
+                        ProblemKind::DiscriminantTooLarge { disc: variant.disc, tag_size });
+                }
+                let end = payload_offset.saturating_add(size_of(&variant.payload));
+                if end > size {
+                    report(problems, &variant_path, ProblemKind::OutOfBounds { end, size });
+                }
+                validate(&variant.payload, &variant_path, problems);
+            }
+        }
+        Ty::Union(ref un) => {
+            let size = un.layout.size();
+            for (idx, variant) in un.variants.iter().enumerate() {
+                let variant_path = member(path, ".", &variant.name, idx);
+                let end = size_of(&variant.ty);
+                if end > size {
+                    report(problems, &variant_path, ProblemKind::OutOfBounds { end, size });
+                }
+                validate(&variant.ty, &variant_path, problems);
+            }
+        }
+        Ty::UnsafeCell(ref inner) => validate(inner, path, problems),
+        Ty::Valid(ref valid) => {
+            let before = problems.len();
+            validate(&valid.ty, path, problems);
+            if problems.len() > before {
+                return;
+            }
+            if valid.ty.valid_values().is_none() {
+                report(problems, path, ProblemKind::NotNarrowable);
+                return;
+            }
+            let size = size_of(&valid.ty);
+            let max = int_max(size as u32);
+            for &(start, end) in &valid.ranges {
+                if start > end {
+                    report(problems, path, ProblemKind::EmptyRange { start, end });
+                }
+                let bounds = if start == end { &[start][..] } else { &[start, end][..] };
+                for &bound in bounds {
+                    if bound > max {
+                        report(problems, path, ProblemKind::BoundTooLarge { bound, size });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Size of `ty`, or 0 if it has none, which is reported separately.
+fn size_of(ty: &Ty) -> usize {
+    layout_of(ty).map_or(0, |layout| layout.size())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::error::Error;
+    use core::alloc::Layout;
+
+    fn problems(ty: &Ty) -> Vec<(String, ProblemKind)> {
+        ty.validate().into_iter().map(|problem| (problem.path, problem.kind)).collect()
+    }
+
+    fn byte_enum(variants: &[(&str, u128)]) -> Enum {
+        let mut en = Enum::new("E", 1).unwrap();
+        for &(name, disc) in variants {
+            en.variants.push(EnumVariant { name: name.to_string(), disc, payload: Ty::Void });
+        }
+        en
+    }
+
+    #[test]
+    fn valid() {
+        let mut st = Struct::new("S");
+        st.add_field("a", false, Ty::Int(2)).unwrap();
+        st.add_field("b", false, Ty::Enum(byte_enum(&[("A", 0), ("B", 1)]))).unwrap();
+        assert_eq!(problems(&Ty::Struct(st)), []);
+    }
+
+    #[test]
+    fn invalid_int_size() {
+        let mut st = Struct::new("S");
+        st.fields.push(Field { name: "a".to_string(), private: false, offset: 0, ty: Ty::Int(3) });
+        assert_eq!(problems(&Ty::Struct(st)), [("S.a".to_string(), ProblemKind::InvalidIntSize(3))]);
+        let mut en = byte_enum(&[("A", 0)]);
+        en.tag_layout = Layout::from_size_align(3, 1).unwrap();
+        en.layout = en.tag_layout;
+        assert_eq!(problems(&Ty::Enum(en)), [("E".to_string(), ProblemKind::InvalidIntSize(3))]);
+    }
+
+    #[test]
+    fn layout_overflow() {
+        let arr = Ty::Array(Box::new(Array { element: Ty::Int(8), count: usize::MAX / 8 }));
+        assert_eq!(problems(&arr), [(String::new(), ProblemKind::LayoutOverflow)]);
+    }
+
+    #[test]
+    fn no_variants_is_uninhabited() {
+        assert_eq!(problems(&Ty::Enum(byte_enum(&[]))), []);
+        assert_eq!(problems(&Ty::Union(Union::new("U"))), []);
+    }
+
+    #[test]
+    fn duplicate_discriminant() {
+        let en = byte_enum(&[("A", 1), ("B", 2), ("C", 1)]);
+        assert_eq!(problems(&Ty::Enum(en)),
+            [("E::C".to_string(), ProblemKind::DuplicateDiscriminant(1))]);
+    }
+
+    #[test]
+    fn discriminant_too_large() {
+        let en = byte_enum(&[("A", 0), ("B", 0x100)]);
+        assert_eq!(problems(&Ty::Enum(en)),
+            [("E::B".to_string(), ProblemKind::DiscriminantTooLarge { disc: 0x100, tag_size: 1 })]);
+    }
+
+    #[test]
+    fn bound_too_large() {
+        let mut st = Struct::new("S");
+        st.add_field("a", false, Ty::Scalar(Scalar::new(1, 0x100, 1))).unwrap();
+        assert_eq!(problems(&Ty::Struct(st)),
+            [("S.a".to_string(), ProblemKind::BoundTooLarge { bound: 0x100, size: 1 })]);
+    }
+
+    #[test]
+    fn valid_ranges() {
+        let mut st = Struct::new("S");
+        st.add_field("a", false, Ty::Int(2).narrowed(vec![(3, 2), (0, 0x10000)])).unwrap();
+        let empty = Ty::Struct(Struct::new("E"));
+        st.add_field("b", false, empty.narrowed(vec![(0, 0)])).unwrap();
+        assert_eq!(problems(&Ty::Struct(st)), [
+            ("S.a".to_string(), ProblemKind::EmptyRange { start: 3, end: 2 }),
+            ("S.a".to_string(), ProblemKind::BoundTooLarge { bound: 0x10000, size: 2 }),
+            ("S.b".to_string(), ProblemKind::NotNarrowable),
+        ]);
+    }
+
+    #[test]
+    fn overlapping_field() {
+        let mut st = Struct::new("S");
+        st.add_field("a", false, Ty::Int(4)).unwrap();
+        st.fields.push(Field { name: "b".to_string(), private: false, offset: 2, ty: Ty::Int(1) });
+        assert_eq!(problems(&Ty::Struct(st)),
+            [("S.b".to_string(), ProblemKind::OverlappingField { offset: 2, end: 4 })]);
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut st = Struct::new("S");
+        st.add_field("a", false, Ty::Int(2)).unwrap();
+        st.fields.push(Field { name: "b".to_string(), private: false, offset: 2, ty: Ty::Int(1) });
+        let mut un = Union::new("U");
+        un.add_variant("s", false, Ty::Struct(st)).unwrap();
+        un.variants.push(UnionVariant { name: "x".to_string(), private: false, ty: Ty::Int(4) });
+        assert_eq!(problems(&Ty::Union(un)), [
+            ("U.s.b".to_string(), ProblemKind::OutOfBounds { end: 3, size: 2 }),
+            ("U.x".to_string(), ProblemKind::OutOfBounds { end: 4, size: 2 }),
+        ]);
+    }
+
+    #[test]
+    fn compile_reports_every_problem() {
+        let en = byte_enum(&[("A", 0), ("A2", 0), ("B", 0x100)]);
+        let err = Compiler::compile(&Ty::Enum(en), Endian::Little, "E").err().unwrap();
+        assert!(matches!(err, Error::InvalidTy(ref problems) if problems.len() == 2));
+        assert_eq!(err.to_string(), "invalid type: \
+            E::A2: discriminant 0 is used more than once; \
+            E::B: discriminant 256 does not fit a 1 byte tag");
+    }
+}